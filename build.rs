@@ -3,7 +3,12 @@ use std::env;
 fn main() {
     let triple = env::var("TARGET").expect("TARGET was not set");
     if !triple.starts_with("wasm32-") {
-        println!("wasi-vfs only supports wasm32-unknown-unknown");
+        // Native builds are only for unit tests against a mock WASI host,
+        // which need the embedded storage.
+        cc::Build::new()
+            .file("src/embed/linked_storage.c")
+            .compile("wasi_vfs_c");
+        println!("cargo:rerun-if-changed=src/embed/linked_storage.c");
         return;
     }
     let wasi_sdk = env::var("WASI_SDK_PATH").expect("WASI_SDK_PATH is not set");
@@ -12,6 +17,10 @@ fn main() {
         .compiler(format!("{}/bin/clang", wasi_sdk))
         .archiver(format!("{}/bin/llvm-ar", wasi_sdk))
         .file("src/init.c");
+    if triple.ends_with("-threads") {
+        // Objects linked into modules with shared memory need atomics
+        build.flag("-pthread");
+    }
 
     let trampoline_file = if env::var("CARGO_FEATURE_LEGACY_WASI_LIBC").is_ok() {
         "src/trampoline_generated_legacy_wasi_libc.c"
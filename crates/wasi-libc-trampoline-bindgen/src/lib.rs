@@ -41,9 +41,22 @@ pub(crate) const WASI_HOOK_FUNCTIONS: &[&str] = &[
     "poll_oneoff",
 ];
 
+/// Hooked functions which may block on the host, e.g. reading a pipe, or run
+/// application code, e.g. generating a file on open. Their implementations
+/// take the global state lock by value, so that they can release it meanwhile.
+pub(crate) const WASI_BLOCKING_FUNCTIONS: &[&str] = &[
+    "fd_pread",
+    "fd_pwrite",
+    "fd_read",
+    "fd_write",
+    "path_open",
+    "poll_oneoff",
+];
+
 #[derive(StructOpt)]
 pub enum App {
     Wrapper,
+    ErrnoNames,
     ObjectLink { abi_variant: AbiVariant },
 }
 
@@ -54,6 +67,9 @@ impl App {
             App::Wrapper => {
                 print!("{}", wrapper::generate(&witx_paths));
             }
+            App::ErrnoNames => {
+                print!("{}", wrapper::generate_errno_names(&witx_paths));
+            }
             App::ObjectLink { abi_variant } => {
                 print!("{}", object_link::generate(&witx_paths, abi_variant));
             }
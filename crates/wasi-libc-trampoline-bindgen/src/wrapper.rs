@@ -25,7 +25,53 @@ use crate::UserFd;
         raw.push('\n');
     }
 
+    raw.push_str(
+        "\
+/// Names of the hooked syscalls, which are the ones that can be traced.
+#[cfg(feature = \"trace-syscall\")]
+pub(crate) const SYSCALL_NAMES: &[&str] = &[",
+    );
+    for m in doc.modules() {
+        for f in m.funcs() {
+            if crate::WASI_HOOK_FUNCTIONS.contains(&f.name.as_str()) {
+                raw.push_str(&format!("\"{}\", ", f.name.as_str()));
+            }
+        }
+    }
+    raw.push_str("];\n");
+    rustfmt(&raw)
+}
+
+/// Generate the table of errno names, which is shared by wasi-vfs and the
+/// CLI to report errors.
+pub fn generate_errno_names<P: AsRef<Path>>(witx_paths: &[P]) -> String {
+    let doc = witx::load(witx_paths).unwrap();
+    let errno = doc.typename(&Id::new("errno")).expect("errno is defined");
+    let Type::Variant(v) = &**errno.type_() else {
+        panic!("errno is an enum");
+    };
+
+    let mut raw = String::new();
+    raw.push_str(
+        "\
+// This file is automatically generated, DO NOT EDIT
+//
+// To regenerate this file run the `crates/wasi-libc-trampoline-bindgen` command
+
+/// Symbolic names of WASI errno values, indexed by the values.
+pub const ERRNO_NAMES: &[&str] = &[",
+    );
+    for case in v.cases.iter() {
+        raw.push_str(&format!("\"E{}\", ", case.name.as_str().to_uppercase()));
+    }
+    raw.push_str("];\n");
+    rustfmt(&raw)
+}
+
+fn rustfmt(raw: &str) -> String {
+    // Same edition as wasi-vfs, which includes the generated files
     let mut rustfmt = Command::new("rustfmt")
+        .args(["--edition", "2024"])
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
@@ -169,90 +215,308 @@ impl Render for Module {
     }
 }
 
-fn render_trace_syscall_entry_format_args(func: &InterfaceFunc, src: &mut String) {
-    src.push_str("format_args!(\"");
-    src.push_str(func.name.as_str());
-    src.push('(');
+/// A value formatted for tracing, as a format string and its arguments.
+struct Traced {
+    fmt: String,
+    args: Vec<String>,
+    /// The only argument is a `String` formatted by a helper
+    formatted: bool,
+}
+
+impl Traced {
+    fn display(arg: String) -> Self {
+        Traced {
+            fmt: "{}".to_string(),
+            args: vec![arg],
+            formatted: false,
+        }
+    }
+
+    fn helper(arg: String) -> Self {
+        Traced {
+            fmt: "{}".to_string(),
+            args: vec![arg],
+            formatted: true,
+        }
+    }
 
-    let mut name_and_values = vec![];
+    fn hex(arg: String) -> Self {
+        Traced {
+            fmt: "{:#x}".to_string(),
+            args: vec![arg],
+            formatted: false,
+        }
+    }
+
+    fn concat(fmt: String, args: Vec<String>) -> Self {
+        Traced {
+            fmt,
+            args,
+            formatted: false,
+        }
+    }
+
+    /// Render an expression of `String`.
+    fn into_string(self) -> String {
+        match (self.fmt.as_str(), self.args.as_slice()) {
+            ("{}", [arg]) if self.formatted => arg.clone(),
+            ("{}", [arg]) => format!("{}.to_string()", arg),
+            _ => format!("format!(\"{}\", {})", self.fmt, self.args.join(", ")),
+        }
+    }
+}
+
+/// Render the fd which `func` operates on, used to filter traces and to
+/// attribute calls to a backend.
+fn render_trace_fd(func: &InterfaceFunc) -> &'static str {
+    match func.params.first() {
+        Some(param) if param.name.as_str() == "fd" => "Some(arg0 as UserFd)",
+        _ => "None",
+    }
+}
+
+/// Render an expression formatting the arguments of `func` for tracing.
+/// `fs` in the expression is the overlay file system if it's active.
+fn render_trace_args(func: &InterfaceFunc) -> String {
+    let mut fields = vec![];
+    let mut args = vec![];
     let mut arg_idx = 0;
     for param in func.params.iter() {
-        let name = String::from(param.name.as_str());
-        match &**param.tref.type_() {
+        let arg = format!("arg{}", arg_idx);
+        let traced = match &**param.tref.type_() {
             Type::List(element) => {
-                let ptr = format!("arg{}", arg_idx);
                 let len = format!("arg{}", arg_idx + 1);
-                match **element.type_() {
-                    Type::Builtin(BuiltinType::Char) => {
-                        name_and_values.push((name, format!("{{
-                            let str_bytes = core::slice::from_raw_parts({} as *const u8, ({} + 1) as usize);
-                            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-                            cstr.to_str().unwrap().to_string()
-                        }}", ptr, len)));
-                    }
-                    _ => {
-                        name_and_values.push((name.clone(), ptr));
-                        name_and_values.push((format!("{}_len", name), len));
-                    }
-                }
                 arg_idx += 2;
+                trace_list(element, &arg, &len)
             }
             _ => {
-                name_and_values.push((name, format!("arg{}", arg_idx)));
                 arg_idx += 1;
+                trace_value(&param.tref, &arg, false)
+            }
+        };
+        fields.push(format!("{}: {}", param.name.as_str(), traced.fmt));
+        args.extend(traced.args);
+    }
+    Traced::concat(fields.join(", "), args).into_string()
+}
+
+/// Render an expression formatting the result of `func` on success, which
+/// is stored at the pointer passed as the last argument. The overlay is
+/// locked only for results containing fds.
+fn render_trace_result(func: &InterfaceFunc, retptr: &str) -> String {
+    let ok = func
+        .results
+        .first()
+        .and_then(|result| match &**result.tref.type_() {
+            Type::Variant(v) => v.as_expected().and_then(|(ok, _)| ok.cloned()),
+            _ => None,
+        });
+    match ok {
+        Some(ok) => {
+            let mut formatted = trace_value(&ok, "value", true).into_string();
+            if has_handle(&ok) {
+                formatted = format!("crate::trace::with_overlay(|fs| {})", formatted);
             }
+            format!(
+                "{{ let value = unsafe {{ core::ptr::read({} as *const {}) }}; {} }}",
+                retptr,
+                ok.to_rust_ident(),
+                formatted
+            )
         }
+        None => "0.to_string()".to_string(),
     }
+}
 
-    src.push_str(
-        &name_and_values
+/// Whether values of `tref` contain fds, whose backings are looked up in the
+/// overlay to format them.
+fn has_handle(tref: &TypeRef) -> bool {
+    match &**tref.type_() {
+        Type::Handle(_) => true,
+        Type::Record(r) => r.members.iter().any(|member| has_handle(&member.tref)),
+        Type::Variant(v) => v
+            .cases
             .iter()
-            .map(|(name, _)| format!("{}: {{}}", name))
-            .collect::<Vec<_>>()
-            .join(", "),
-    );
-    src.push_str(")\\n\"");
-    for (_, value) in name_and_values.iter() {
-        src.push_str(", ");
-        src.push_str(value);
+            .filter_map(|case| case.tref.as_ref())
+            .any(has_handle),
+        _ => false,
     }
-    src.push(')');
 }
 
-fn render_trampoline(func: &InterfaceFunc, name: &str, module: &Id, src: &mut String) {
-    src.push_str(" #[unsafe(no_mangle)]\n");
-    src.push_str("pub unsafe extern \"C\" fn ");
-    src.push_str(name);
-
-    let (params, results) = func.wasm_signature();
-    assert!(results.len() <= 1);
-    src.push('(');
-
-    for (i, param) in params.iter().enumerate() {
-        src.push_str(&format!("arg{}: ", i));
-        param.render(src);
-        src.push(',');
+/// Format a list argument. Strings are quoted, and only lengths of buffers
+/// are shown.
+fn trace_list(element: &TypeRef, ptr: &str, len: &str) -> Traced {
+    match &**element.type_() {
+        Type::Builtin(BuiltinType::Char) => Traced::helper(format!(
+            "crate::trace::string({} as *const u8, {} as usize)",
+            ptr, len
+        )),
+        Type::Record(r) if r.members.iter().any(|m| m.name.as_str() == "buf_len") => {
+            Traced::helper(format!(
+                "crate::trace::buf_lens(unsafe {{ core::slice::from_raw_parts({} as *const {}, {} as usize) }}.iter().map(|buf| buf.buf_len))",
+                ptr,
+                element.to_rust_ident(),
+                len
+            ))
+        }
+        t => panic!("tracing a list of {} is not supported", t.kind()),
     }
-    src.push(')');
+}
 
-    if func.noreturn {
-        src.push_str(" -> !");
-    } else if let Some(result) = results.get(0) {
-        src.push_str(" -> ");
-        result.render(src);
+/// Format `expr` of type `tref`. Values read from memory have the types in
+/// the `wasi` crate, and the others are raw wasm arguments.
+fn trace_value(tref: &TypeRef, expr: &str, in_memory: bool) -> Traced {
+    match &**tref.type_() {
+        Type::Handle(_) if in_memory => Traced::helper(format!("crate::trace::fd(fs, {})", expr)),
+        Type::Handle(_) => Traced::helper(format!("crate::trace::fd(fs, {} as UserFd)", expr)),
+        Type::Variant(v) if v.is_enum() => {
+            let prefix = type_prefix(tref);
+            let names = v
+                .cases
+                .iter()
+                .map(|case| format!("\"{}_{}\"", prefix, case.name.as_str().to_uppercase()))
+                .collect::<Vec<_>>();
+            let raw = if in_memory {
+                format!("{}.raw()", expr)
+            } else {
+                expr.to_string()
+            };
+            Traced::helper(format!(
+                "crate::trace::enum_name({} as u64, &[{}])",
+                raw,
+                names.join(", ")
+            ))
+        }
+        Type::Variant(v) => {
+            // A union stored in memory, whose payload is chosen by the tag
+            assert!(in_memory, "unions are only passed through memory");
+            let mut arms = String::new();
+            for (i, case) in v.cases.iter().enumerate() {
+                let payload = match &case.tref {
+                    Some(payload) => trace_value(
+                        payload,
+                        &format!("unsafe {{ {}.u.{} }}", expr, case.name.as_str()),
+                        true,
+                    ),
+                    None => Traced::concat(String::new(), vec![]),
+                };
+                let traced = Traced::concat(
+                    format!("{{{{{}: {}}}}}", case.name.as_str(), payload.fmt),
+                    payload.args,
+                );
+                arms.push_str(&format!("{} => {},\n", i, traced.into_string()));
+            }
+            arms.push_str("tag => format!(\"{{tag: {}}}\", tag),\n");
+            Traced::helper(format!("match {}.tag {{ {} }}", expr, arms))
+        }
+        // Rights are left as a number since every right is usually set
+        Type::Record(_) if named_type(tref) == Some("rights") && in_memory => {
+            Traced::hex(expr.to_string())
+        }
+        Type::Record(_) if named_type(tref) == Some("rights") => {
+            Traced::hex(format!("{} as u64", expr))
+        }
+        Type::Record(r) if r.bitflags_repr().is_some() => {
+            let prefix = type_prefix(tref);
+            let names = r
+                .members
+                .iter()
+                .map(|member| format!("\"{}_{}\"", prefix, member.name.as_str().to_uppercase()))
+                .collect::<Vec<_>>();
+            Traced::helper(format!(
+                "crate::trace::flags({} as u64, &[{}])",
+                expr,
+                names.join(", ")
+            ))
+        }
+        Type::Record(r) => {
+            assert!(in_memory, "records are only passed through memory");
+            let mut fields = vec![];
+            let mut args = vec![];
+            for member in r.members.iter() {
+                let field = format!("{}.{}", expr, to_rust_ident(member.name.as_str()));
+                let traced = trace_value(&member.tref, &field, true);
+                fields.push(format!("{}: {}", member.name.as_str(), traced.fmt));
+                args.extend(traced.args);
+            }
+            Traced::concat(format!("{{{{{}}}}}", fields.join(", ")), args)
+        }
+        Type::Pointer(_) | Type::ConstPointer(_) => Traced::hex(format!("{} as u32", expr)),
+        Type::Builtin(_) if in_memory => Traced::display(expr.to_string()),
+        Type::Builtin(t) => {
+            let mut ty = String::new();
+            t.render(&mut ty);
+            // Signed values have the same types as wasm arguments
+            if ty == "i32" || ty == "i64" {
+                Traced::display(expr.to_string())
+            } else {
+                Traced::display(format!("({} as {})", expr, ty))
+            }
+        }
+        t => panic!("tracing a value of {} is not supported", t.kind()),
     }
-    src.push_str("{\n");
+}
 
-    {
-        src.push_str("#[cfg(feature = \"trace-syscall\")]\n");
-        src.push_str("crate::trace::trace_syscall_entry(");
-        render_trace_syscall_entry_format_args(func, src);
-        src.push_str(");\n");
+/// Prefix of constant names in the `wasi` crate for values of `tref`.
+fn type_prefix(tref: &TypeRef) -> String {
+    named_type(tref)
+        .expect("enums and flags are named")
+        .to_uppercase()
+}
+
+fn named_type(tref: &TypeRef) -> Option<&str> {
+    match tref {
+        TypeRef::Name(t) => Some(t.name.as_str()),
+        TypeRef::Value(_) => None,
     }
+}
+
+fn render_trampoline(func: &InterfaceFunc, name: &str, module: &Id, src: &mut String) {
+    let (params, results) = func.wasm_signature();
+    assert!(results.len() == 1 && !func.noreturn);
+    let hook_name = format!("hook_{}", func.name.as_str());
+    let args = (0..params.len())
+        .map(|i| format!("arg{}", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // The exported function traces the call around the hook
+    src.push_str(" #[unsafe(no_mangle)]\n");
+    src.push_str("pub unsafe extern \"C\" fn ");
+    src.push_str(name);
+    render_signature(&params, &results, src);
+    src.push_str("{\n");
+    src.push_str("#[cfg(feature = \"trace-syscall\")]\n");
+    src.push_str(&format!(
+        "let trace = crate::trace::enter(\"{}\", {}, |fs| {});\n",
+        func.name.as_str(),
+        render_trace_fd(func),
+        render_trace_args(func)
+    ));
+    src.push_str(&format!(
+        "let ret = unsafe {{ {}({}) }};\n",
+        hook_name, args
+    ));
+    src.push_str("#[cfg(feature = \"trace-syscall\")]\n");
+    src.push_str(&format!(
+        "crate::trace::exit(trace, ret, || {});\n",
+        render_trace_result(func, &format!("arg{}", params.len() - 1))
+    ));
+    src.push_str("ret\n");
+    src.push_str("}\n");
+
+    src.push_str("unsafe fn ");
+    src.push_str(&hook_name);
+    render_signature(&params, &results, src);
+    src.push_str("{\n");
     {
-        src.push_str(
-            "let fs = if let Some(fs) = unsafe { (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE)).overlay_fs.as_mut() } { fs } else {\n",
-        );
+        // Calls which may block on the host take the lock by value to
+        // release it before calling the host.
+        if is_blocking(func.name.as_str()) {
+            src.push_str("let Some(fs) = crate::OverlayFs::lock() else {\n");
+        } else {
+            src.push_str("let Some(mut fs) = crate::OverlayFs::lock() else {\n");
+        }
         src.push_str(&format!(
             "return unsafe {{ wasi::{}::{}(\n",
             module.as_str(),
@@ -277,6 +541,24 @@ fn render_trampoline(func: &InterfaceFunc, name: &str, module: &Id, src: &mut St
     src.push('}');
 }
 
+fn render_signature(params: &[WasmType], results: &[WasmType], src: &mut String) {
+    src.push('(');
+    for (i, param) in params.iter().enumerate() {
+        src.push_str(&format!("arg{}: ", i));
+        param.render(src);
+        src.push(',');
+    }
+    src.push(')');
+    for result in results {
+        src.push_str(" -> ");
+        result.render(src);
+    }
+}
+
+fn is_blocking(func_name: &str) -> bool {
+    crate::WASI_BLOCKING_FUNCTIONS.contains(&func_name)
+}
+
 struct Rust<'a> {
     src: &'a mut String,
     func_name: &'a str,
@@ -345,10 +627,16 @@ impl Bindgen for Rust<'_> {
             Instruction::F32FromIf32 => todo!(),
             Instruction::F64FromIf64 => todo!(),
             Instruction::CallInterface { module, func } => {
+                let fs = if is_blocking(self.func_name) {
+                    "fs"
+                } else {
+                    "&mut fs"
+                };
                 results.push(format!(
-                    "crate::{}::{}(fs, {})",
+                    "crate::{}::{}({}, {})",
                     module,
                     func.name.as_str(),
+                    fs,
                     operands.join(", ")
                 ));
             }
@@ -395,19 +683,21 @@ impl Bindgen for Rust<'_> {
                 let err = self.blocks.pop().unwrap();
                 let ok = self.blocks.pop().unwrap();
                 let val = operands.pop().unwrap();
+                // No statement for results without values, as rustfmt leaves
+                // a block with an empty statement
+                let ok = if ok.is_empty() {
+                    "wasi::ERRNO_SUCCESS.raw() as i32".to_string()
+                } else {
+                    format!("{{ {}; wasi::ERRNO_SUCCESS.raw() as i32 }}", ok)
+                };
                 results.push(format!(
                     "{{
                     match {} {{
-                        Ok(e) => {{ {}; wasi::ERRNO_SUCCESS.raw() as i32 }}
-                        Err(e) => {{
-                            #[cfg(feature = \"trace-syscall\")]
-                            crate::trace::trace_syscall_error(\"{}\", e.clone());
-
-                            {}
-                        }}
+                        Ok(e) => {},
+                        Err(e) => {}
                     }}
                 }}",
-                    val, ok, self.func_name, err
+                    val, ok, err
                 ));
             }
             Instruction::EnumLift { .. } => {
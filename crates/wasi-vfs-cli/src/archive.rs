@@ -0,0 +1,234 @@
+//! Conversion of archives into the tree stream fed to the in-guest packer.
+
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::stream;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Open an archive at `path`, or stdin if `path` is `-`.
+fn open_archive(path: &Path) -> Result<Box<dyn BufRead>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(BufReader::new(std::io::stdin())));
+    }
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open archive '{}'", path.display()))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// Open a tar archive, decompressing it if it's gzip compressed.
+fn open_tar(path: &Path) -> Result<Box<dyn Read>> {
+    let mut reader = open_archive(path)?;
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    Ok(if is_gzip {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else {
+        reader
+    })
+}
+
+/// Write entries of a tar archive, optionally gzip compressed, at `path` into
+/// the stream. Unsupported entries are skipped with a warning unless `strict`.
+pub(crate) fn feed_tar<W: Write>(
+    feed: &mut stream::Writer<W>,
+    path: &Path,
+    strict: bool,
+) -> Result<()> {
+    let mut archive = tar::Archive::new(open_tar(path)?);
+    let context = || format!("failed to read tar archive '{}'", path.display());
+    for entry in archive.entries().with_context(context)? {
+        let mut entry = entry.with_context(context)?;
+        let entry_path = entry.path().with_context(context)?.into_owned();
+        let skip = |reason: &str| skip_entry(path, &entry_path.to_string_lossy(), reason, strict);
+        let guest_path = match normalize_path(&entry_path) {
+            Some(guest_path) => guest_path,
+            None => {
+                skip("entry escaping the archive root")?;
+                continue;
+            }
+        };
+        let header = entry.header();
+        let mtime = header.mtime().unwrap_or(0).saturating_mul(NANOS_PER_SEC);
+        match header.entry_type() {
+            tar::EntryType::XGlobalHeader => {}
+            tar::EntryType::Directory => {
+                // The archive root itself, e.g. `./`
+                if !guest_path.is_empty() {
+                    feed.dir(&guest_path, mtime)?;
+                }
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mut content = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut content).with_context(context)?;
+                feed.file(&guest_path, mtime, &content)?;
+            }
+            tar::EntryType::Symlink => {
+                let target = entry.link_name().with_context(context)?;
+                match target.as_deref().and_then(Path::to_str) {
+                    Some(target) => feed.symlink(&guest_path, mtime, target)?,
+                    None => skip("symbolic link with non UTF-8 target")?,
+                }
+            }
+            tar::EntryType::Link => {
+                let target = entry.link_name().with_context(context)?;
+                match target.as_deref().and_then(normalize_path) {
+                    Some(target) => feed.hard_link(&guest_path, &target)?,
+                    None => skip("hard link escaping the archive root")?,
+                }
+            }
+            other => skip(&format!("unsupported entry type {:?}", other))?,
+        }
+    }
+    Ok(())
+}
+
+/// Read a tar archive at `path` as an uncompressed blob. Gzip compressed
+/// archives are decompressed.
+pub(crate) fn read_tar(path: &Path) -> Result<Vec<u8>> {
+    let mut blob = Vec::new();
+    open_tar(path)?
+        .read_to_end(&mut blob)
+        .with_context(|| format!("failed to read tar archive '{}'", path.display()))?;
+    Ok(blob)
+}
+
+/// Write entries of a zip archive at `path` into the stream. Unsupported
+/// entries are skipped with a warning unless `strict`.
+pub(crate) fn feed_zip<W: Write>(
+    feed: &mut stream::Writer<W>,
+    path: &Path,
+    strict: bool,
+) -> Result<()> {
+    // Zip archives are indexed by the central directory at the end, so
+    // non-seekable stdin is buffered in memory.
+    if path == Path::new("-") {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+        return feed_zip_from(feed, path, Cursor::new(bytes), strict);
+    }
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open archive '{}'", path.display()))?;
+    feed_zip_from(feed, path, BufReader::new(file), strict)
+}
+
+fn feed_zip_from<W: Write, R: Read + Seek>(
+    feed: &mut stream::Writer<W>,
+    path: &Path,
+    reader: R,
+    strict: bool,
+) -> Result<()> {
+    let context = || format!("failed to read zip archive '{}'", path.display());
+    let mut archive = zip::ZipArchive::new(reader).with_context(context)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).with_context(context)?;
+        let entry_name = entry.name().to_string();
+        let skip = |reason: &str| skip_entry(path, &entry_name, reason, strict);
+        let guest_path = match normalize_path(Path::new(&entry_name)) {
+            Some(guest_path) => guest_path,
+            None => {
+                skip("entry escaping the archive root")?;
+                continue;
+            }
+        };
+        let mtime = entry
+            .last_modified()
+            .map(zip_datetime_to_unix)
+            .unwrap_or(0)
+            .saturating_mul(NANOS_PER_SEC);
+        if entry.is_dir() {
+            if !guest_path.is_empty() {
+                feed.dir(&guest_path, mtime)?;
+            }
+            continue;
+        }
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content).with_context(context)?;
+        if entry.is_symlink() {
+            match String::from_utf8(content) {
+                Ok(target) => feed.symlink(&guest_path, mtime, &target)?,
+                Err(_) => skip("symbolic link with non UTF-8 target")?,
+            }
+        } else {
+            feed.file(&guest_path, mtime, &content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Convert a zip timestamp to seconds since the Unix epoch. Zip timestamps
+/// have no time zone, so they are interpreted as UTC.
+fn zip_datetime_to_unix(datetime: zip::DateTime) -> u64 {
+    // Days from 1970-01-01 to the given civil date (proleptic Gregorian)
+    let (year, month, day) = (
+        datetime.year() as i64,
+        datetime.month() as i64,
+        datetime.day() as i64,
+    );
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400
+        + datetime.hour() as i64 * 3600
+        + datetime.minute() as i64 * 60
+        + datetime.second() as i64;
+    seconds.max(0) as u64
+}
+
+/// Report an archive entry which can't be packed. Returns an error if
+/// `strict`, otherwise prints a warning to skip the entry.
+fn skip_entry(archive: &Path, entry: &str, reason: &str, strict: bool) -> Result<()> {
+    let message = format!("{} in '{}': {}", reason, archive.display(), entry);
+    if strict {
+        anyhow::bail!("failed to pack {}", message);
+    }
+    eprintln!("warning: skipping {}", message);
+    Ok(())
+}
+
+/// Normalize a path in an archive to a `/`-prefixed path relative to the
+/// archive root. Returns `None` if the path escapes the root or isn't UTF-8.
+/// The archive root itself is normalized to an empty string.
+fn normalize_path(path: &Path) -> Option<String> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    let mut guest_path = String::new();
+    for name in normalized.iter() {
+        guest_path.push('/');
+        guest_path.push_str(name.to_str()?);
+    }
+    Some(guest_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_path, zip_datetime_to_unix};
+    use std::path::Path;
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(Path::new("./a/b.txt")).unwrap(), "/a/b.txt");
+        assert_eq!(normalize_path(Path::new("/a/./b/")).unwrap(), "/a/b");
+        assert_eq!(normalize_path(Path::new("./")).unwrap(), "");
+        assert!(normalize_path(Path::new("a/../../b")).is_none());
+    }
+
+    #[test]
+    fn test_zip_datetime_to_unix() {
+        let datetime = zip::DateTime::from_date_and_time(2001, 9, 9, 1, 46, 40).unwrap();
+        assert_eq!(zip_datetime_to_unix(datetime), 1_000_000_000);
+        let datetime = zip::DateTime::from_date_and_time(1980, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(zip_datetime_to_unix(datetime), 315_532_800);
+    }
+}
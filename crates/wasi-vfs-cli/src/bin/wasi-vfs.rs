@@ -2,5 +2,8 @@ use structopt::StructOpt;
 use wasi_vfs_cli::App;
 
 fn main() {
-    App::from_args().execute().unwrap();
+    if let Err(err) = App::from_args().execute() {
+        eprintln!("error: {:#}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,284 @@
+//! Declarative configuration of `pack` read from a TOML file.
+//!
+//! ```toml
+//! output = "app.packed.wasm"
+//! excludes = ["**/__pycache__"]
+//! dedup = true
+//! dev = true
+//! mtime = "zero"
+//!
+//! [[mounts]]
+//! dir = "lib"
+//! guest = "/lib"
+//!
+//! [[mounts]]
+//! tar = "assets.tar.gz"
+//! guest = "/assets"
+//!
+//! [[files]]
+//! host = "cert.pem"
+//! guest = "/etc/ssl/cert.pem"
+//! ```
+//!
+//! There is no compression option. Embedded files are served in place from
+//! the linear memory, so they are stored uncompressed.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{MtimePolicy, PackMode, PackOptions};
+
+/// Everything `pack` takes besides the input module. Relative host paths are
+/// resolved against the directory of the configuration file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct PackConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) strict: Option<bool>,
+    pub(crate) mode: PackMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dedup: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) dev: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) mtime: Option<MtimePolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) init_func: Option<String>,
+    pub(crate) excludes: Vec<String>,
+    pub(crate) includes: Vec<String>,
+    pub(crate) mounts: Vec<MountConfig>,
+    pub(crate) files: Vec<FileConfig>,
+}
+
+/// A tree packed at a guest directory. Exactly one of the sources is given.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct MountConfig {
+    pub(crate) guest: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tar: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) embed_tar: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) zip: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileConfig {
+    pub(crate) host: PathBuf,
+    pub(crate) guest: String,
+}
+
+impl PackConfig {
+    /// Read a configuration file, resolving relative host paths in it.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let context = || format!("failed to read config '{}'", path.display());
+        let text = std::fs::read_to_string(path).with_context(context)?;
+        let mut config: PackConfig = toml::from_str(&text).with_context(context)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        config.resolve_paths(base);
+        config.validate().with_context(context)?;
+        Ok(config)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        // `-` means stdin for archives, so it's kept as is
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() && path.as_os_str() != "-" {
+                *path = base.join(&*path);
+            }
+        };
+        self.output.iter_mut().for_each(resolve);
+        for mount in &mut self.mounts {
+            let sources = [
+                &mut mount.dir,
+                &mut mount.tar,
+                &mut mount.embed_tar,
+                &mut mount.zip,
+            ];
+            sources.into_iter().flatten().for_each(resolve);
+        }
+        for file in &mut self.files {
+            resolve(&mut file.host);
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        for mount in &self.mounts {
+            let sources = [&mount.dir, &mount.tar, &mount.embed_tar, &mount.zip];
+            if sources.iter().filter(|source| source.is_some()).count() != 1 {
+                anyhow::bail!(
+                    "mount at '{}' must have exactly one of dir, tar, embed-tar and zip",
+                    mount.guest
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Add settings given on the command line. Lists are appended and the
+    /// other settings override the configuration file if given.
+    pub(crate) fn extend(&mut self, other: PackConfig) {
+        if other.output.is_some() {
+            self.output = other.output;
+        }
+        if other.strict.is_some() {
+            self.strict = other.strict;
+        }
+        if other.mode != PackMode::default() {
+            self.mode = other.mode;
+        }
+        if other.dedup.is_some() {
+            self.dedup = other.dedup;
+        }
+        if other.dev.is_some() {
+            self.dev = other.dev;
+        }
+        if other.mtime.is_some() {
+            self.mtime = other.mtime;
+        }
+        if other.init_func.is_some() {
+            self.init_func = other.init_func;
+        }
+        self.excludes.extend(other.excludes);
+        self.includes.extend(other.includes);
+        self.mounts.extend(other.mounts);
+        self.files.extend(other.files);
+    }
+
+    /// Split into host directories to map and the other options.
+    pub(crate) fn into_pack_args(self) -> (Vec<(String, PathBuf)>, PackOptions) {
+        let mut map_dirs = vec![];
+        let mut options = PackOptions {
+            files: self
+                .files
+                .into_iter()
+                .map(|file| (file.host, file.guest))
+                .collect(),
+            strict: self.strict.unwrap_or_default(),
+            excludes: self.excludes,
+            includes: self.includes,
+            mode: self.mode,
+            dedup: self.dedup.unwrap_or_default(),
+            dev: self.dev.unwrap_or_default(),
+            mtime: self.mtime.unwrap_or_default(),
+            init_func: self.init_func,
+            ..Default::default()
+        };
+        for mount in self.mounts {
+            let guest = mount.guest;
+            if let Some(dir) = mount.dir {
+                map_dirs.push((guest, dir));
+            } else if let Some(tar) = mount.tar {
+                options.tars.push((tar, guest));
+            } else if let Some(tar) = mount.embed_tar {
+                options.embedded_tars.push((tar, guest));
+            } else if let Some(zip) = mount.zip {
+                options.zips.push((zip, guest));
+            }
+        }
+        (map_dirs, options)
+    }
+
+    pub(crate) fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+/// `preserve`, `zero` or seconds since the Unix epoch.
+impl<'de> Deserialize<'de> for MtimePolicy {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Seconds(u64),
+            Name(String),
+        }
+        match Value::deserialize(deserializer)? {
+            Value::Seconds(seconds) => Ok(MtimePolicy::Fixed(seconds)),
+            Value::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl Serialize for MtimePolicy {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MtimePolicy::Preserve => serializer.serialize_str("preserve"),
+            MtimePolicy::Fixed(seconds) => serializer.serialize_u64(*seconds),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wasi-vfs.toml");
+        std::fs::write(
+            &path,
+            r#"
+            output = "out/app.wasm"
+            mode = "merge"
+            dedup = true
+            dev = true
+            mtime = "zero"
+            init-func = "init"
+            excludes = ["*.pyc"]
+
+            [[mounts]]
+            dir = "lib"
+            guest = "/lib"
+
+            [[mounts]]
+            tar = "-"
+            guest = "/assets"
+
+            [[files]]
+            host = "/etc/ssl/cert.pem"
+            guest = "/etc/ssl/cert.pem"
+            "#,
+        )
+        .unwrap();
+        let mut config = PackConfig::load(&path).unwrap();
+        assert_eq!(config.output, Some(dir.path().join("out/app.wasm")));
+        assert_eq!(config.mtime, Some(MtimePolicy::Fixed(0)));
+        assert_eq!(config.mounts[0].dir, Some(dir.path().join("lib")));
+        assert_eq!(config.mounts[1].tar, Some(PathBuf::from("-")));
+        assert_eq!(config.files[0].host, PathBuf::from("/etc/ssl/cert.pem"));
+
+        config.extend(PackConfig {
+            output: Some(PathBuf::from("app.wasm")),
+            excludes: vec!["*.md".to_string()],
+            dev: Some(false),
+            ..Default::default()
+        });
+        let printed = config.to_toml().unwrap();
+        assert_eq!(toml::from_str::<PackConfig>(&printed).unwrap(), config);
+
+        let (map_dirs, options) = config.into_pack_args();
+        assert_eq!(map_dirs, [("/lib".to_string(), dir.path().join("lib"))]);
+        assert_eq!(options.tars, [(PathBuf::from("-"), "/assets".to_string())]);
+        assert_eq!(options.excludes, ["*.pyc", "*.md"]);
+        assert_eq!(options.mode, PackMode::Merge);
+        assert!(options.dedup);
+        // Command line options override the file even if turning off
+        assert!(!options.dev);
+        assert_eq!(options.init_func.as_deref(), Some("init"));
+
+        std::fs::write(&path, "[[mounts]]\nguest = \"/lib\"\n").unwrap();
+        assert!(PackConfig::load(&path).is_err());
+        std::fs::write(&path, "unknown = true\n").unwrap();
+        assert!(PackConfig::load(&path).is_err());
+    }
+}
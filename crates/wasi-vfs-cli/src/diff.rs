@@ -0,0 +1,221 @@
+//! Comparison of the file systems embedded in two packed modules.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::inspect::{EmbeddedEntry, EmbeddedMount, EntryKind, join_guest_path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// What an entry looks like to readers, ignoring timestamps and whether it's
+/// a hard link.
+#[derive(PartialEq, Eq)]
+enum Content<'a> {
+    Dir,
+    File(&'a [u8]),
+    Symlink(&'a str),
+    Dangling,
+}
+
+impl Content<'_> {
+    fn size(&self) -> i64 {
+        match self {
+            Content::File(content) => content.len() as i64,
+            Content::Symlink(target) => target.len() as i64,
+            Content::Dir | Content::Dangling => 0,
+        }
+    }
+}
+
+fn content<'a>(mount: &'a EmbeddedMount, entry: &'a EmbeddedEntry) -> Content<'a> {
+    match &mount.link_target(entry).kind {
+        EntryKind::Dir => Content::Dir,
+        EntryKind::File(content) => Content::File(content),
+        EntryKind::Symlink(target) => Content::Symlink(target),
+        EntryKind::HardLink(_) => Content::Dangling,
+    }
+}
+
+/// Mounts keyed by their guest directories. Mounts registered more than once
+/// at the same guest directory are paired in their registration order.
+fn key_mounts(mounts: &[EmbeddedMount]) -> BTreeMap<(String, usize), &EmbeddedMount> {
+    let mut keyed = BTreeMap::new();
+    for mount in mounts {
+        let nth = (0..)
+            .find(|nth| !keyed.contains_key(&(mount.guest_dir.clone(), *nth)))
+            .unwrap();
+        keyed.insert((mount.guest_dir.clone(), nth), mount);
+    }
+    keyed
+}
+
+/// Write added, removed and modified paths per mount with size deltas. With
+/// `unified`, a unified diff of modified text files follows each of them.
+pub(crate) fn diff(
+    out: &mut impl Write,
+    old: &[EmbeddedMount],
+    new: &[EmbeddedMount],
+    unified: bool,
+) -> Result<()> {
+    let (old, new) = (key_mounts(old), key_mounts(new));
+    let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    let mut counts = [0; 3];
+    let mut total_delta = 0;
+    for key in keys {
+        let (old, new) = (old.get(key), new.get(key));
+        let header = match (old, new) {
+            (Some(_), Some(_)) => format!("mount {}", key.0),
+            (Some(_), None) => format!("mount {} (removed)", key.0),
+            (None, _) => format!("mount {} (added)", key.0),
+        };
+        let mut changes = vec![];
+        if let Some(old) = old {
+            for entry in &old.entries {
+                let old_content = content(old, entry);
+                match new.and_then(|new| Some((new, new.find(&entry.path)?))) {
+                    None => changes.push((entry.path.as_str(), Change::Removed, old_content, None)),
+                    Some((new, new_entry)) => {
+                        let new_content = content(new, new_entry);
+                        if old_content != new_content {
+                            changes.push((
+                                entry.path.as_str(),
+                                Change::Modified,
+                                old_content,
+                                Some(new_content),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(new) = new {
+            for entry in &new.entries {
+                if old.and_then(|old| old.find(&entry.path)).is_none() {
+                    changes.push((
+                        entry.path.as_str(),
+                        Change::Added,
+                        content(new, entry),
+                        None,
+                    ));
+                }
+            }
+        }
+        if changes.is_empty() {
+            continue;
+        }
+        changes.sort_by(|a, b| a.0.cmp(b.0));
+
+        writeln!(out, "{}", header)?;
+        let guest_dir = &key.0;
+        for (path, change, content, new_content) in changes {
+            let guest_path = join_guest_path(guest_dir, path);
+            let (mark, delta, sizes) = match (change, &new_content) {
+                (Change::Added, _) => ('+', content.size(), content.size().to_string()),
+                (Change::Removed, _) => ('-', -content.size(), content.size().to_string()),
+                (Change::Modified, Some(new_content)) => (
+                    'M',
+                    new_content.size() - content.size(),
+                    format!("{} -> {}", content.size(), new_content.size()),
+                ),
+                (Change::Modified, None) => unreachable!(),
+            };
+            counts[change as usize] += 1;
+            total_delta += delta;
+            writeln!(
+                out,
+                "{} {} ({} bytes, {:+})",
+                mark, guest_path, sizes, delta
+            )?;
+            if let (true, Content::File(old), Some(Content::File(new))) =
+                (unified, &content, &new_content)
+            {
+                write_unified(out, &guest_path, old, new)?;
+            }
+        }
+    }
+    writeln!(
+        out,
+        "{} added, {} removed, {} modified, {:+} bytes",
+        counts[Change::Added as usize],
+        counts[Change::Removed as usize],
+        counts[Change::Modified as usize],
+        total_delta
+    )?;
+    Ok(())
+}
+
+fn write_unified(out: &mut impl Write, guest_path: &str, old: &[u8], new: &[u8]) -> Result<()> {
+    let (Ok(old), Ok(new)) = (std::str::from_utf8(old), std::str::from_utf8(new)) else {
+        writeln!(out, "Binary files differ")?;
+        return Ok(());
+    };
+    let diff = similar::TextDiff::from_lines(old, new);
+    let old_header = format!("a{}", guest_path);
+    let new_header = format!("b{}", guest_path);
+    write!(
+        out,
+        "{}",
+        diff.unified_diff().header(&old_header, &new_header)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::tests::sample_mounts;
+
+    #[test]
+    fn test_diff() {
+        let old = sample_mounts();
+        let mut new = sample_mounts();
+        let entries = &mut new[0].entries;
+        entries[1].kind = EntryKind::File(b"hello\nworld\n".to_vec());
+        entries[1].mtime = 1;
+        entries.remove(2);
+        entries.push(EmbeddedEntry {
+            path: "/new.txt".to_string(),
+            mtime: 0,
+            kind: EntryKind::File(b"new\n".to_vec()),
+        });
+
+        let mut out = vec![];
+        diff(&mut out, &old, &new, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "mount /mnt\n\
+             M /mnt/dir/hello.txt (6 -> 12 bytes, +6)\n\
+             --- a/mnt/dir/hello.txt\n\
+             +++ b/mnt/dir/hello.txt\n\
+             @@ -1 +1,2 @@\n\
+             \x20hello\n\
+             +world\n\
+             - /mnt/dir/link (9 bytes, -9)\n\
+             M /mnt/hard (6 -> 12 bytes, +6)\n\
+             --- a/mnt/hard\n\
+             +++ b/mnt/hard\n\
+             @@ -1 +1,2 @@\n\
+             \x20hello\n\
+             +world\n\
+             + /mnt/new.txt (4 bytes, +4)\n\
+             1 added, 1 removed, 2 modified, +7 bytes\n"
+        );
+
+        let mut out = vec![];
+        diff(&mut out, &old, &old, false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0 added, 0 removed, 0 modified, +0 bytes\n"
+        );
+    }
+}
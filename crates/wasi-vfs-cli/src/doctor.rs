@@ -0,0 +1,407 @@
+//! Static checks of a module before packing, explaining how to fix each
+//! problem instead of failing obscurely inside Wizer.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::manifest::read_manifest;
+
+/// WASI functions hooked by `libwasi_vfs.a`. Keep in sync with
+/// `WASI_HOOK_FUNCTIONS` of `wasi-libc-trampoline-bindgen`.
+const HOOK_FUNCTIONS: &[&str] = &[
+    "fd_advise",
+    "fd_allocate",
+    "fd_close",
+    "fd_datasync",
+    "fd_fdstat_get",
+    "fd_fdstat_set_flags",
+    "fd_fdstat_set_rights",
+    "fd_filestat_get",
+    "fd_filestat_set_size",
+    "fd_filestat_set_times",
+    "fd_pread",
+    "fd_prestat_dir_name",
+    "fd_prestat_get",
+    "fd_pwrite",
+    "fd_read",
+    "fd_readdir",
+    "fd_renumber",
+    "fd_seek",
+    "fd_sync",
+    "fd_tell",
+    "fd_write",
+    "path_create_directory",
+    "path_filestat_get",
+    "path_filestat_set_times",
+    "path_link",
+    "path_open",
+    "path_readlink",
+    "path_remove_directory",
+    "path_rename",
+    "path_symlink",
+    "path_unlink_file",
+    "poll_oneoff",
+];
+
+/// Symbol prefix of WASI imports in wasi-libc since the `wasi_snapshot_preview1`
+/// ABI, overridden by the default trampolines.
+const LATEST_ABI_PREFIX: &str = "__imported_wasi_snapshot_preview1_";
+/// Symbol prefix of WASI imports in older wasi-libc, overridden by the
+/// trampolines of the `legacy-wasi-libc` feature.
+const LEGACY_ABI_PREFIX: &str = "__wasi_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Finding {
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+    /// How to fix the problem.
+    pub(crate) fix: Option<String>,
+}
+
+impl Finding {
+    fn ok(message: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(severity: Severity, message: impl Into<String>, fix: impl Into<String>) -> Self {
+        Finding {
+            severity,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// What the checks need to know about a module.
+#[derive(Debug, Default)]
+struct ModuleInfo {
+    exports: HashSet<String>,
+    /// Debug names of imported functions.
+    imported_funcs: HashSet<String>,
+    /// Debug names of defined functions.
+    defined_funcs: HashSet<String>,
+    has_name_section: bool,
+}
+
+impl ModuleInfo {
+    fn parse(wasm_bytes: &[u8]) -> Result<Self> {
+        let mut info = ModuleInfo::default();
+        let mut imported_func_count = 0;
+        let mut names = HashMap::new();
+        for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+            match payload.context("not a valid Wasm module")? {
+                wasmparser::Payload::Version { encoding, .. }
+                    if encoding != wasmparser::Encoding::Module =>
+                {
+                    anyhow::bail!(
+                        "the input is a component, but wasi-vfs only supports core modules"
+                    );
+                }
+                wasmparser::Payload::ImportSection(imports) => {
+                    for import in imports {
+                        if let wasmparser::TypeRef::Func(_) = import?.ty {
+                            imported_func_count += 1;
+                        }
+                    }
+                }
+                wasmparser::Payload::ExportSection(exports) => {
+                    for export in exports {
+                        info.exports.insert(export?.name.to_string());
+                    }
+                }
+                wasmparser::Payload::CustomSection(section) => {
+                    if let wasmparser::KnownCustom::Name(reader) = section.as_known() {
+                        info.has_name_section = true;
+                        for name in reader {
+                            // Malformed name sections are ignored like runtimes do
+                            let Ok(wasmparser::Name::Function(map)) = name else {
+                                continue;
+                            };
+                            for naming in map.into_iter().flatten() {
+                                names.insert(naming.index, naming.name.to_string());
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (index, name) in names {
+            if index < imported_func_count {
+                info.imported_funcs.insert(name);
+            } else {
+                info.defined_funcs.insert(name);
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// Check the module and describe each problem with its fix.
+pub(crate) fn diagnose(wasm_bytes: &[u8]) -> Result<Vec<Finding>> {
+    let info = ModuleInfo::parse(wasm_bytes)?;
+    let mut findings = vec![];
+    check_exports(&info, &mut findings);
+    check_hooks(&info, &mut findings);
+    check_shape(&info, &mut findings);
+    check_embedded(wasm_bytes, &mut findings)?;
+    Ok(findings)
+}
+
+fn check_exports(info: &ModuleInfo, findings: &mut Vec<Finding>) {
+    let missing = ["wasi_vfs_pack_fs", "__wasi_vfs_rt_init"]
+        .into_iter()
+        .filter(|name| !info.exports.contains(*name))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        findings.push(Finding::problem(
+            Severity::Error,
+            format!("missing exports: {}", missing.join(", ")),
+            "Link the application with libwasi_vfs.a, e.g. `clang app.o libwasi_vfs.a -o app.wasm`.",
+        ));
+        return;
+    }
+    if info.exports.contains("wasi_vfs_dump_fs") {
+        findings.push(Finding::ok(
+            "exports wasi_vfs_pack_fs, __wasi_vfs_rt_init and wasi_vfs_dump_fs",
+        ));
+    } else {
+        findings.push(Finding::problem(
+            Severity::Warning,
+            "missing export: wasi_vfs_dump_fs",
+            "The module is linked with an older libwasi_vfs.a, so packed files can't be inspected \
+             and no manifest is attached. Relink with the libwasi_vfs.a of this version.",
+        ));
+    }
+}
+
+fn check_hooks(info: &ModuleInfo, findings: &mut Vec<Finding>) {
+    if !info.has_name_section {
+        findings.push(Finding::problem(
+            Severity::Warning,
+            "can't check WASI hooks because the module has no name section",
+            "Run doctor on the module before stripping it, e.g. without `-Wl,--strip-all`.",
+        ));
+        return;
+    }
+    let mut hooked = 0;
+    for func in HOOK_FUNCTIONS {
+        let latest = format!("{}{}", LATEST_ABI_PREFIX, func);
+        let legacy = format!("{}{}", LEGACY_ABI_PREFIX, func);
+        if info.imported_funcs.contains(&latest) {
+            findings.push(Finding::problem(
+                Severity::Error,
+                format!("{} is not hooked and reaches the host directly", func),
+                "wasi-libc imports WASI functions as `__imported_wasi_snapshot_preview1_*`, but \
+                 libwasi_vfs.a hooks the older `__wasi_*` symbols. Rebuild libwasi_vfs.a without \
+                 the `legacy-wasi-libc` feature.",
+            ));
+        } else if info.imported_funcs.contains(&legacy) {
+            findings.push(Finding::problem(
+                Severity::Error,
+                format!("{} is not hooked and reaches the host directly", func),
+                "wasi-libc imports WASI functions as `__wasi_*`, but libwasi_vfs.a hooks the newer \
+                 `__imported_wasi_snapshot_preview1_*` symbols. Rebuild libwasi_vfs.a with \
+                 `--features legacy-wasi-libc`, or update wasi-sdk.",
+            ));
+        } else if info.defined_funcs.contains(&latest) || info.defined_funcs.contains(&legacy) {
+            hooked += 1;
+        }
+    }
+    // Functions the application doesn't use are not linked at all
+    findings.push(Finding::ok(format!(
+        "{} of {} hookable WASI functions are used and hooked",
+        hooked,
+        HOOK_FUNCTIONS.len()
+    )));
+}
+
+fn check_shape(info: &ModuleInfo, findings: &mut Vec<Finding>) {
+    let command = info.exports.contains("_start");
+    let reactor = info.exports.contains("_initialize");
+    match (command, reactor) {
+        (true, false) => findings.push(Finding::ok("command module exporting _start")),
+        (false, true) => findings.push(Finding::ok(
+            "reactor module exporting _initialize, which the host must call before other exports",
+        )),
+        (true, true) => findings.push(Finding::problem(
+            Severity::Error,
+            "the module exports both _start and _initialize",
+            "Build either a command with `_start` or a reactor with `-mexec-model=reactor`.",
+        )),
+        (false, false) => findings.push(Finding::problem(
+            Severity::Warning,
+            "the module exports neither _start nor _initialize",
+            "The embedded file system is set up by wasi-libc constructors of commands or by \
+             `_initialize` of reactors. Otherwise, call the `__wasi_vfs_rt_init` export before \
+             using files.",
+        )),
+    }
+}
+
+fn check_embedded(wasm_bytes: &[u8], findings: &mut Vec<Finding>) -> Result<()> {
+    let Some(manifest) = read_manifest(wasm_bytes)? else {
+        findings.push(Finding::ok(
+            "no embedded file system manifest; the module is not packed yet or packed by an older wasi-vfs",
+        ));
+        return Ok(());
+    };
+    let current = env!("CARGO_PKG_VERSION");
+    findings.push(Finding::ok(format!(
+        "already packed by wasi-vfs {} with mounts: {}",
+        manifest.version,
+        manifest.mounts.join(", ")
+    )));
+    if parse_version(&manifest.version) > parse_version(current) {
+        findings.push(Finding::problem(
+            Severity::Warning,
+            format!(
+                "packed by wasi-vfs {}, which is newer than this wasi-vfs {}",
+                manifest.version, current
+            ),
+            "Update wasi-vfs before packing again.",
+        ));
+    }
+    Ok(())
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Write findings, returning the number of errors.
+pub(crate) fn write_report(out: &mut impl Write, findings: &[Finding]) -> Result<usize> {
+    let mut errors = 0;
+    for finding in findings {
+        let label = match finding.severity {
+            Severity::Ok => "ok",
+            Severity::Warning => "warning",
+            Severity::Error => {
+                errors += 1;
+                "error"
+            }
+        };
+        writeln!(out, "{}: {}", label, finding.message)?;
+        if let Some(fix) = &finding.fix {
+            writeln!(out, "  fix: {}", fix)?;
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(exports: &[&str], imports: &[&str], defined: &[&str]) -> Vec<u8> {
+        use wasm_encoder::*;
+        let mut module = Module::new();
+        let mut types = TypeSection::new();
+        types.ty().function([], []);
+        module.section(&types);
+        let mut import_section = ImportSection::new();
+        for name in imports {
+            import_section.import("wasi_snapshot_preview1", name, EntityType::Function(0));
+        }
+        module.section(&import_section);
+        let mut functions = FunctionSection::new();
+        for _ in defined {
+            functions.function(0);
+        }
+        module.section(&functions);
+        let mut export_section = ExportSection::new();
+        for name in exports {
+            export_section.export(name, ExportKind::Func, imports.len() as u32);
+        }
+        module.section(&export_section);
+        let mut code = CodeSection::new();
+        for _ in defined {
+            let mut body = Function::new([]);
+            body.instruction(&Instruction::End);
+            code.function(&body);
+        }
+        module.section(&code);
+        let mut names = NameMap::new();
+        for (index, name) in imports.iter().chain(defined).enumerate() {
+            names.append(index as u32, name);
+        }
+        let mut name_section = NameSection::new();
+        name_section.functions(&names);
+        module.section(&name_section);
+        module.finish()
+    }
+
+    fn errors(findings: &[Finding]) -> Vec<&str> {
+        findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let exports = [
+            "_start",
+            "wasi_vfs_pack_fs",
+            "__wasi_vfs_rt_init",
+            "wasi_vfs_dump_fs",
+        ];
+        let linked = module(
+            &exports,
+            &["fd_write"],
+            &["__imported_wasi_snapshot_preview1_fd_read", "main"],
+        );
+        let findings = diagnose(&linked).unwrap();
+        assert!(errors(&findings).is_empty());
+        assert!(findings.contains(&Finding::ok(
+            "1 of 32 hookable WASI functions are used and hooked"
+        )));
+
+        let legacy = module(
+            &exports,
+            &["__imported_wasi_snapshot_preview1_fd_read"],
+            &["main"],
+        );
+        assert_eq!(
+            errors(&diagnose(&legacy).unwrap()),
+            ["fd_read is not hooked and reaches the host directly"]
+        );
+
+        let unlinked = module(&["_start", "_initialize"], &[], &["main"]);
+        assert_eq!(
+            errors(&diagnose(&unlinked).unwrap()),
+            [
+                "missing exports: wasi_vfs_pack_fs, __wasi_vfs_rt_init",
+                "the module exports both _start and _initialize"
+            ]
+        );
+
+        let mut out = vec![];
+        assert_eq!(
+            write_report(&mut out, &diagnose(&unlinked).unwrap()).unwrap(),
+            2
+        );
+        assert!(
+            String::from_utf8(out)
+                .unwrap()
+                .contains("  fix: Link the application")
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::{fmt, path::Path, path::PathBuf};
+
+/// An error reported by the in-guest scanner while packing host directories.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackError {
+    /// The guest path of the entry which failed to be packed.
+    pub guest_path: String,
+    /// The host path of the entry, if it belongs to a mapped host directory.
+    pub host_path: Option<PathBuf>,
+    /// Raw WASI errno reported by the guest.
+    pub errno: u16,
+}
+
+impl PackError {
+    /// Parse a report written by the guest into the control directory and
+    /// resolve its host path from the given directory mappings.
+    pub(crate) fn from_report(report: &str, map_dirs: &[(String, PathBuf)]) -> Option<Self> {
+        let mut errno = None;
+        let mut mount = None;
+        let mut path = None;
+        for line in report.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "errno" => errno = value.parse().ok(),
+                "mount" => mount = Some(value),
+                "path" => path = Some(value),
+                _ => {}
+            }
+        }
+        let (errno, mount, path) = (errno?, mount?, path?);
+        let host_path = map_dirs
+            .iter()
+            .rev()
+            .find(|(guest_dir, _)| guest_dir == mount)
+            .map(|(_, host_dir)| join_relpath(host_dir, path));
+        let guest_path = if path.is_empty() {
+            mount.to_string()
+        } else {
+            format!("{}{}", mount.trim_end_matches('/'), path)
+        };
+        Some(PackError {
+            guest_path,
+            host_path,
+            errno,
+        })
+    }
+
+    /// Symbolic name of the errno, e.g. `ENOENT`.
+    pub fn errno_name(&self) -> &'static str {
+        errno_name(self.errno)
+    }
+}
+
+fn join_relpath(base: &Path, relpath: &str) -> PathBuf {
+    let relpath = relpath.trim_start_matches('/');
+    if relpath.is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relpath)
+    }
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to pack '{}'", self.guest_path)?;
+        if let Some(host_path) = &self.host_path {
+            write!(f, " from '{}'", host_path.display())?;
+        }
+        write!(f, ": {} (errno {})", self.errno_name(), self.errno)
+    }
+}
+
+impl std::error::Error for PackError {}
+
+/// Returns the symbolic name of a WASI errno value.
+pub fn errno_name(errno: u16) -> &'static str {
+    crate::errno_generated::ERRNO_NAMES
+        .get(errno as usize)
+        .copied()
+        .unwrap_or("EUNKNOWN")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_error_from_report() {
+        let map_dirs = vec![("/mnt".to_string(), PathBuf::from("host/mnt"))];
+        let error =
+            PackError::from_report("errno=22\nmount=/mnt\npath=/big.bin\n", &map_dirs).unwrap();
+        assert_eq!(error.guest_path, "/mnt/big.bin");
+        assert_eq!(error.host_path, Some(PathBuf::from("host/mnt/big.bin")));
+        assert_eq!(error.errno_name(), "EFBIG");
+    }
+
+    #[test]
+    fn test_pack_error_from_malformed_report() {
+        assert!(PackError::from_report("errno=x\n", &[]).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//! Extraction of the embedded file system back to the host.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+
+use crate::inspect::{EmbeddedMount, EntryKind};
+
+/// Recreate embedded mounts under `output`. Each mount is extracted at its
+/// guest directory relative to `output`, e.g. `/mnt` at `<output>/mnt`. Only
+/// mounts whose guest directories are in `selected` are extracted unless it's
+/// empty.
+pub(crate) fn extract(mounts: &[EmbeddedMount], output: &Path, selected: &[String]) -> Result<()> {
+    for guest_dir in selected {
+        if !mounts
+            .iter()
+            .any(|mount| same_guest_dir(&mount.guest_dir, guest_dir))
+        {
+            anyhow::bail!("'{}' is not an embedded directory", guest_dir);
+        }
+    }
+    for mount in mounts {
+        if !selected.is_empty()
+            && !selected
+                .iter()
+                .any(|guest_dir| same_guest_dir(&mount.guest_dir, guest_dir))
+        {
+            continue;
+        }
+        let root = host_path(output, &mount.guest_dir)?;
+        extract_mount(mount, &root)
+            .with_context(|| format!("failed to extract '{}'", mount.guest_dir))?;
+    }
+    Ok(())
+}
+
+fn extract_mount(mount: &EmbeddedMount, root: &Path) -> Result<()> {
+    std::fs::create_dir_all(root)
+        .with_context(|| format!("failed to create '{}'", root.display()))?;
+    let mut dirs = vec![];
+    for entry in &mount.entries {
+        let path = host_path(root, &entry.path)?;
+        let context = || format!("failed to create '{}'", path.display());
+        match &entry.kind {
+            EntryKind::Dir => {
+                std::fs::create_dir_all(&path).with_context(context)?;
+                dirs.push((path, entry.mtime));
+            }
+            EntryKind::File(content) => {
+                let file = File::create_new(&path).with_context(context)?;
+                (&file).write_all(content).with_context(context)?;
+                if let Some(mtime) = to_system_time(entry.mtime) {
+                    file.set_modified(mtime).with_context(context)?;
+                }
+            }
+            EntryKind::Symlink(target) => {
+                let is_dir = mount
+                    .resolve(&entry.path)
+                    .is_some_and(|target| target.kind == EntryKind::Dir);
+                symlink(target, &path, is_dir).with_context(context)?;
+            }
+            EntryKind::HardLink(target) => {
+                let target = host_path(root, target)?;
+                std::fs::hard_link(&target, &path).with_context(context)?;
+            }
+        }
+    }
+    // Directory timestamps are updated by creating their children, so set
+    // them after the whole tree is populated, deepest first.
+    for (path, mtime) in dirs.iter().rev() {
+        if let Some(mtime) = to_system_time(*mtime) {
+            set_dir_modified(path, mtime);
+        }
+    }
+    Ok(())
+}
+
+/// Join a `/`-separated guest path under `root`, refusing paths escaping it.
+fn host_path(root: &Path, guest_path: &str) -> Result<PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in guest_path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => anyhow::bail!("'{}' escapes the extraction root", guest_path),
+            name => path.push(name),
+        }
+    }
+    Ok(path)
+}
+
+fn same_guest_dir(a: &str, b: &str) -> bool {
+    let trim = |dir: &str| dir.trim_end_matches('/').to_string();
+    trim(a) == trim(b)
+}
+
+/// Timestamps of zero are treated as not recorded.
+fn to_system_time(mtime: u64) -> Option<SystemTime> {
+    if mtime == 0 {
+        return None;
+    }
+    Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(mtime))
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path, _is_dir: bool) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &str, path: &Path, is_dir: bool) -> std::io::Result<()> {
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, path)
+    } else {
+        std::os::windows::fs::symlink_file(target, path)
+    }
+}
+
+/// Best-effort as directories can't be opened as files on some platforms.
+fn set_dir_modified(path: &Path, mtime: SystemTime) {
+    if let Ok(dir) = File::open(path) {
+        let _ = dir.set_modified(mtime);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::tests::sample_mounts;
+
+    #[test]
+    fn test_extract() {
+        let mut mounts = sample_mounts();
+        mounts[0].entries[1].mtime = 1_000_000_000_000_000_000;
+        let output = tempfile::tempdir().unwrap();
+        extract(&mounts, output.path(), &["/mnt/".to_string()]).unwrap();
+
+        let hello = output.path().join("mnt/dir/hello.txt");
+        assert_eq!(std::fs::read(&hello).unwrap(), b"hello\n");
+        let modified = std::fs::metadata(&hello).unwrap().modified().unwrap();
+        assert_eq!(
+            modified.duration_since(SystemTime::UNIX_EPOCH).unwrap(),
+            Duration::from_secs(1_000_000_000)
+        );
+        let link = output.path().join("mnt/dir/link");
+        assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("hello.txt"));
+        assert_eq!(
+            std::fs::read(output.path().join("mnt/hard")).unwrap(),
+            b"hello\n"
+        );
+
+        assert!(extract(&mounts, output.path(), &["/other".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_host_path() {
+        let root = Path::new("out");
+        assert_eq!(host_path(root, "/a/./b").unwrap(), root.join("a").join("b"));
+        assert_eq!(host_path(root, "/").unwrap(), root);
+        assert!(host_path(root, "/a/../../b").is_err());
+    }
+}
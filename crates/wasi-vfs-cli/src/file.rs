@@ -0,0 +1,139 @@
+//! Conversion of individual host files and in-memory trees into the tree
+//! stream.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::stream;
+
+/// Write a host file at `guest_path` into the stream. The file is placed in
+/// the innermost of `mounts` containing the guest path, or in a new mount at
+/// its parent directory. Missing intermediate directories are created by the
+/// guest.
+pub(crate) fn feed_file<W: Write>(
+    feed: &mut stream::Writer<W>,
+    host_path: &Path,
+    guest_path: &str,
+    mounts: &[&str],
+) -> Result<()> {
+    let (mount, relpath) = split_guest_path(guest_path, mounts)?;
+    let context = || format!("failed to read file '{}'", host_path.display());
+    let metadata = std::fs::metadata(host_path).with_context(context)?;
+    if !metadata.is_file() {
+        anyhow::bail!("'{}' is not a regular file", host_path.display());
+    }
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |mtime| mtime.as_nanos() as u64);
+    let content = std::fs::read(host_path).with_context(context)?;
+    feed.mount(&mount)?;
+    feed.file(&relpath, mtime, &content)?;
+    Ok(())
+}
+
+/// Write in-memory files keyed by paths relative to the current mount into
+/// the stream. Missing parent directories are created by the guest.
+pub(crate) fn feed_tree<W: Write>(
+    feed: &mut stream::Writer<W>,
+    files: &BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    for (path, content) in files {
+        let relpath = normalize_relpath(path)
+            .ok_or_else(|| anyhow::anyhow!("'{}' is not a file path in the tree", path))?;
+        // In-memory files have no timestamps to record
+        feed.file(&relpath, 0, content)?;
+    }
+    Ok(())
+}
+
+/// Normalize a path relative to a mount into the `/`-prefixed form used in
+/// the stream. Returns `None` for paths escaping the mount or naming it.
+fn normalize_relpath(path: &str) -> Option<String> {
+    let mut components = vec![];
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            name => components.push(name),
+        }
+    }
+    if components.is_empty() || path.ends_with('/') {
+        return None;
+    }
+    Some(components.iter().map(|name| format!("/{}", name)).collect())
+}
+
+/// Split an absolute guest file path into the guest directory of the mount
+/// containing it and the path relative to the mount.
+fn split_guest_path(guest_path: &str, mounts: &[&str]) -> Result<(String, String)> {
+    let invalid = || anyhow::anyhow!("'{}' is not an absolute file path", guest_path);
+    if !guest_path.starts_with('/') {
+        return Err(invalid());
+    }
+    let path = normalize_relpath(guest_path).ok_or_else(invalid)?;
+    let mount = mounts
+        .iter()
+        .map(|mount| mount.trim_end_matches('/'))
+        .filter(|mount| {
+            path.strip_prefix(mount)
+                .is_some_and(|relpath| relpath.starts_with('/'))
+        })
+        .max_by_key(|mount| mount.len());
+    let mount = match mount {
+        Some(mount) => mount.to_string(),
+        None => path[..path.rfind('/').unwrap()].to_string(),
+    };
+    let relpath = path[mount.len()..].to_string();
+    let mount = if mount.is_empty() {
+        "/".to_string()
+    } else {
+        mount
+    };
+    Ok((mount, relpath))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_relpath, split_guest_path};
+
+    #[test]
+    fn test_split_guest_path() {
+        let split = |path, mounts: &[&str]| split_guest_path(path, mounts).unwrap();
+        assert_eq!(
+            split("/etc/ssl/cert.pem", &[]),
+            ("/etc/ssl".to_string(), "/cert.pem".to_string())
+        );
+        assert_eq!(
+            split("/app/conf/./config.toml", &["/app", "/app/data", "/ap"]),
+            ("/app".to_string(), "/conf/config.toml".to_string())
+        );
+        assert_eq!(
+            split("/config.toml", &[]),
+            ("/".to_string(), "/config.toml".to_string())
+        );
+        assert_eq!(
+            split("/etc/hosts", &["/"]),
+            ("/".to_string(), "/etc/hosts".to_string())
+        );
+        assert!(split_guest_path("relative.txt", &[]).is_err());
+        assert!(split_guest_path("/dir/", &[]).is_err());
+        assert!(split_guest_path("/..", &[]).is_err());
+    }
+
+    #[test]
+    fn test_normalize_relpath() {
+        assert_eq!(normalize_relpath("a/./b.txt").as_deref(), Some("/a/b.txt"));
+        assert_eq!(normalize_relpath("/a//b.txt").as_deref(), Some("/a/b.txt"));
+        assert_eq!(normalize_relpath("a/../b.txt").as_deref(), Some("/b.txt"));
+        assert_eq!(normalize_relpath("../b.txt"), None);
+        assert_eq!(normalize_relpath("."), None);
+        assert_eq!(normalize_relpath("a/"), None);
+    }
+}
@@ -0,0 +1,306 @@
+//! Inspection of the file system embedded in packed Wasm modules.
+
+use anyhow::{Context, Result};
+
+use crate::stream::{self, Record};
+use crate::{CONTROL_DIR_ENV, CONTROL_DIR_GUEST_PATH};
+
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// A tree embedded at a guest directory, registered as a preopen at runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedMount {
+    pub guest_dir: String,
+    /// Entries in the depth-first order. Parents precede their children.
+    pub entries: Vec<EmbeddedEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedEntry {
+    /// Path relative to the mount, starting with `/`.
+    pub path: String,
+    /// Last modification time in nanoseconds since the epoch.
+    pub mtime: u64,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    Dir,
+    File(Vec<u8>),
+    Symlink(String),
+    /// Another path of the entry at the target path in the same mount.
+    HardLink(String),
+}
+
+impl EmbeddedEntry {
+    /// The last component of the path.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+impl EmbeddedMount {
+    /// Find an entry at `path` relative to the mount without following
+    /// symbolic links or hard links.
+    pub fn find(&self, path: &str) -> Option<&EmbeddedEntry> {
+        let path = normalize(path);
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// Find an entry at `path` following symbolic links. Absolute link
+    /// targets are resolved from the mount root like the runtime does.
+    pub fn resolve(&self, path: &str) -> Option<&EmbeddedEntry> {
+        let mut path = normalize(path);
+        for _ in 0..MAX_SYMLINK_DEPTH {
+            let entry = self.find(&path)?;
+            let entry = match &entry.kind {
+                EntryKind::HardLink(target) => self.find(target)?,
+                _ => entry,
+            };
+            match &entry.kind {
+                EntryKind::Symlink(target) if target.starts_with('/') => path = normalize(target),
+                EntryKind::Symlink(target) => {
+                    let parent = path.rsplit_once('/').map(|(p, _)| p).unwrap_or_default();
+                    path = normalize(&format!("{}/{}", parent, target));
+                }
+                _ => return Some(entry),
+            }
+        }
+        None
+    }
+
+    /// Entries directly under the directory at `dir`.
+    pub fn children<'a>(&'a self, dir: &str) -> impl Iterator<Item = &'a EmbeddedEntry> + 'a {
+        let dir = normalize(dir);
+        self.entries
+            .iter()
+            .filter(move |entry| entry.path.rsplit_once('/').map(|(p, _)| p) == Some(&dir))
+    }
+
+    /// The entry a hard link points to, or the entry itself.
+    pub fn link_target<'a>(&'a self, entry: &'a EmbeddedEntry) -> &'a EmbeddedEntry {
+        match &entry.kind {
+            EntryKind::HardLink(target) => self.find(target).unwrap_or(entry),
+            _ => entry,
+        }
+    }
+
+    /// Number of paths referring to the entry.
+    pub fn nlink(&self, entry: &EmbeddedEntry) -> u64 {
+        let target = self.link_target(entry);
+        if target.kind == EntryKind::Dir {
+            return 1;
+        }
+        let links = self
+            .entries
+            .iter()
+            .filter(|other| matches!(&other.kind, EntryKind::HardLink(t) if *t == target.path))
+            .count();
+        1 + links as u64
+    }
+}
+
+/// Normalize a mount-relative path to start with `/` without `.`, `..`
+/// and trailing `/`. The mount root is normalized to an empty string.
+fn normalize(path: &str) -> String {
+    let mut components: Vec<&str> = vec![];
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    components.iter().map(|name| format!("/{}", name)).collect()
+}
+
+/// Locate the mount containing `guest_path` and the path relative to it. The
+/// mount with the longest matching guest directory wins.
+pub fn locate<'a>(
+    mounts: &'a [EmbeddedMount],
+    guest_path: &str,
+) -> Option<(&'a EmbeddedMount, String)> {
+    let guest_path = normalize(guest_path);
+    mounts
+        .iter()
+        .filter_map(|mount| {
+            let guest_dir = normalize(&mount.guest_dir);
+            let relpath = guest_path.strip_prefix(&guest_dir)?;
+            if !relpath.is_empty() && !relpath.starts_with('/') {
+                return None;
+            }
+            Some((mount, guest_dir.len(), relpath.to_string()))
+        })
+        .max_by_key(|(_, len, _)| *len)
+        .map(|(mount, _, relpath)| (mount, relpath))
+}
+
+/// Join a guest directory and a path relative to it.
+pub fn join_guest_path(guest_dir: &str, relpath: &str) -> String {
+    let joined = format!("{}{}", guest_dir.trim_end_matches('/'), relpath);
+    if joined.is_empty() {
+        "/".to_string()
+    } else {
+        joined
+    }
+}
+
+/// Read the file system embedded in a packed Wasm module.
+///
+/// The module is instantiated without running its entry point, and the
+/// `wasi_vfs_dump_fs` export writes the embedded trees into a temporary
+/// control directory.
+pub fn dump_fs(wasm_bytes: &[u8]) -> Result<Vec<EmbeddedMount>> {
+    let engine = wasmtime::Engine::default();
+    let (module, wasi, control_dir) = prepare_dump(&engine, wasm_bytes)?;
+    let mut store = wasmtime::Store::new(&engine, wasi.build_p1());
+    let mut linker = wasmtime::Linker::new(&engine);
+    wasmtime_wasi::p1::add_to_linker_sync(&mut linker, |x| x)?;
+    linker.define_unknown_imports_as_traps(&module)?;
+    let instance = linker.instantiate(&mut store, &module)?;
+    let dump = instance.get_typed_func::<(), ()>(&mut store, "wasi_vfs_dump_fs")?;
+    dump.call(&mut store, ())
+        .context("failed to dump the embedded file system")?;
+    read_dump(&control_dir)
+}
+
+/// Same as [`dump_fs`], but on the async engine used for packing.
+pub(crate) async fn dump_fs_async(
+    engine: &wasmtime::Engine,
+    wasm_bytes: &[u8],
+) -> Result<Vec<EmbeddedMount>> {
+    let (module, wasi, control_dir) = prepare_dump(engine, wasm_bytes)?;
+    let mut store = wasmtime::Store::new(engine, wasi.build_p1());
+    let mut linker = wasmtime::Linker::new(engine);
+    wasmtime_wasi::p1::add_to_linker_async(&mut linker, |x| x)?;
+    linker.define_unknown_imports_as_traps(&module)?;
+    let instance = linker.instantiate_async(&mut store, &module).await?;
+    let dump = instance.get_typed_func::<(), ()>(&mut store, "wasi_vfs_dump_fs")?;
+    dump.call_async(&mut store, ())
+        .await
+        .context("failed to dump the embedded file system")?;
+    read_dump(&control_dir)
+}
+
+/// Compile the module and configure WASI to let it write the dump into a new
+/// control directory.
+fn prepare_dump(
+    engine: &wasmtime::Engine,
+    wasm_bytes: &[u8],
+) -> Result<(
+    wasmtime::Module,
+    wasmtime_wasi::WasiCtxBuilder,
+    tempfile::TempDir,
+)> {
+    let module = wasmtime::Module::new(engine, wasm_bytes)?;
+    if module.get_export("wasi_vfs_dump_fs").is_none() {
+        anyhow::bail!(
+            "the module doesn't export `wasi_vfs_dump_fs`; it's not packed by wasi-vfs or packed by an older version"
+        );
+    }
+
+    let control_dir = tempfile::tempdir()?;
+    let mut wasi = wasmtime_wasi::WasiCtxBuilder::new();
+    wasi.inherit_stderr();
+    wasi.env(CONTROL_DIR_ENV, CONTROL_DIR_GUEST_PATH);
+    wasi.preopened_dir(
+        control_dir.path(),
+        CONTROL_DIR_GUEST_PATH,
+        wasmtime_wasi::DirPerms::all(),
+        wasmtime_wasi::FilePerms::all(),
+    )?;
+    Ok((module, wasi, control_dir))
+}
+
+fn read_dump(control_dir: &tempfile::TempDir) -> Result<Vec<EmbeddedMount>> {
+    let bytes = std::fs::read(control_dir.path().join("dump"))?;
+    parse_dump(&bytes)
+}
+
+fn parse_dump(bytes: &[u8]) -> Result<Vec<EmbeddedMount>> {
+    let mut reader = stream::Reader::new(bytes)?;
+    let mut mounts: Vec<EmbeddedMount> = vec![];
+    while let Some(record) = reader.next_record()? {
+        let entry = match record {
+            Record::Mount { guest_dir } => {
+                mounts.push(EmbeddedMount {
+                    guest_dir,
+                    entries: vec![],
+                });
+                continue;
+            }
+            Record::Dir { path, mtime } => EmbeddedEntry {
+                path,
+                mtime,
+                kind: EntryKind::Dir,
+            },
+            Record::File {
+                path,
+                mtime,
+                content,
+            } => EmbeddedEntry {
+                path,
+                mtime,
+                kind: EntryKind::File(content),
+            },
+            Record::Symlink {
+                path,
+                mtime,
+                target,
+            } => EmbeddedEntry {
+                path,
+                mtime,
+                kind: EntryKind::Symlink(target),
+            },
+            Record::HardLink { path, target } => EmbeddedEntry {
+                path,
+                mtime: 0,
+                kind: EntryKind::HardLink(target),
+            },
+            Record::Archive { .. } => anyhow::bail!("unexpected archive record in dump"),
+        };
+        let mount = mounts
+            .last_mut()
+            .context("malformed dump: entry without mount")?;
+        mount.entries.push(entry);
+    }
+    Ok(mounts)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A mount with `/dir/hello.txt`, a symbolic link and a hard link to it.
+    pub(crate) fn sample_mounts() -> Vec<EmbeddedMount> {
+        let entry = |path: &str, kind| EmbeddedEntry {
+            path: path.to_string(),
+            mtime: 0,
+            kind,
+        };
+        vec![EmbeddedMount {
+            guest_dir: "/mnt".to_string(),
+            entries: vec![
+                entry("/dir", EntryKind::Dir),
+                entry("/dir/hello.txt", EntryKind::File(b"hello\n".to_vec())),
+                entry("/dir/link", EntryKind::Symlink("hello.txt".to_string())),
+                entry("/hard", EntryKind::HardLink("/dir/hello.txt".to_string())),
+            ],
+        }]
+    }
+
+    #[test]
+    fn test_locate_and_resolve() {
+        let mounts = sample_mounts();
+        let (mount, relpath) = locate(&mounts, "/mnt/dir/link").unwrap();
+        assert_eq!(relpath, "/dir/link");
+        let entry = mount.resolve(&relpath).unwrap();
+        assert_eq!(entry.path, "/dir/hello.txt");
+        assert_eq!(mount.nlink(entry), 2);
+        assert_eq!(mount.children("/dir").count(), 2);
+        assert!(locate(&mounts, "/mntx").is_none());
+    }
+}
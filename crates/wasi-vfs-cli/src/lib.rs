@@ -1,8 +1,27 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use anyhow::Result;
 use structopt::StructOpt;
+mod archive;
+mod config;
+mod diff;
+mod doctor;
+mod errno_generated;
+mod error;
+mod extract;
+mod file;
+mod inspect;
+mod ls;
+mod manifest;
 mod module_link;
+mod packer;
+mod stream;
+
+pub use error::PackError;
+pub use inspect::{EmbeddedEntry, EmbeddedMount, EntryKind, dump_fs};
+pub use manifest::{Manifest, ManifestEntry, ManifestEntryKind, ModuleSize, Totals, read_manifest};
+pub use packer::{Packed, Packer};
 
 fn parse_map_dirs(s: &str) -> anyhow::Result<(String, PathBuf)> {
     let parts: Vec<&str> = s.split("::").collect();
@@ -20,6 +39,16 @@ fn parse_dirs(s: &str) -> anyhow::Result<(PathBuf, String)> {
     Ok((parts[0].into(), parts[1].into()))
 }
 
+/// A `--FLAG`/`--no-FLAG` pair, `None` if neither is given so that the
+/// config decides.
+fn flag(on: bool, off: bool) -> Option<bool> {
+    match (on, off) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
 #[derive(Debug, StructOpt)]
 pub enum App {
     #[structopt(setting(structopt::clap::AppSettings::Hidden))]
@@ -45,24 +74,235 @@ pub enum App {
         #[structopt(long = "dir", value_name = "HOST_DIR::GUEST_DIR", parse(try_from_str = parse_dirs))]
         dirs: Vec<(PathBuf, String)>,
 
+        /// Package a tar archive (optionally gzip compressed) into Wasm module
+        /// at a guest directory. Use `-` to read the archive from stdin.
+        #[structopt(long = "tar", value_name = "ARCHIVE::GUEST_DIR", parse(try_from_str = parse_dirs))]
+        tars: Vec<(PathBuf, String)>,
+
+        /// Embed a tar archive as is without unpacking it at packing time.
+        /// It's served in place by wasi-vfs built with `archive-storage`
+        /// feature, otherwise it's unpacked in the guest. Gzip compressed
+        /// archives are decompressed first. Use `-` to read from stdin.
+        #[structopt(long = "embed-tar", value_name = "ARCHIVE::GUEST_DIR", parse(try_from_str = parse_dirs))]
+        embedded_tars: Vec<(PathBuf, String)>,
+
+        /// Package a zip archive into Wasm module at a guest directory. Use
+        /// `-` to read the archive from stdin.
+        #[structopt(long = "zip", value_name = "ARCHIVE::GUEST_DIR", parse(try_from_str = parse_dirs))]
+        zips: Vec<(PathBuf, String)>,
+
+        /// Package a host file into Wasm module at a guest path. The file is
+        /// placed in the packed directory containing the guest path, or in a
+        /// new one at its parent directory.
+        #[structopt(long = "file", value_name = "HOST_FILE::GUEST_PATH", parse(try_from_str = parse_dirs))]
+        files: Vec<(PathBuf, String)>,
+
+        /// Read mounts, files and options from a TOML file. Relative paths in
+        /// it are resolved against its directory. Settings given on the
+        /// command line are added to or override the file.
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
+
+        /// The file path to write the output Wasm module to.
+        #[structopt(long, short, parse(from_os_str), required_unless = "config")]
+        output: Option<PathBuf>,
+
+        /// Fail instead of skipping host entries which can't be packed
+        /// (e.g. too large files, FIFOs or non UTF-8 file names)
+        #[structopt(long, overrides_with = "no-strict")]
+        strict: bool,
+
+        /// Skip host entries which can't be packed, overriding the config
+        #[structopt(long, overrides_with = "strict")]
+        no_strict: bool,
+
+        /// Exclude host entries matching the glob pattern. Patterns follow
+        /// `.gitignore` conventions and are matched against paths relative to
+        /// the mapped directory. `.wasivfsignore` in the mapped directory is
+        /// also read if exists.
+        #[structopt(long = "exclude", value_name = "PATTERN")]
+        excludes: Vec<String>,
+
+        /// Package only files matching the glob pattern
+        #[structopt(long = "include", value_name = "PATTERN")]
+        includes: Vec<String>,
+
+        /// Replace directories embedded by previous packing at the same guest
+        /// directories instead of shadowing them
+        #[structopt(long, conflicts_with = "merge")]
+        replace: bool,
+
+        /// Merge into directories embedded by previous packing at the same
+        /// guest directories. Existing paths are overwritten.
+        #[structopt(long)]
+        merge: bool,
+
+        /// Embed files with identical content and timestamp once, as hard
+        /// links of each other
+        #[structopt(long, overrides_with = "no-dedup")]
+        dedup: bool,
+
+        /// Embed every file separately, overriding the config
+        #[structopt(long, overrides_with = "dedup")]
+        no_dedup: bool,
+
+        /// Mount built-in device nodes `/dev/null`, `/dev/zero` and
+        /// `/dev/urandom` at runtime. Also enabled by `WASI_VFS_DEV=1`.
+        #[structopt(long, overrides_with = "no-dev")]
+        dev: bool,
+
+        /// Don't mount device nodes unless `WASI_VFS_DEV=1`, overriding the
+        /// config
+        #[structopt(long, overrides_with = "dev")]
+        no_dev: bool,
+
+        /// Timestamp of packed entries: `preserve` host timestamps, `zero`,
+        /// or seconds since the Unix epoch
+        #[structopt(long, value_name = "POLICY")]
+        mtime: Option<MtimePolicy>,
+
+        /// Run the exported function after packing in the same snapshot, with
+        /// the packed files visible as at runtime. In reactor modules, the
+        /// function has to call `_initialize` if needed.
+        #[structopt(long, value_name = "NAME")]
+        init_func: Option<String>,
+    },
+
+    /// Remove embedded directories from a packed Wasm module
+    RmMount {
+        /// The packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The guest directory to remove
+        #[structopt(long = "mount", value_name = "GUEST_DIR", required = true)]
+        mounts: Vec<String>,
+
         /// The file path to write the output Wasm module to.
         #[structopt(long, short, parse(from_os_str))]
         output: PathBuf,
     },
+
+    /// List files embedded in a packed Wasm module
+    Ls {
+        /// The packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The guest path to list. Embedded directories are listed if not given.
+        guest_path: Option<String>,
+
+        /// List subdirectories recursively
+        #[structopt(short = "R", long)]
+        recursive: bool,
+
+        /// Show type, link count and size of each entry
+        #[structopt(short, long)]
+        long: bool,
+    },
+
+    /// Show the manifest of the file system embedded in a packed Wasm module
+    Inspect {
+        /// The packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Print the manifest and size totals in JSON
+        #[structopt(long)]
+        json: bool,
+    },
+
+    /// Print the content of a file embedded in a packed Wasm module
+    Cat {
+        /// The packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The guest path of the file to print.
+        guest_path: String,
+    },
+
+    /// Compare files embedded in two packed Wasm modules
+    Diff {
+        /// The old packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// The new packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// Show unified diffs of modified text files
+        #[structopt(short, long)]
+        unified: bool,
+    },
+
+    /// Extract files embedded in a packed Wasm module to a host directory
+    Extract {
+        /// The packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The host directory to extract to. Each embedded directory is
+        /// extracted at its guest path under it.
+        #[structopt(long, short, parse(from_os_str))]
+        output: PathBuf,
+
+        /// Extract only the embedded directory at the guest path
+        #[structopt(long = "mount", value_name = "GUEST_DIR")]
+        mounts: Vec<String>,
+    },
+
+    /// Check that a Wasm module is ready to be packed, explaining how to fix
+    /// each problem found
+    Doctor {
+        /// The Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// Show files embedded in a packed Wasm module as trees
+    Tree {
+        /// The packed Wasm module's file path.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// The guest directory to show. All embedded directories are shown if
+        /// not given.
+        guest_path: Option<String>,
+    },
 }
 
 impl App {
     pub fn execute(self) -> Result<()> {
         match self {
-            App::LinkModule { input, .. } => {
+            App::LinkModule { input, output } => {
                 let bytes = std::fs::read(&input)?;
-                module_link::link(&bytes);
+                let output_bytes = module_link::link(&bytes)?;
+                std::fs::write(output, output_bytes)?;
             }
             App::Pack {
                 input,
                 map_dirs,
                 dirs,
+                tars,
+                embedded_tars,
+                zips,
+                files,
+                config,
                 output,
+                strict,
+                no_strict,
+                excludes,
+                includes,
+                replace,
+                merge,
+                dedup,
+                no_dedup,
+                dev,
+                no_dev,
+                mtime,
+                init_func,
             } => {
                 let wasm_bytes = std::fs::read(&input)?;
                 if !map_dirs.is_empty() {
@@ -74,35 +314,347 @@ impl App {
                 let mut map_dirs = map_dirs;
                 map_dirs.extend(dirs.into_iter().map(|(a, b)| (b, a)));
 
-                let output_bytes = pack(&wasm_bytes, map_dirs)?;
+                let mounts = map_dirs
+                    .into_iter()
+                    .map(|(guest, dir)| config::MountConfig {
+                        guest,
+                        dir: Some(dir),
+                        ..Default::default()
+                    })
+                    .chain(tars.into_iter().map(|(tar, guest)| config::MountConfig {
+                        guest,
+                        tar: Some(tar),
+                        ..Default::default()
+                    }))
+                    .chain(
+                        embedded_tars
+                            .into_iter()
+                            .map(|(tar, guest)| config::MountConfig {
+                                guest,
+                                embed_tar: Some(tar),
+                                ..Default::default()
+                            }),
+                    )
+                    .chain(zips.into_iter().map(|(zip, guest)| config::MountConfig {
+                        guest,
+                        zip: Some(zip),
+                        ..Default::default()
+                    }))
+                    .collect();
+                let args = config::PackConfig {
+                    output,
+                    strict: flag(strict, no_strict),
+                    mode: if replace {
+                        PackMode::Replace
+                    } else if merge {
+                        PackMode::Merge
+                    } else {
+                        PackMode::Append
+                    },
+                    dedup: flag(dedup, no_dedup),
+                    dev: flag(dev, no_dev),
+                    mtime,
+                    init_func,
+                    excludes,
+                    includes,
+                    mounts,
+                    files: files
+                        .into_iter()
+                        .map(|(host, guest)| config::FileConfig { host, guest })
+                        .collect(),
+                    ..Default::default()
+                };
+                let mut config = match config {
+                    Some(path) => config::PackConfig::load(&path)?,
+                    None => config::PackConfig::default(),
+                };
+                config.extend(args);
+                if std::env::var("WASI_VFS_VERBOSE").is_ok_and(|v| v == "1") {
+                    eprint!("effective configuration:\n{}", config.to_toml()?);
+                }
+
+                let output = config.output.take().ok_or_else(|| {
+                    anyhow::anyhow!("the output path is given neither by -o nor the config")
+                })?;
+                let (map_dirs, options) = config.into_pack_args();
+                let output_bytes = pack_with_options(&wasm_bytes, map_dirs, &options)?;
                 std::fs::write(output, output_bytes)?;
             }
+            App::RmMount {
+                input,
+                mounts: guest_dirs,
+                output,
+            } => {
+                let wasm_bytes = std::fs::read(&input)?;
+                let mounts = dump_fs(&wasm_bytes)?;
+                for guest_dir in &guest_dirs {
+                    let normalize = |dir: &str| dir.trim_end_matches('/').to_string();
+                    if !mounts
+                        .iter()
+                        .any(|mount| normalize(&mount.guest_dir) == normalize(guest_dir))
+                    {
+                        anyhow::bail!("'{}' is not an embedded directory", guest_dir);
+                    }
+                }
+                let options = PackOptions {
+                    remove_mounts: guest_dirs,
+                    ..Default::default()
+                };
+                let output_bytes = pack_with_options(&wasm_bytes, vec![], &options)?;
+                std::fs::write(output, output_bytes)?;
+            }
+            App::Ls {
+                input,
+                guest_path,
+                recursive,
+                long,
+            } => {
+                let mounts = dump_fs(&std::fs::read(&input)?)?;
+                let options = ls::LsOptions { recursive, long };
+                ls::ls(
+                    &mut std::io::stdout().lock(),
+                    &mounts,
+                    guest_path.as_deref(),
+                    options,
+                )?;
+            }
+            App::Inspect { input, json } => {
+                let manifest = read_manifest(&std::fs::read(&input)?)?.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "'{}' has no wasi-vfs manifest; it's not packed by wasi-vfs or packed by an older version",
+                        input.display()
+                    )
+                })?;
+                let mut out = std::io::stdout().lock();
+                if json {
+                    manifest::write_json(&mut out, &manifest)?;
+                } else {
+                    manifest::write_summary(&mut out, &manifest)?;
+                }
+            }
+            App::Cat { input, guest_path } => {
+                let mounts = dump_fs(&std::fs::read(&input)?)?;
+                ls::cat(&mut std::io::stdout().lock(), &mounts, &guest_path)?;
+            }
+            App::Diff { old, new, unified } => {
+                let old = dump_fs(&std::fs::read(&old)?)?;
+                let new = dump_fs(&std::fs::read(&new)?)?;
+                diff::diff(&mut std::io::stdout().lock(), &old, &new, unified)?;
+            }
+            App::Extract {
+                input,
+                output,
+                mounts: selected,
+            } => {
+                let mounts = dump_fs(&std::fs::read(&input)?)?;
+                extract::extract(&mounts, &output, &selected)?;
+            }
+            App::Doctor { input } => {
+                let findings = doctor::diagnose(&std::fs::read(&input)?)?;
+                let errors = doctor::write_report(&mut std::io::stdout().lock(), &findings)?;
+                if errors > 0 {
+                    anyhow::bail!("found {} problem(s) in '{}'", errors, input.display());
+                }
+            }
+            App::Tree { input, guest_path } => {
+                let mounts = dump_fs(&std::fs::read(&input)?)?;
+                ls::tree(
+                    &mut std::io::stdout().lock(),
+                    &mounts,
+                    guest_path.as_deref(),
+                )?;
+            }
         }
         Ok(())
     }
 }
 
-pub fn pack(wasm_bytes: &[u8], map_dirs: Vec<(String, PathBuf)>) -> Result<Vec<u8>> {
-    unsafe {
-        std::env::set_var("__WASI_VFS_PACKING", "1");
+/// Options to control how host directories are packed.
+#[derive(Debug, Clone, Default)]
+pub struct PackOptions {
+    /// Tar archives to package, paired with their guest directories. The
+    /// archive path `-` means stdin.
+    pub tars: Vec<(PathBuf, String)>,
+    /// Uncompressed tar archives to embed as is, paired with their guest
+    /// directories. Exclude and include patterns are not applied to them.
+    pub embedded_tars: Vec<(PathBuf, String)>,
+    /// Zip archives to package, paired with their guest directories. The
+    /// archive path `-` means stdin.
+    pub zips: Vec<(PathBuf, String)>,
+    /// Host files to package, paired with their guest paths.
+    pub files: Vec<(PathBuf, String)>,
+    /// In-memory files to package, keyed by paths relative to their guest
+    /// directories.
+    pub trees: Vec<(String, BTreeMap<String, Vec<u8>>)>,
+    /// Fail with [`PackError`] instead of skipping entries which can't be packed.
+    pub strict: bool,
+    /// Glob patterns of host entries to exclude.
+    pub excludes: Vec<String>,
+    /// Glob patterns of files to include. Everything is included if empty.
+    pub includes: Vec<String>,
+    /// How to treat directories embedded by previous packing at the same
+    /// guest directories.
+    pub mode: PackMode,
+    /// Guest directories embedded by previous packing to remove.
+    pub remove_mounts: Vec<String>,
+    /// Embed files with identical content and timestamp once by packing
+    /// them as hard links.
+    pub dedup: bool,
+    /// Mount built-in device nodes at `/dev` at runtime.
+    pub dev: bool,
+    /// Timestamps given to packed entries.
+    pub mtime: MtimePolicy,
+    /// Print packed entries from the guest. `WASI_VFS_VERBOSE` is forwarded
+    /// to the guest if not set.
+    pub verbose: bool,
+    /// Exported function to run after packing in the same snapshot. The
+    /// packed file system is active while it runs, and host directories are
+    /// not visible.
+    pub init_func: Option<String>,
+}
+
+/// How packed directories treat directories embedded by previous packing at
+/// the same guest directories.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackMode {
+    /// Add new directories shadowing the previous ones.
+    #[default]
+    Append,
+    /// Remove the previous directories.
+    Replace,
+    /// Add entries into the previous directory, overwriting existing paths.
+    Merge,
+}
+
+/// Timestamps given to packed entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MtimePolicy {
+    /// Keep timestamps of host entries and archive members.
+    #[default]
+    Preserve,
+    /// Use the given seconds since the Unix epoch for every entry, e.g. for
+    /// reproducible builds.
+    Fixed(u64),
+}
+
+impl std::str::FromStr for MtimePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "preserve" => Ok(MtimePolicy::Preserve),
+            "zero" => Ok(MtimePolicy::Fixed(0)),
+            _ => s.parse().map(MtimePolicy::Fixed).map_err(|_| {
+                anyhow::anyhow!(
+                    "invalid mtime policy '{}', expected preserve, zero or seconds",
+                    s
+                )
+            }),
+        }
     }
+}
 
-    // Use tokio runtime for async wizer
-    let runtime = tokio::runtime::Runtime::new()?;
-    runtime.block_on(async { pack_async(wasm_bytes, map_dirs).await })
+pub fn pack(wasm_bytes: &[u8], map_dirs: Vec<(String, PathBuf)>) -> Result<Vec<u8>> {
+    pack_with_options(wasm_bytes, map_dirs, &PackOptions::default())
 }
 
-async fn pack_async(wasm_bytes: &[u8], map_dirs: Vec<(String, PathBuf)>) -> Result<Vec<u8>> {
+/// Package host directories into the given Wasm module.
+///
+/// Failures reported by the in-guest scanner are returned as [`PackError`].
+/// See [`Packer`] for more control over packing.
+pub fn pack_with_options(
+    wasm_bytes: &[u8],
+    map_dirs: Vec<(String, PathBuf)>,
+    options: &PackOptions,
+) -> Result<Vec<u8>> {
+    let packer = map_dirs
+        .into_iter()
+        .fold(Packer::new(), |packer, (guest_dir, host_dir)| {
+            packer.dir(host_dir, guest_dir)
+        })
+        .options(options.clone());
+    Ok(packer.pack(wasm_bytes)?.wasm)
+}
+
+/// The guest path of the control directory, which is used by the guest
+/// scanner to report errors. It's excluded from the packed file system.
+const CONTROL_DIR_GUEST_PATH: &str = "/.wasi-vfs-control";
+/// The environment variable telling the guest the control directory path.
+const CONTROL_DIR_ENV: &str = "__WASI_VFS_PACK_CONTROL";
+
+async fn pack_async(
+    wasm_bytes: &[u8],
+    map_dirs: &[(String, PathBuf)],
+    options: &PackOptions,
+    engine: Option<&wasmtime::Engine>,
+) -> Result<Packed> {
+    let control_dir = tempfile::tempdir()?;
+
     // Configure WASI
     let mut wasi = wasmtime_wasi::WasiCtxBuilder::new();
     wasi.inherit_stdio();
     wasi.env("__WASI_VFS_PACKING", "1");
+    wasi.env(CONTROL_DIR_ENV, CONTROL_DIR_GUEST_PATH);
+    if options.strict {
+        wasi.env("__WASI_VFS_PACK_STRICT", "1");
+    }
+    if options.dedup {
+        wasi.env("__WASI_VFS_PACK_DEDUP", "1");
+    }
+    if options.dev {
+        wasi.env("__WASI_VFS_PACK_DEV", "1");
+    }
+    if let Some(init_func) = &options.init_func {
+        if !has_export(wasm_bytes, init_func) {
+            anyhow::bail!("the module doesn't export '{}' to initialize", init_func);
+        }
+        wasi.env("__WASI_VFS_PACK_ACTIVATE", "1");
+    }
+    if let MtimePolicy::Fixed(seconds) = options.mtime {
+        let nanos = seconds
+            .checked_mul(1_000_000_000)
+            .ok_or_else(|| anyhow::anyhow!("mtime {} is out of range", seconds))?;
+        wasi.env("__WASI_VFS_PACK_MTIME", nanos.to_string());
+    }
+    match options.mode {
+        PackMode::Append => {}
+        PackMode::Replace => {
+            wasi.env("__WASI_VFS_PACK_MODE", "replace");
+        }
+        PackMode::Merge => {
+            wasi.env("__WASI_VFS_PACK_MODE", "merge");
+        }
+    }
+    // Lists are forwarded to the guest scanner as newline separated values
+    for (key, values) in [
+        ("__WASI_VFS_PACK_EXCLUDE", &options.excludes),
+        ("__WASI_VFS_PACK_INCLUDE", &options.includes),
+        ("__WASI_VFS_PACK_REMOVE", &options.remove_mounts),
+    ] {
+        if let Some(value) = values.iter().find(|v| v.contains('\n')) {
+            anyhow::bail!("{:?} must not contain a newline", value);
+        }
+        if !values.is_empty() {
+            wasi.env(key, values.join("\n"));
+        }
+    }
+
+    write_feed(&control_dir.path().join("feed"), map_dirs, options)?;
 
     let verbose_env_key = "WASI_VFS_VERBOSE";
-    if let Ok(verbose) = std::env::var(verbose_env_key) {
+    if options.verbose {
+        wasi.env(verbose_env_key, "1");
+    } else if let Ok(verbose) = std::env::var(verbose_env_key) {
         wasi.env(verbose_env_key, &verbose);
     }
 
+    wasi.preopened_dir(
+        control_dir.path(),
+        CONTROL_DIR_GUEST_PATH,
+        wasmtime_wasi::DirPerms::all(),
+        wasmtime_wasi::FilePerms::all(),
+    )?;
     for (guest_dir, host_dir) in map_dirs {
         wasi.preopened_dir(
             host_dir,
@@ -112,18 +664,30 @@ async fn pack_async(wasm_bytes: &[u8], map_dirs: Vec<(String, PathBuf)>) -> Resu
         )?;
     }
 
-    // Configure Wasmtime
-    let mut config = wasmtime::Config::new();
-    config.wasm_bulk_memory(true);
-    config.async_support(true);
-
-    let engine = wasmtime::Engine::new(&config)?;
+    // Configure Wasmtime unless the caller gave an engine
+    let engine = match engine {
+        Some(engine) => engine.clone(),
+        None => {
+            let mut config = wasmtime::Config::new();
+            config.wasm_bulk_memory(true);
+            config.async_support(true);
+            wasmtime::Engine::new(&config)?
+        }
+    };
     let mut store = wasmtime::Store::new(&engine, wasi.build_p1());
 
-    // Configure Wizer
+    // Configure Wizer. With an application init function, Wizer runs it as
+    // the init function after the file system is packed on instantiation.
     let mut wizer = wasmtime_wizer::Wizer::new();
-    wizer.init_func("wasi_vfs_pack_fs");
-    wizer.keep_init_func(true);
+    match &options.init_func {
+        Some(init_func) => {
+            wizer.init_func(init_func);
+        }
+        None => {
+            wizer.init_func("wasi_vfs_pack_fs");
+            wizer.keep_init_func(true);
+        }
+    }
 
     // For reactor modules, wasi-vfs needs some special initialization process.
     // 1st pack: Wizer removes `_initialize` and renames `__wasi_vfs_rt_init` to `_initialize`.
@@ -142,15 +706,120 @@ async fn pack_async(wasm_bytes: &[u8], map_dirs: Vec<(String, PathBuf)>) -> Resu
             let mut linker = wasmtime::Linker::new(module.engine());
             wasmtime_wasi::p1::add_to_linker_async(&mut linker, |x| x)?;
             linker.define_unknown_imports_as_traps(module)?;
-            linker.instantiate_async(store, module).await
+            let instance = linker.instantiate_async(&mut *store, module).await?;
+            if options.init_func.is_some() {
+                let pack_fs = instance.get_typed_func::<(), ()>(&mut *store, "wasi_vfs_pack_fs")?;
+                pack_fs.call_async(&mut *store, ()).await?;
+            }
+            Ok(instance)
         })
-        .await?;
+        .await;
+    let output_bytes = match output_bytes {
+        Ok(output_bytes) => output_bytes,
+        Err(err) => {
+            // Prefer the structured error reported by the guest scanner
+            let report = std::fs::read_to_string(control_dir.path().join("error"));
+            if let Some(pack_error) = report
+                .ok()
+                .and_then(|report| PackError::from_report(&report, map_dirs))
+            {
+                return Err(pack_error.into());
+            }
+            return Err(err);
+        }
+    };
 
     let output_bytes = copy_export_entry(&output_bytes, "_initialize", "__wasi_vfs_rt_init")?;
-    Ok(output_bytes)
+    attach_manifest(&engine, wasm_bytes, output_bytes).await
+}
+
+/// Replace the manifest section of the packed module with one describing the
+/// current embedded file system, which is dumped on the packing engine.
+async fn attach_manifest(
+    engine: &wasmtime::Engine,
+    input_bytes: &[u8],
+    output_bytes: Vec<u8>,
+) -> Result<Packed> {
+    let mut output_bytes = manifest::strip_manifest(&output_bytes)?;
+    // Modules linked with an older libwasi_vfs.a can't dump their trees
+    if !has_export(&output_bytes, "wasi_vfs_dump_fs") {
+        return Ok(Packed {
+            wasm: output_bytes,
+            manifest: None,
+        });
+    }
+    let unpacked = match read_manifest(input_bytes)? {
+        Some(manifest) => manifest.module_size.unpacked,
+        None => input_bytes.len() as u64,
+    };
+    let module_size = manifest::ModuleSize {
+        unpacked,
+        packed: output_bytes.len() as u64,
+    };
+    let mounts = inspect::dump_fs_async(engine, &output_bytes).await?;
+    let manifest = Manifest::new(&mounts, module_size);
+    manifest::append_manifest(&mut output_bytes, &manifest)?;
+    Ok(Packed {
+        wasm: output_bytes,
+        manifest: Some(manifest),
+    })
+}
+
+/// Write trees which are not host directories into the feed file read by the
+/// guest packer.
+fn write_feed(
+    path: &std::path::Path,
+    map_dirs: &[(String, PathBuf)],
+    options: &PackOptions,
+) -> Result<()> {
+    if options.tars.is_empty()
+        && options.embedded_tars.is_empty()
+        && options.zips.is_empty()
+        && options.files.is_empty()
+        && options.trees.is_empty()
+    {
+        return Ok(());
+    }
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut feed = stream::Writer::new(file)?;
+    for (archive, guest_dir) in &options.tars {
+        feed.mount(guest_dir)?;
+        archive::feed_tar(&mut feed, archive, options.strict)?;
+    }
+    for (archive, guest_dir) in &options.embedded_tars {
+        feed.mount(guest_dir)?;
+        feed.archive("", &archive::read_tar(archive)?)?;
+    }
+    for (archive, guest_dir) in &options.zips {
+        feed.mount(guest_dir)?;
+        archive::feed_zip(&mut feed, archive, options.strict)?;
+    }
+    for (guest_dir, files) in &options.trees {
+        feed.mount(guest_dir)?;
+        file::feed_tree(&mut feed, files)?;
+    }
+    // Files join directories packed in this run if they contain them
+    let mounts = map_dirs
+        .iter()
+        .map(|(guest_dir, _)| guest_dir)
+        .chain(options.tars.iter().map(|(_, guest_dir)| guest_dir))
+        .chain(options.embedded_tars.iter().map(|(_, guest_dir)| guest_dir))
+        .chain(options.zips.iter().map(|(_, guest_dir)| guest_dir))
+        .chain(options.trees.iter().map(|(guest_dir, _)| guest_dir))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    for (host_file, guest_path) in &options.files {
+        file::feed_file(&mut feed, host_file, guest_path, &mounts)?;
+    }
+    feed.finish()?;
+    Ok(())
 }
 
 fn is_wasi_reactor(bytes: &[u8]) -> bool {
+    has_export(bytes, "_initialize")
+}
+
+fn has_export(bytes: &[u8], name: &str) -> bool {
     let parser = wasmparser::Parser::new(0);
     for payload in parser.parse_all(bytes) {
         let payload = match payload {
@@ -164,7 +833,7 @@ fn is_wasi_reactor(bytes: &[u8]) -> bool {
                         Ok(entry) => entry,
                         Err(_) => continue,
                     };
-                    if entry.name == "_initialize" {
+                    if entry.name == name {
                         return true;
                     }
                 }
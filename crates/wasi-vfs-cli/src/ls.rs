@@ -0,0 +1,231 @@
+//! `ls`, `tree` and `cat` views of the embedded file system.
+
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::inspect::{EmbeddedEntry, EmbeddedMount, EntryKind, join_guest_path, locate};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LsOptions {
+    pub(crate) recursive: bool,
+    pub(crate) long: bool,
+}
+
+/// List entries at `guest_path`, or the embedded mounts if not given.
+pub(crate) fn ls(
+    out: &mut impl Write,
+    mounts: &[EmbeddedMount],
+    guest_path: Option<&str>,
+    options: LsOptions,
+) -> Result<()> {
+    let guest_path = match guest_path {
+        Some(guest_path) => guest_path,
+        None if options.recursive => {
+            for mount in mounts {
+                list_dir(out, mount, "", options)?;
+            }
+            return Ok(());
+        }
+        None => {
+            for mount in mounts {
+                if options.long {
+                    writeln!(out, "d {:>3} {:>10} {}", 1, 0, mount.guest_dir)?;
+                } else {
+                    writeln!(out, "{}", mount.guest_dir)?;
+                }
+            }
+            return Ok(());
+        }
+    };
+    let (mount, relpath) = locate(mounts, guest_path)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not in the embedded file system", guest_path))?;
+    if relpath.is_empty() {
+        return list_dir(out, mount, "", options);
+    }
+    let entry = mount
+        .find(&relpath)
+        .ok_or_else(|| anyhow::anyhow!("no such file or directory: '{}'", guest_path))?;
+    if mount.link_target(entry).kind == EntryKind::Dir {
+        list_dir(out, mount, &entry.path, options)
+    } else {
+        write_entry(out, mount, entry, guest_path, options)
+    }
+}
+
+/// List entries in a directory. With `recursive`, entries in subdirectories
+/// are listed with their full guest paths.
+fn list_dir(
+    out: &mut impl Write,
+    mount: &EmbeddedMount,
+    dir: &str,
+    options: LsOptions,
+) -> Result<()> {
+    for entry in mount.children(dir) {
+        if options.recursive {
+            let guest_path = join_guest_path(&mount.guest_dir, &entry.path);
+            write_entry(out, mount, entry, &guest_path, options)?;
+            if entry.kind == EntryKind::Dir {
+                list_dir(out, mount, &entry.path, options)?;
+            }
+        } else {
+            write_entry(out, mount, entry, entry.name(), options)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_entry(
+    out: &mut impl Write,
+    mount: &EmbeddedMount,
+    entry: &EmbeddedEntry,
+    display_path: &str,
+    options: LsOptions,
+) -> Result<()> {
+    if !options.long {
+        writeln!(out, "{}", display_path)?;
+        return Ok(());
+    }
+    let target = mount.link_target(entry);
+    let (kind, size) = match &target.kind {
+        EntryKind::Dir => ('d', 0),
+        EntryKind::File(content) => ('-', content.len()),
+        EntryKind::Symlink(link) => ('l', link.len()),
+        // dangling hard link
+        EntryKind::HardLink(_) => ('?', 0),
+    };
+    write!(
+        out,
+        "{} {:>3} {:>10} {}",
+        kind,
+        mount.nlink(entry),
+        size,
+        display_path
+    )?;
+    if let EntryKind::Symlink(link) = &target.kind {
+        write!(out, " -> {}", link)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Draw entries under `guest_path`, or all mounts if not given, as trees.
+pub(crate) fn tree(
+    out: &mut impl Write,
+    mounts: &[EmbeddedMount],
+    guest_path: Option<&str>,
+) -> Result<()> {
+    let guest_path = match guest_path {
+        Some(guest_path) => guest_path,
+        None => {
+            for mount in mounts {
+                writeln!(out, "{}", mount.guest_dir)?;
+                draw_dir(out, mount, "", "")?;
+            }
+            return Ok(());
+        }
+    };
+    let (mount, relpath) = locate(mounts, guest_path)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not in the embedded file system", guest_path))?;
+    if !relpath.is_empty() && mount.find(&relpath).is_none() {
+        anyhow::bail!("no such file or directory: '{}'", guest_path);
+    }
+    writeln!(out, "{}", guest_path)?;
+    draw_dir(out, mount, &relpath, "")
+}
+
+fn draw_dir(out: &mut impl Write, mount: &EmbeddedMount, dir: &str, indent: &str) -> Result<()> {
+    let children = mount.children(dir).collect::<Vec<_>>();
+    for (i, entry) in children.iter().enumerate() {
+        let is_last = i + 1 == children.len();
+        let branch = if is_last { "└── " } else { "├── " };
+        write!(out, "{}{}{}", indent, branch, entry.name())?;
+        if let EntryKind::Symlink(link) = &entry.kind {
+            write!(out, " -> {}", link)?;
+        }
+        writeln!(out)?;
+        if entry.kind == EntryKind::Dir {
+            let indent = format!("{}{}", indent, if is_last { "    " } else { "│   " });
+            draw_dir(out, mount, &entry.path, &indent)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the content of the file at `guest_path`, following symbolic links.
+pub(crate) fn cat(out: &mut impl Write, mounts: &[EmbeddedMount], guest_path: &str) -> Result<()> {
+    let (mount, relpath) = locate(mounts, guest_path)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not in the embedded file system", guest_path))?;
+    let entry = match mount.resolve(&relpath) {
+        Some(entry) => entry,
+        // The mount root itself has no entry
+        None if relpath.is_empty() => anyhow::bail!("'{}' is a directory", guest_path),
+        None => anyhow::bail!("no such file or directory: '{}'", guest_path),
+    };
+    match &entry.kind {
+        EntryKind::File(content) => out.write_all(content)?,
+        EntryKind::Dir => anyhow::bail!("'{}' is a directory", guest_path),
+        _ => anyhow::bail!("'{}' is not a regular file", guest_path),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::tests::sample_mounts;
+
+    fn render(f: impl FnOnce(&mut Vec<u8>) -> Result<()>) -> String {
+        let mut out = vec![];
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_ls() {
+        let mounts = sample_mounts();
+        let output = render(|out| ls(out, &mounts, None, LsOptions::default()));
+        assert_eq!(output, "/mnt\n");
+        let output = render(|out| ls(out, &mounts, Some("/mnt/dir"), LsOptions::default()));
+        assert_eq!(output, "hello.txt\nlink\n");
+        let options = LsOptions {
+            recursive: true,
+            long: true,
+        };
+        let output = render(|out| ls(out, &mounts, Some("/mnt"), options));
+        assert_eq!(
+            output,
+            "d   1          0 /mnt/dir\n\
+             -   2          6 /mnt/dir/hello.txt\n\
+             l   1          9 /mnt/dir/link -> hello.txt\n\
+             -   2          6 /mnt/hard\n"
+        );
+    }
+
+    #[test]
+    fn test_cat() {
+        let mounts = sample_mounts();
+        let output = render(|out| cat(out, &mounts, "/mnt/dir/link"));
+        assert_eq!(output, "hello\n");
+        let output = render(|out| cat(out, &mounts, "/mnt/hard"));
+        assert_eq!(output, "hello\n");
+        let error = cat(&mut vec![], &mounts, "/mnt/dir").unwrap_err();
+        assert_eq!(error.to_string(), "'/mnt/dir' is a directory");
+        let error = cat(&mut vec![], &mounts, "/mnt/missing").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no such file or directory: '/mnt/missing'"
+        );
+        assert!(cat(&mut vec![], &mounts, "/mnt").is_err());
+    }
+
+    #[test]
+    fn test_tree() {
+        let mounts = sample_mounts();
+        let output = render(|out| tree(out, &mounts, None));
+        assert_eq!(
+            output,
+            "/mnt\n├── dir\n│   ├── hello.txt\n│   └── link -> hello.txt\n└── hard\n"
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Manifest of the embedded file system stored in a custom section of packed
+//! Wasm modules.
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::inspect::{EmbeddedMount, EntryKind, join_guest_path};
+
+/// The name of the custom section holding the manifest in JSON.
+pub(crate) const SECTION_NAME: &str = "wasi-vfs-manifest";
+
+/// Description of the file system embedded in a packed Wasm module.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The version of wasi-vfs CLI which packed the module.
+    pub version: String,
+    pub module_size: ModuleSize,
+    /// Guest directories of the embedded mounts.
+    pub mounts: Vec<String>,
+    /// Every embedded path except the mount roots, parents first.
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Sizes of the module in bytes, excluding the manifest section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleSize {
+    /// Size before the first packing.
+    pub unpacked: u64,
+    pub packed: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Absolute guest path.
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: ManifestEntryKind,
+    /// Content size for files and target length for symbolic links.
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content of files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Target of symbolic links, or the absolute guest path of hard links.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestEntryKind {
+    Dir,
+    File,
+    Symlink,
+    /// Another path of a file listed before. Its size and hash are of the
+    /// linked file.
+    Hardlink,
+}
+
+/// Aggregated sizes of a manifest in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Totals {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub hardlinks: u64,
+    /// Bytes of file contents. Hard linked contents are counted once.
+    pub content_bytes: u64,
+    /// Bytes added to the module by packing.
+    pub embedded_bytes: u64,
+    /// Bytes added by packing other than file contents, e.g. tree metadata.
+    pub overhead_bytes: u64,
+}
+
+impl Manifest {
+    pub(crate) fn new(mounts: &[EmbeddedMount], module_size: ModuleSize) -> Self {
+        let mut entries = vec![];
+        for mount in mounts {
+            for entry in &mount.entries {
+                let target = mount.link_target(entry);
+                let (kind, size, sha256, link) = match (&entry.kind, &target.kind) {
+                    (EntryKind::Dir, _) => (ManifestEntryKind::Dir, 0, None, None),
+                    (EntryKind::File(content), _) => (
+                        ManifestEntryKind::File,
+                        content.len(),
+                        Some(sha256(content)),
+                        None,
+                    ),
+                    (EntryKind::Symlink(link), _) => (
+                        ManifestEntryKind::Symlink,
+                        link.len(),
+                        None,
+                        Some(link.clone()),
+                    ),
+                    (EntryKind::HardLink(link), EntryKind::File(content)) => (
+                        ManifestEntryKind::Hardlink,
+                        content.len(),
+                        Some(sha256(content)),
+                        Some(join_guest_path(&mount.guest_dir, link)),
+                    ),
+                    (EntryKind::HardLink(link), _) => (
+                        ManifestEntryKind::Hardlink,
+                        0,
+                        None,
+                        Some(join_guest_path(&mount.guest_dir, link)),
+                    ),
+                };
+                entries.push(ManifestEntry {
+                    path: join_guest_path(&mount.guest_dir, &entry.path),
+                    kind,
+                    size: size as u64,
+                    sha256,
+                    target: link,
+                });
+            }
+        }
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            module_size,
+            mounts: mounts.iter().map(|mount| mount.guest_dir.clone()).collect(),
+            entries,
+        }
+    }
+
+    pub fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for entry in &self.entries {
+            match entry.kind {
+                ManifestEntryKind::Dir => totals.dirs += 1,
+                ManifestEntryKind::File => {
+                    totals.files += 1;
+                    totals.content_bytes += entry.size;
+                }
+                ManifestEntryKind::Symlink => totals.symlinks += 1,
+                ManifestEntryKind::Hardlink => totals.hardlinks += 1,
+            }
+        }
+        totals.embedded_bytes = self
+            .module_size
+            .packed
+            .saturating_sub(self.module_size.unpacked);
+        totals.overhead_bytes = totals.embedded_bytes.saturating_sub(totals.content_bytes);
+        totals
+    }
+}
+
+/// Write the manifest and its totals as pretty-printed JSON.
+pub(crate) fn write_json(out: &mut impl Write, manifest: &Manifest) -> Result<()> {
+    #[derive(Serialize)]
+    struct Output<'a> {
+        manifest: &'a Manifest,
+        totals: Totals,
+    }
+    let output = Output {
+        manifest,
+        totals: manifest.totals(),
+    };
+    serde_json::to_writer_pretty(&mut *out, &output)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write a human readable summary of the manifest.
+pub(crate) fn write_summary(out: &mut impl Write, manifest: &Manifest) -> Result<()> {
+    let totals = manifest.totals();
+    writeln!(out, "packed by wasi-vfs {}", manifest.version)?;
+    writeln!(out, "mounts: {}", manifest.mounts.join(", "))?;
+    writeln!(
+        out,
+        "entries: {} files, {} directories, {} symbolic links, {} hard links",
+        totals.files, totals.dirs, totals.symlinks, totals.hardlinks
+    )?;
+    writeln!(
+        out,
+        "module size: {} bytes ({} bytes before packing)",
+        manifest.module_size.packed, manifest.module_size.unpacked
+    )?;
+    writeln!(
+        out,
+        "embedded: {} bytes ({} bytes of content, {} bytes of overhead)",
+        totals.embedded_bytes, totals.content_bytes, totals.overhead_bytes
+    )?;
+    Ok(())
+}
+
+fn sha256(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Read the manifest from a packed Wasm module if it has one.
+pub fn read_manifest(wasm_bytes: &[u8]) -> Result<Option<Manifest>> {
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        if let wasmparser::Payload::CustomSection(section) = payload?
+            && section.name() == SECTION_NAME
+        {
+            let manifest = serde_json::from_slice(section.data())
+                .context("malformed wasi-vfs manifest section")?;
+            return Ok(Some(manifest));
+        }
+    }
+    Ok(None)
+}
+
+/// Remove the manifest section from the module if exists.
+pub(crate) fn strip_manifest(wasm_bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut module = wasm_encoder::Module::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm_bytes) {
+        let payload = payload?;
+        if let wasmparser::Payload::CustomSection(section) = &payload
+            && section.name() == SECTION_NAME
+        {
+            continue;
+        }
+        if let Some((id, range)) = payload.as_section() {
+            module.section(&wasm_encoder::RawSection {
+                id,
+                data: &wasm_bytes[range.start..range.end],
+            });
+        }
+    }
+    Ok(module.finish())
+}
+
+/// Append the manifest section to a module without one.
+pub(crate) fn append_manifest(wasm_bytes: &mut Vec<u8>, manifest: &Manifest) -> Result<()> {
+    let data = serde_json::to_vec(manifest)?;
+    let section = wasm_encoder::CustomSection {
+        name: SECTION_NAME.into(),
+        data: data.into(),
+    };
+    wasm_encoder::Section::append_to(&section, wasm_bytes);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect::tests::sample_mounts;
+
+    #[test]
+    fn test_manifest() {
+        let module_size = ModuleSize {
+            unpacked: 100,
+            packed: 150,
+        };
+        let manifest = Manifest::new(&sample_mounts(), module_size);
+        assert_eq!(manifest.mounts, ["/mnt"]);
+        let paths = manifest.entries.iter().map(|e| e.path.as_str());
+        assert!(paths.eq([
+            "/mnt/dir",
+            "/mnt/dir/hello.txt",
+            "/mnt/dir/link",
+            "/mnt/hard"
+        ]));
+        let hello = &manifest.entries[1];
+        assert_eq!(
+            hello.sha256.as_deref(),
+            Some("5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03")
+        );
+        assert_eq!(
+            manifest.entries[3].target.as_deref(),
+            Some("/mnt/dir/hello.txt")
+        );
+
+        let totals = manifest.totals();
+        assert_eq!(
+            (totals.files, totals.dirs, totals.symlinks, totals.hardlinks),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(totals.content_bytes, 6);
+        assert_eq!(totals.overhead_bytes, 44);
+
+        let mut module = wasm_encoder::Module::new().finish();
+        append_manifest(&mut module, &manifest).unwrap();
+        assert_eq!(read_manifest(&module).unwrap(), Some(manifest));
+        let stripped = strip_manifest(&module).unwrap();
+        assert_eq!(read_manifest(&stripped).unwrap(), None);
+    }
+}
@@ -44,17 +44,15 @@
 /// )
 /// ```
 ///
-pub fn link(_main_bytes: &[u8]) {
-    unimplemented!(
-        r#"
-    This feature requires module-linking and interface-type proposals, and interface-type support of WASI.
-    Current WASI interface (wasi_snapshot_preview1) requires the callee to access the caller's linear memory,
-    so VFS instance has to import it while exporting WASI APIs. Also, calling real WASI API from VFS instance
-    requires the VFS instance to pass the pointer of the main instance memory. In theory, a virtualized WASI API
-    in VFS only calls corresponding real WASI API, so it's possible to implement this feature without interface-type
-    by annotating which parameters and results are pull/push address, and who has the ownership of the pointer.
-    However, it's hard to maintain such annotations, so I decided to wait the interface-type proposal and WASI adaptation
-    of shared-nothing architecture.
-    "#
+pub fn link(_main_bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    // Composing as a component doesn't help either: core modules in a component
+    // can't import `wasi_snapshot_preview1` from the host, so the VFS module
+    // would have to target WASI Preview 2, which is what WASI-Virt does.
+    anyhow::bail!(
+        "link-module is not supported yet.\n\
+         wasi_snapshot_preview1 passes pointers into the caller's linear memory, so a separate VFS \
+         module has to access the main module's memory while keeping its own heap and stack out of \
+         it, and libwasi_vfs can't be built in such a form yet. Link libwasi_vfs.a into the \
+         application instead, or use WASI-Virt for WASI Preview 2 components."
     )
 }
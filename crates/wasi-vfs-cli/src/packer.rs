@@ -0,0 +1,183 @@
+//! Builder API to package host directories and in-memory trees.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::{Manifest, MtimePolicy, PackMode, PackOptions};
+
+/// Builder of a packing run.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let wasm_bytes = std::fs::read("app.wasm")?;
+/// let packed = wasi_vfs_cli::Packer::new()
+///     .dir("./lib", "/lib")
+///     .tree("/etc", [("app/config.toml", "debug = false\n")])
+///     .exclude("*.pyc")
+///     .pack(&wasm_bytes)?;
+/// std::fs::write("app.packed.wasm", &packed.wasm)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Packer {
+    map_dirs: Vec<(String, PathBuf)>,
+    options: PackOptions,
+    engine: Option<wasmtime::Engine>,
+}
+
+/// The result of a packing run.
+#[derive(Debug, Clone)]
+pub struct Packed {
+    /// The packed Wasm module.
+    pub wasm: Vec<u8>,
+    /// Description of the whole embedded file system, including trees packed
+    /// by previous runs. `None` if the module is linked with a wasi-vfs
+    /// version which can't describe its file system.
+    pub manifest: Option<Manifest>,
+}
+
+impl Packer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Package a host directory at a guest directory.
+    pub fn dir(mut self, host_dir: impl Into<PathBuf>, guest_dir: impl Into<String>) -> Self {
+        self.map_dirs.push((guest_dir.into(), host_dir.into()));
+        self
+    }
+
+    /// Package files held in memory at a guest directory. Keys are paths
+    /// relative to the guest directory, and missing parent directories are
+    /// created.
+    pub fn tree<P, C>(
+        mut self,
+        guest_dir: impl Into<String>,
+        files: impl IntoIterator<Item = (P, C)>,
+    ) -> Self
+    where
+        P: Into<String>,
+        C: Into<Vec<u8>>,
+    {
+        let files = files
+            .into_iter()
+            .map(|(path, content)| (path.into(), content.into()))
+            .collect::<BTreeMap<_, _>>();
+        self.options.trees.push((guest_dir.into(), files));
+        self
+    }
+
+    /// Package a host file at a guest path.
+    pub fn file(mut self, host_file: impl Into<PathBuf>, guest_path: impl Into<String>) -> Self {
+        self.options
+            .files
+            .push((host_file.into(), guest_path.into()));
+        self
+    }
+
+    /// Package a tar archive, optionally gzip compressed, at a guest directory.
+    pub fn tar(mut self, archive: impl Into<PathBuf>, guest_dir: impl Into<String>) -> Self {
+        self.options.tars.push((archive.into(), guest_dir.into()));
+        self
+    }
+
+    /// Embed an uncompressed tar archive as is at a guest directory.
+    pub fn embed_tar(mut self, archive: impl Into<PathBuf>, guest_dir: impl Into<String>) -> Self {
+        self.options
+            .embedded_tars
+            .push((archive.into(), guest_dir.into()));
+        self
+    }
+
+    /// Package a zip archive at a guest directory.
+    pub fn zip(mut self, archive: impl Into<PathBuf>, guest_dir: impl Into<String>) -> Self {
+        self.options.zips.push((archive.into(), guest_dir.into()));
+        self
+    }
+
+    /// Exclude entries matching the glob pattern.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.options.excludes.push(pattern.into());
+        self
+    }
+
+    /// Package only files matching the glob pattern.
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.options.includes.push(pattern.into());
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    pub fn mode(mut self, mode: PackMode) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.options.dedup = dedup;
+        self
+    }
+
+    /// Mount built-in device nodes at `/dev` at runtime.
+    pub fn dev(mut self, dev: bool) -> Self {
+        self.options.dev = dev;
+        self
+    }
+
+    pub fn mtime(mut self, mtime: MtimePolicy) -> Self {
+        self.options.mtime = mtime;
+        self
+    }
+
+    /// Run the exported function after packing in the same snapshot, with the
+    /// packed file system active.
+    pub fn init_func(mut self, name: impl Into<String>) -> Self {
+        self.options.init_func = Some(name.into());
+        self
+    }
+
+    /// Print packed entries from the guest to stdout.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    /// Replace all options set so far, including files, trees and archives
+    /// to package. Directories added by [`Packer::dir`] are kept.
+    pub fn options(mut self, options: PackOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Run the packing on the given engine instead of a new one. The engine
+    /// must be configured with async support.
+    pub fn engine(mut self, engine: wasmtime::Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Package everything into the given Wasm module on a new tokio runtime.
+    pub fn pack(&self, wasm_bytes: &[u8]) -> Result<Packed> {
+        // Use tokio runtime for async wizer
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(self.pack_async(wasm_bytes))
+    }
+
+    /// Package everything into the given Wasm module on the caller's runtime.
+    pub async fn pack_async(&self, wasm_bytes: &[u8]) -> Result<Packed> {
+        crate::pack_async(
+            wasm_bytes,
+            &self.map_dirs,
+            &self.options,
+            self.engine.as_ref(),
+        )
+        .await
+    }
+}
@@ -0,0 +1,229 @@
+//! Codec of the tree stream format, which is used to feed trees that are not
+//! host directories (e.g. archives) to the in-guest packer, and to dump the
+//! embedded file system of packed modules.
+//!
+//! The format must be kept in sync with `src/stream.rs` of the wasi-vfs crate.
+
+use std::io::{self, Write};
+
+const MAGIC: &[u8; 8] = b"WVFSTREE";
+const VERSION: u32 = 1;
+
+pub(crate) struct Writer<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub(crate) fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&VERSION.to_le_bytes())?;
+        Ok(Writer { inner })
+    }
+
+    /// Start a new tree mounted at `guest_dir`. Following entries are placed
+    /// under it until the next mount.
+    pub(crate) fn mount(&mut self, guest_dir: &str) -> io::Result<()> {
+        self.inner.write_all(b"M")?;
+        self.string(guest_dir)
+    }
+
+    pub(crate) fn dir(&mut self, path: &str, mtime: u64) -> io::Result<()> {
+        self.inner.write_all(b"D")?;
+        self.string(path)?;
+        self.inner.write_all(&mtime.to_le_bytes())
+    }
+
+    pub(crate) fn file(&mut self, path: &str, mtime: u64, content: &[u8]) -> io::Result<()> {
+        self.inner.write_all(b"F")?;
+        self.string(path)?;
+        self.inner.write_all(&mtime.to_le_bytes())?;
+        self.inner
+            .write_all(&(content.len() as u64).to_le_bytes())?;
+        self.inner.write_all(content)
+    }
+
+    pub(crate) fn symlink(&mut self, path: &str, mtime: u64, target: &str) -> io::Result<()> {
+        self.inner.write_all(b"L")?;
+        self.string(path)?;
+        self.inner.write_all(&mtime.to_le_bytes())?;
+        self.string(target)
+    }
+
+    /// Add a hard link to `target`, which must be written before the link.
+    pub(crate) fn hard_link(&mut self, path: &str, target: &str) -> io::Result<()> {
+        self.inner.write_all(b"H")?;
+        self.string(path)?;
+        self.string(target)
+    }
+
+    /// Add an uncompressed tar archive to be placed as is at the directory.
+    pub(crate) fn archive(&mut self, path: &str, content: &[u8]) -> io::Result<()> {
+        self.inner.write_all(b"T")?;
+        self.string(path)?;
+        self.inner
+            .write_all(&(content.len() as u64).to_le_bytes())?;
+        self.inner.write_all(content)
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn string(&mut self, s: &str) -> io::Result<()> {
+        let len = u32::try_from(s.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too long path"))?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(s.as_bytes())
+    }
+}
+
+/// A decoded record. Paths are relative to the last mount and start with `/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Record {
+    Mount {
+        guest_dir: String,
+    },
+    Dir {
+        path: String,
+        mtime: u64,
+    },
+    File {
+        path: String,
+        mtime: u64,
+        content: Vec<u8>,
+    },
+    Symlink {
+        path: String,
+        mtime: u64,
+        target: String,
+    },
+    HardLink {
+        path: String,
+        target: String,
+    },
+    Archive {
+        path: String,
+        content: Vec<u8>,
+    },
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> io::Result<Self> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+            return Err(invalid_data("unknown tree stream format"));
+        }
+        Ok(reader)
+    }
+
+    /// Returns the next record, or `None` at the end of the stream.
+    pub(crate) fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let kind = match self.data.first() {
+            Some(kind) => *kind,
+            None => return Ok(None),
+        };
+        self.data = &self.data[1..];
+        let record = match kind {
+            b'M' => Record::Mount {
+                guest_dir: self.string()?,
+            },
+            b'D' => Record::Dir {
+                path: self.string()?,
+                mtime: self.u64()?,
+            },
+            b'F' => Record::File {
+                path: self.string()?,
+                mtime: self.u64()?,
+                content: self.content()?,
+            },
+            b'L' => Record::Symlink {
+                path: self.string()?,
+                mtime: self.u64()?,
+                target: self.string()?,
+            },
+            b'H' => Record::HardLink {
+                path: self.string()?,
+                target: self.string()?,
+            },
+            b'T' => Record::Archive {
+                path: self.string()?,
+                content: self.content()?,
+            },
+            _ => return Err(invalid_data("unknown tree stream record")),
+        };
+        Ok(Some(record))
+    }
+
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(invalid_data("truncated tree stream"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn content(&mut self) -> io::Result<Vec<u8>> {
+        let len = usize::try_from(self.u64()?).map_err(|_| invalid_data("too large file"))?;
+        Ok(self.bytes(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| invalid_data("non UTF-8 path"))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read_stream() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.mount("/mnt").unwrap();
+        writer.file("/a", 1, b"xy").unwrap();
+        let bytes = writer.finish().unwrap();
+        let mut expected = b"WVFSTREE\x01\x00\x00\x00".to_vec();
+        expected.extend(b"M\x04\x00\x00\x00/mnt");
+        expected.extend(b"F\x02\x00\x00\x00/a\x01\x00\x00\x00\x00\x00\x00\x00");
+        expected.extend(b"\x02\x00\x00\x00\x00\x00\x00\x00xy");
+        assert_eq!(bytes, expected);
+
+        let mut reader = Reader::new(&bytes).unwrap();
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some(Record::Mount {
+                guest_dir: "/mnt".to_string()
+            })
+        );
+        assert_eq!(
+            reader.next_record().unwrap(),
+            Some(Record::File {
+                path: "/a".to_string(),
+                mtime: 1,
+                content: b"xy".to_vec()
+            })
+        );
+        assert_eq!(reader.next_record().unwrap(), None);
+    }
+}
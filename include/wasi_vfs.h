@@ -0,0 +1,33 @@
+#ifndef WASI_VFS_H
+#define WASI_VFS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Produces the content of a generated file by passing `out` to
+// `wasi_vfs_generated_file_write`. It may access files and stdio, and is
+// called from whichever thread opens the file.
+typedef void (*wasi_vfs_generate_fn)(void *userdata, void *out);
+
+// Register a file at the absolute guest `path` whose content is produced by
+// `generate` each time it's opened. A directory is mounted at the top-level
+// directory of the path if no packed directory contains it. Register files
+// at startup before opening other files. `stat` on the path reports a size
+// of 0, while `fstat` reports the size of the content of the open file.
+// Returns 0 on success or a WASI errno.
+int32_t wasi_vfs_register_generated_file(const char *path,
+                                         wasi_vfs_generate_fn generate,
+                                         void *userdata);
+
+// Append `len` bytes at `data` to the content being generated.
+void wasi_vfs_generated_file_write(void *out, const void *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
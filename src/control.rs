@@ -0,0 +1,111 @@
+//! Out-of-band communication with the CLI through the control directory.
+
+use std::ffi::CStr;
+
+use crate::env_var;
+
+/// Name of the environment variable holding the guest path of the control
+/// directory. The control directory is a host directory preopened by the CLI
+/// to exchange out-of-band information (e.g. error reports) with the guest.
+/// It is never packed into the embedded file system.
+pub(crate) const CONTROL_DIR_ENV: &str = "__WASI_VFS_PACK_CONTROL";
+
+/// Find the real fd of the control directory preopened by the CLI.
+fn control_dir_fd() -> Option<u32> {
+    let control_dir = env_var(CONTROL_DIR_ENV)?;
+    for fd in 3.. {
+        let stat = match unsafe { wasi::fd_prestat_get(fd) } {
+            Ok(stat) => stat,
+            Err(_) => return None,
+        };
+        if stat.tag != wasi::PREOPENTYPE_DIR.raw() {
+            continue;
+        }
+        if prestat_dir_name(fd, &stat).ok()? == control_dir {
+            return Some(fd);
+        }
+    }
+    None
+}
+
+/// Write a file into the control directory. Returns `Ok(false)` if there is
+/// no control directory.
+pub(crate) fn write_file(name: &str, bytes: &[u8]) -> Result<bool, wasi::Errno> {
+    match control_dir_fd() {
+        Some(dir_fd) => write_file_at(dir_fd, name, bytes).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+fn write_file_at(dir_fd: u32, name: &str, bytes: &[u8]) -> Result<(), wasi::Errno> {
+    let fd = unsafe {
+        wasi::path_open(
+            dir_fd,
+            0,
+            name,
+            wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC,
+            wasi::RIGHTS_FD_WRITE,
+            0,
+            0,
+        )?
+    };
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let data = [wasi::Ciovec {
+            buf: bytes[offset..].as_ptr(),
+            buf_len: bytes.len() - offset,
+        }];
+        offset += unsafe { wasi::fd_write(fd, &data)? };
+    }
+    unsafe { wasi::fd_close(fd) }
+}
+
+/// Read the whole content of a file opened as `fd`.
+pub(crate) fn read_to_end(fd: u32) -> Result<Vec<u8>, wasi::Errno> {
+    let stat = unsafe { wasi::fd_filestat_get(fd)? };
+    let mut buf = vec![0; stat.size as usize];
+    let mut offset = 0;
+    while offset < buf.len() {
+        let read = unsafe {
+            wasi::fd_read(
+                fd,
+                &[wasi::Iovec {
+                    buf: buf[offset..].as_mut_ptr(),
+                    buf_len: buf.len() - offset,
+                }],
+            )?
+        };
+        if read == 0 {
+            // the file was truncated while reading
+            return Err(wasi::ERRNO_IO);
+        }
+        offset += read;
+    }
+    Ok(buf)
+}
+
+/// Read a file written by the CLI into the control directory, if any.
+pub(crate) fn read_file(name: &str) -> Result<Option<Vec<u8>>, wasi::Errno> {
+    let dir_fd = match control_dir_fd() {
+        Some(fd) => fd,
+        None => return Ok(None),
+    };
+    let rights = wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_FILESTAT_GET;
+    let fd = match unsafe { wasi::path_open(dir_fd, 0, name, 0, rights, 0, 0) } {
+        Ok(fd) => fd,
+        Err(wasi::ERRNO_NOENT) => return Ok(None),
+        Err(other) => return Err(other),
+    };
+    let content = read_to_end(fd);
+    unsafe { wasi::fd_close(fd)? };
+    content.map(Some)
+}
+
+pub(crate) fn prestat_dir_name(fd: u32, stat: &wasi::Prestat) -> Result<String, wasi::Errno> {
+    unsafe {
+        let mut prefix = vec![0; stat.u.dir.pr_name_len + 1];
+        wasi::fd_prestat_dir_name(fd, prefix.as_mut_ptr(), stat.u.dir.pr_name_len)?;
+        let dir = CStr::from_bytes_with_nul(&prefix).map_err(|_| wasi::ERRNO_ILSEQ)?;
+        Ok(dir.to_string_lossy().to_string())
+    }
+}
@@ -0,0 +1,145 @@
+//! Built-in device nodes mounted at `/dev`, served without any backing file.
+//! The mount is enabled by `--dev` at packing time or `WASI_VFS_DEV=1` at
+//! runtime.
+
+use std::slice;
+
+use wasi::{CiovecArray, Fdstat, Filestat, Filetype, IovecArray, Rights, Size};
+
+use crate::env_var;
+
+/// The guest directory the device nodes are mounted at.
+pub(crate) const DEV_DIR: &str = "/dev";
+
+/// Device numbers of embedded files are 0, so device nodes never share
+/// (dev, ino) with them.
+const DEV_ID: u64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Device {
+    /// The `/dev` directory itself
+    Dir,
+    /// Discards writes and reads nothing
+    Null,
+    /// Discards writes and reads zero bytes
+    Zero,
+    /// Discards writes and reads bytes from `random_get`
+    Urandom,
+}
+
+const DEVICE_NODES: [(&str, Device); 3] = [
+    ("null", Device::Null),
+    ("zero", Device::Zero),
+    ("urandom", Device::Urandom),
+];
+
+/// Returns true if the `/dev` mount is requested at runtime.
+pub(crate) fn enabled_by_env() -> bool {
+    env_var("WASI_VFS_DEV").is_some_and(|v| v == "1")
+}
+
+impl Device {
+    /// Resolve `path` relative to the `/dev` directory.
+    pub(crate) fn lookup(path: &[u8]) -> Result<Device, wasi::Errno> {
+        let mut components = path
+            .split(|byte| *byte == b'/')
+            .filter(|component| !component.is_empty() && *component != b".");
+        let Some(name) = components.next() else {
+            return Ok(Device::Dir);
+        };
+        if name == b".." {
+            return Err(wasi::ERRNO_PERM);
+        }
+        let device = DEVICE_NODES
+            .iter()
+            .find(|(node, _)| node.as_bytes() == name)
+            .map(|(_, device)| *device)
+            .ok_or(wasi::ERRNO_NOENT)?;
+        match components.next() {
+            Some(_) => Err(wasi::ERRNO_NOTDIR),
+            None => Ok(device),
+        }
+    }
+
+    /// Directory entries of `/dev` as (name, ino, filetype).
+    pub(crate) fn entries() -> impl Iterator<Item = (&'static str, u64, Filetype)> {
+        DEVICE_NODES
+            .iter()
+            .map(|(name, device)| (*name, device.ino(), device.filetype()))
+    }
+
+    fn ino(self) -> u64 {
+        match self {
+            Device::Dir => 1,
+            Device::Null => 2,
+            Device::Zero => 3,
+            Device::Urandom => 4,
+        }
+    }
+
+    fn filetype(self) -> Filetype {
+        match self {
+            Device::Dir => wasi::FILETYPE_DIRECTORY,
+            Device::Null | Device::Zero | Device::Urandom => wasi::FILETYPE_CHARACTER_DEVICE,
+        }
+    }
+
+    pub(crate) unsafe fn read(self, iovs: IovecArray<'_>) -> Result<Size, wasi::Errno> {
+        let mut bytes_read = 0;
+        for iov in iovs {
+            let buf = unsafe { slice::from_raw_parts_mut(iov.buf, iov.buf_len) };
+            match self {
+                Device::Dir => return Err(wasi::ERRNO_ISDIR),
+                Device::Null => return Ok(0),
+                Device::Zero => buf.fill(0),
+                Device::Urandom => unsafe { wasi::random_get(buf.as_mut_ptr(), buf.len())? },
+            }
+            bytes_read += buf.len();
+        }
+        Ok(bytes_read)
+    }
+
+    pub(crate) fn write(self, iovs: CiovecArray<'_>) -> Result<Size, wasi::Errno> {
+        match self {
+            Device::Dir => Err(wasi::ERRNO_ISDIR),
+            Device::Null | Device::Zero | Device::Urandom => {
+                Ok(iovs.iter().map(|iov| iov.buf_len).sum())
+            }
+        }
+    }
+
+    pub(crate) fn filestat(self) -> Filestat {
+        Filestat {
+            dev: DEV_ID,
+            ino: self.ino(),
+            filetype: self.filetype(),
+            nlink: 1,
+            size: 0,
+            atim: 0,
+            mtim: 0,
+            ctim: 0,
+        }
+    }
+
+    pub(crate) fn fdstat(self) -> Fdstat {
+        const DIR_RIGHTS: Rights = wasi::RIGHTS_PATH_OPEN
+            | wasi::RIGHTS_FD_READDIR
+            | wasi::RIGHTS_FD_FILESTAT_GET
+            | wasi::RIGHTS_PATH_FILESTAT_GET;
+        const DEVICE_RIGHTS: Rights = wasi::RIGHTS_FD_READ
+            | wasi::RIGHTS_FD_WRITE
+            | wasi::RIGHTS_FD_SEEK
+            | wasi::RIGHTS_FD_TELL
+            | wasi::RIGHTS_FD_FILESTAT_GET;
+        let fs_rights_base = match self {
+            Device::Dir => DIR_RIGHTS,
+            Device::Null | Device::Zero | Device::Urandom => DEVICE_RIGHTS,
+        };
+        Fdstat {
+            fs_filetype: self.filetype(),
+            fs_flags: 0,
+            fs_rights_base,
+            fs_rights_inheriting: DEVICE_RIGHTS,
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! A storage serving uncompressed tar archives in place.
+//!
+//! Archives are kept as single buffers, which are embedded into the packed
+//! module byte-for-byte. The index of their entries is built when the
+//! runtime starts, and file contents are slices of the archive buffers.
+//! Entries created by other means (e.g. host directories) are stored in the
+//! same index with their own buffers.
+
+use std::path::Path;
+
+use super::tar::{TarEntryKind, TarReader};
+use super::{
+    DirEntry, Link, Node, NodeDirBody, NodeFileBody, NodeIdTrait, NodeMetadata, NodeSymlinkBody,
+    Storage,
+};
+
+const MAX_SYMLINK_DEPTH: usize = 40;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Hash, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(u32);
+
+impl NodeIdTrait for NodeId {
+    fn ino(&self) -> u64 {
+        self.0 as u64 + 1
+    }
+}
+
+#[derive(Hash, Clone, Copy, PartialEq, Eq)]
+pub struct LinkId(u32);
+
+pub(crate) struct FileBody {
+    // Points into `ArchiveStorage::buffers`, which are never freed or moved
+    data: *const u8,
+    len: usize,
+}
+
+impl NodeFileBody for FileBody {
+    fn content(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+pub(crate) struct DirBody {
+    entries: Vec<DirEntry<ArchiveStorage>>,
+}
+
+impl NodeDirBody<ArchiveStorage> for DirBody {
+    type Iter = std::vec::IntoIter<DirEntry<ArchiveStorage>>;
+    fn entries(&self) -> Self::Iter {
+        self.entries.clone().into_iter()
+    }
+}
+
+pub(crate) struct SymlinkBody {
+    target: String,
+}
+
+impl NodeSymlinkBody for SymlinkBody {
+    fn target(&self) -> &str {
+        &self.target
+    }
+}
+
+enum NodeBody {
+    File(FileBody),
+    Dir(DirBody),
+    Symlink(SymlinkBody),
+}
+
+struct InnerNode {
+    body: NodeBody,
+    mtime: u64,
+    nlink: u32,
+}
+
+struct InnerLink {
+    parent: Option<LinkId>,
+    node: NodeId,
+}
+
+#[derive(Default)]
+pub struct ArchiveStorage {
+    nodes: Vec<InnerNode>,
+    links: Vec<InnerLink>,
+    /// Archives and file contents. Boxed slices keep their address even if
+    /// the vector grows.
+    buffers: Vec<Box<[u8]>>,
+    /// Archives not indexed yet, paired with the directory to serve them at.
+    pending_archives: Vec<(usize, NodeId, LinkId)>,
+}
+
+impl ArchiveStorage {
+    fn node(&self, node_id: NodeId) -> &InnerNode {
+        &self.nodes[node_id.0 as usize]
+    }
+
+    fn link(&self, link_id: LinkId) -> &InnerLink {
+        &self.links[link_id.0 as usize]
+    }
+
+    fn new_node(&mut self, body: NodeBody) -> NodeId {
+        let id = NodeId(self.nodes.len() as u32);
+        self.nodes.push(InnerNode {
+            body,
+            mtime: 0,
+            nlink: 0,
+        });
+        id
+    }
+
+    fn new_link(&mut self, parent: Option<(NodeId, LinkId)>, name: String, node: NodeId) -> LinkId {
+        let link_id = LinkId(self.links.len() as u32);
+        self.links.push(InnerLink {
+            parent: parent.map(|(_, link)| link),
+            node,
+        });
+        self.nodes[node.0 as usize].nlink += 1;
+        if let Some((parent, _)) = parent {
+            if let NodeBody::Dir(dir) = &mut self.nodes[parent.0 as usize].body {
+                dir.entries.push(DirEntry { name, link_id });
+            }
+        }
+        link_id
+    }
+
+    fn new_buffer(&mut self, content: Box<[u8]>) -> FileBody {
+        let body = FileBody {
+            data: content.as_ptr(),
+            len: content.len(),
+        };
+        self.buffers.push(content);
+        body
+    }
+
+    fn lookup(&self, dir: NodeId, name: &str) -> Option<(NodeId, LinkId)> {
+        match &self.node(dir).body {
+            NodeBody::Dir(body) => body
+                .entries
+                .iter()
+                .find(|entry| entry.name == name)
+                .map(|entry| (self.link(entry.link_id).node, entry.link_id)),
+            _ => None,
+        }
+    }
+
+    /// Index entries of an archive under the given directory. Entries at the
+    /// same path as existing ones replace them.
+    fn index_archive(&mut self, buffer: usize, root: (NodeId, LinkId)) {
+        let archive: &[u8] = &self.buffers[buffer];
+        // The archive buffer outlives the index, so entries can borrow it
+        let archive: &'static [u8] =
+            unsafe { std::slice::from_raw_parts(archive.as_ptr(), archive.len()) };
+        for entry in TarReader::new(archive) {
+            let Some((parent, name)) = self.make_parent_dirs(root, &entry.path) else {
+                // the archive root itself or a path through a non-directory
+                continue;
+            };
+            let existing = self.lookup(parent.0, name);
+            let mtime = entry.mtime.saturating_mul(NANOS_PER_SEC);
+            let is_hard_link = matches!(entry.kind, TarEntryKind::HardLink(_));
+            let node = match entry.kind {
+                TarEntryKind::Dir => match existing {
+                    Some(existing) if matches!(self.node(existing.0).body, NodeBody::Dir(_)) => {
+                        existing.0
+                    }
+                    _ => self.new_node(NodeBody::Dir(DirBody { entries: vec![] })),
+                },
+                TarEntryKind::File(data) => self.new_node(NodeBody::File(FileBody {
+                    data: data.as_ptr(),
+                    len: data.len(),
+                })),
+                TarEntryKind::Symlink(target) => {
+                    self.new_node(NodeBody::Symlink(SymlinkBody { target }))
+                }
+                TarEntryKind::HardLink(target) => {
+                    match self.resolve_node(root.0, root.1, Path::new(&target), false) {
+                        Ok((node, _)) if !matches!(self.node(node).body, NodeBody::Dir(_)) => node,
+                        _ => continue,
+                    }
+                }
+            };
+            if existing.is_none_or(|existing| existing.0 != node) {
+                if existing.is_some() {
+                    self.remove_entry(parent, name);
+                }
+                self.new_link(Some(parent), name.to_string(), node);
+            }
+            // Hard links share the metadata of their targets
+            if !is_hard_link {
+                self.nodes[node.0 as usize].mtime = mtime;
+            }
+        }
+    }
+
+    /// Find or create the parent directories of `path` relative to `root`.
+    /// Returns the parent directory and the last component.
+    fn make_parent_dirs<'path>(
+        &mut self,
+        root: (NodeId, LinkId),
+        path: &'path str,
+    ) -> Option<((NodeId, LinkId), &'path str)> {
+        let (dirs, name) = path.rsplit_once('/')?;
+        let mut cursor = root;
+        for component in dirs.split('/').filter(|c| !c.is_empty()) {
+            cursor = match self.lookup(cursor.0, component) {
+                Some(child) if matches!(self.node(child.0).body, NodeBody::Dir(_)) => child,
+                Some(_) => return None,
+                None => self.new_dir(cursor, component.to_string()),
+            };
+        }
+        Some((cursor, name))
+    }
+
+    fn resolve_at(
+        &self,
+        base: (NodeId, LinkId),
+        path: &str,
+        follow_final: bool,
+        depth: &mut usize,
+    ) -> Result<(NodeId, LinkId), wasi::Errno> {
+        let mut current = base;
+        let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+        while let Some(component) = components.next() {
+            if !matches!(self.node(current.0).body, NodeBody::Dir(_)) {
+                return Err(wasi::ERRNO_NOTDIR);
+            }
+            match component {
+                "." => continue,
+                ".." => {
+                    // `..` at the root escapes the mount, which hosts refuse with EPERM
+                    let parent = self.link(current.1).parent.ok_or(wasi::ERRNO_PERM)?;
+                    current = (self.link(parent).node, parent);
+                    continue;
+                }
+                _ => {}
+            }
+            let child = self.lookup(current.0, component).ok_or(wasi::ERRNO_NOENT)?;
+            let is_final = components.peek().is_none();
+            current = match &self.node(child.0).body {
+                NodeBody::Symlink(symlink) if !is_final || follow_final => {
+                    *depth += 1;
+                    if *depth > MAX_SYMLINK_DEPTH {
+                        return Err(wasi::ERRNO_LOOP);
+                    }
+                    let mut start = current;
+                    if symlink.target.starts_with('/') {
+                        // absolute paths are resolved from the root of the embedded tree
+                        while let Some(parent) = self.link(start.1).parent {
+                            start = (self.link(parent).node, parent);
+                        }
+                    }
+                    self.resolve_at(start, &symlink.target, true, depth)?
+                }
+                _ => child,
+            };
+        }
+        // a trailing slash only names a directory
+        if path.ends_with('/') && !matches!(self.node(current.0).body, NodeBody::Dir(_)) {
+            return Err(wasi::ERRNO_NOTDIR);
+        }
+        Ok(current)
+    }
+}
+
+impl Storage for ArchiveStorage {
+    type NodeId = NodeId;
+    type LinkId = LinkId;
+    type NodeFileBody = FileBody;
+    type NodeDirBody = DirBody;
+    type NodeSymlinkBody = SymlinkBody;
+
+    fn new_root_dir(&mut self) -> (NodeId, LinkId) {
+        let node = self.new_node(NodeBody::Dir(DirBody { entries: vec![] }));
+        let link = self.new_link(None, String::new(), node);
+        (node, link)
+    }
+
+    fn new_dir(&mut self, parent: (NodeId, LinkId), name: String) -> (NodeId, LinkId) {
+        let node = self.new_node(NodeBody::Dir(DirBody { entries: vec![] }));
+        let link = self.new_link(Some(parent), name, node);
+        (node, link)
+    }
+
+    fn new_file(
+        &mut self,
+        parent: (NodeId, LinkId),
+        name: String,
+        content: Vec<u8>,
+    ) -> (NodeId, LinkId) {
+        let body = self.new_buffer(content.into_boxed_slice());
+        let node = self.new_node(NodeBody::File(body));
+        let link = self.new_link(Some(parent), name, node);
+        (node, link)
+    }
+
+    fn new_symlink(
+        &mut self,
+        parent: (NodeId, LinkId),
+        name: String,
+        target: String,
+    ) -> (NodeId, LinkId) {
+        let node = self.new_node(NodeBody::Symlink(SymlinkBody { target }));
+        let link = self.new_link(Some(parent), name, node);
+        (node, link)
+    }
+
+    fn new_hard_link(
+        &mut self,
+        parent: (NodeId, LinkId),
+        name: String,
+        node: NodeId,
+    ) -> (NodeId, LinkId) {
+        let link = self.new_link(Some(parent), name, node);
+        (node, link)
+    }
+
+    fn remove_entry(&mut self, parent: (NodeId, LinkId), name: &str) -> bool {
+        let NodeBody::Dir(dir) = &mut self.nodes[parent.0.0 as usize].body else {
+            return false;
+        };
+        let Some(index) = dir.entries.iter().position(|entry| entry.name == name) else {
+            return false;
+        };
+        let entry = dir.entries.remove(index);
+        // Unlinked nodes are left in the arena since nodes are never freed
+        let node = self.links[entry.link_id.0 as usize].node;
+        self.nodes[node.0 as usize].nlink -= 1;
+        true
+    }
+
+    fn set_mtime(&mut self, node_id: &NodeId, mtime: wasi::Timestamp) {
+        self.nodes[node_id.0 as usize].mtime = mtime;
+    }
+
+    fn add_archive(&mut self, dir: (NodeId, LinkId), archive: Vec<u8>) -> Result<(), Vec<u8>> {
+        self.buffers.push(archive.into_boxed_slice());
+        self.pending_archives
+            .push((self.buffers.len() - 1, dir.0, dir.1));
+        Ok(())
+    }
+
+    fn prepare(&mut self) {
+        for (buffer, node, link) in std::mem::take(&mut self.pending_archives) {
+            self.index_archive(buffer, (node, link));
+        }
+    }
+
+    fn get_inode(&self, node_id: &NodeId) -> Node<Self> {
+        match &self.node(*node_id).body {
+            NodeBody::File(body) => Node::File(body),
+            NodeBody::Dir(body) => Node::Dir(body),
+            NodeBody::Symlink(body) => Node::Symlink(body),
+        }
+    }
+
+    fn get_metadata(&self, node_id: &NodeId) -> NodeMetadata {
+        let node = self.node(*node_id);
+        NodeMetadata {
+            mtime: node.mtime,
+            nlink: node.nlink as u64,
+        }
+    }
+
+    fn get_link(&self, link_id: &LinkId) -> Link<Self> {
+        let link = self.link(*link_id);
+        Link {
+            parent: link.parent,
+            node: link.node,
+        }
+    }
+
+    fn resolve_node(
+        &self,
+        base: NodeId,
+        base_link: LinkId,
+        path: &Path,
+        follow_symlinks: bool,
+    ) -> Result<(NodeId, LinkId), wasi::Errno> {
+        let path = path.to_str().ok_or(wasi::ERRNO_ILSEQ)?;
+        let mut depth = 0;
+        self.resolve_at((base, base_link), path, follow_symlinks, &mut depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::super::{EmbeddedFs, NodeFileBody, Storage};
+    use super::ArchiveStorage;
+
+    #[test]
+    fn test_serve_archive_in_place() {
+        let mut header = vec![0; 512];
+        header[..9].copy_from_slice(b"hello.txt");
+        header[124..135].copy_from_slice(b"00000000005");
+        header[156] = b'0';
+        let mut archive = header;
+        archive.extend(b"Hello");
+        archive.extend(vec![0; 512 - 5 + 1024]);
+
+        let mut fs = EmbeddedFs::<ArchiveStorage>::default();
+        let (vfd, node_id, link_id) = fs.preopen_dir("/".to_string());
+        fs.create_archive(node_id, link_id, "/data", archive)
+            .unwrap();
+        assert!(
+            fs.get_filestat_at_path(vfd, Path::new("data/hello.txt"), true)
+                .is_err()
+        );
+
+        fs.prepare();
+        let stat = fs
+            .get_filestat_at_path(vfd, Path::new("data/hello.txt"), true)
+            .unwrap();
+        assert_eq!(stat.size, 5);
+        let fd = fs
+            .open_file(vfd, Path::new("data/hello.txt"), true, 0)
+            .unwrap();
+        let node_id = fs.get_fd_entry(fd).unwrap().node_id;
+        match fs.storage.get_inode(&node_id) {
+            super::Node::File(body) => assert_eq!(body.content(), b"Hello"),
+            _ => panic!("not a file"),
+        }
+    }
+}
@@ -7,8 +7,14 @@
 
 typedef uint16_t wasi_errno_t;
 #define WASI_ERRNO_SUCCESS (0)
+#define WASI_ERRNO_LOOP (32)
 #define WASI_ERRNO_NOENT (44)
 #define WASI_ERRNO_NOTDIR (54)
+#define WASI_ERRNO_PERM (63)
+
+// Maximum number of symbolic links followed while resolving a path, same as
+// Linux's MAXSYMLINKS.
+#define WASI_VFS_MAX_SYMLINK_DEPTH (40)
 
 struct wasi_vfs_embed_linked_storage {};
 
@@ -71,15 +77,27 @@ struct wasi_vfs_dirent {
   struct wasi_vfs_dirent *next;
 };
 
+enum wasi_vfs_node_kind {
+  WASI_VFS_NODE_FILE = 0,
+  WASI_VFS_NODE_DIR = 1,
+  WASI_VFS_NODE_SYMLINK = 2,
+};
+
 // IMPORTANT: This layout must match the layout of struct InnerNode in
 // Rust-side.
 struct wasi_vfs_node {
-  bool is_dir;
+  uint8_t kind;
+  // file: content length, dir: number of entries, symlink: target length
   size_t count;
   union {
+    // file content, or NUL-terminated target path for symlink
     uint8_t *data;
     struct wasi_vfs_dirent *dirents;
   };
+  // last modification time in nanoseconds since the epoch
+  uint64_t mtime;
+  // number of links pointing to this node
+  uint32_t nlink;
 };
 
 typedef struct {
@@ -94,11 +112,13 @@ static void insert_dirent(struct wasi_vfs_node *node,
   node->count++;
 }
 
-static struct wasi_vfs_node *new_node(bool is_dir) {
+static struct wasi_vfs_node *new_node(enum wasi_vfs_node_kind kind) {
   struct wasi_vfs_node *node = malloc(sizeof(struct wasi_vfs_node));
-  node->is_dir = is_dir;
+  node->kind = kind;
   node->count = 0;
   node->data = NULL;
+  node->mtime = 0;
+  node->nlink = 0;
   return node;
 }
 
@@ -106,6 +126,7 @@ static struct wasi_vfs_link *new_link(struct wasi_vfs_node *node) {
   struct wasi_vfs_link *link = malloc(sizeof(struct wasi_vfs_link));
   link->parent = NULL;
   link->node = node;
+  node->nlink++;
   return link;
 }
 
@@ -130,7 +151,7 @@ void wasi_vfs_embed_linked_storage_free(
 node_link_t wasi_vfs_embed_linked_storage_preopen_new_dir(
     struct wasi_vfs_embed_linked_storage *self) {
   (void)self;
-  struct wasi_vfs_node *node = new_node(true);
+  struct wasi_vfs_node *node = new_node(WASI_VFS_NODE_DIR);
   struct wasi_vfs_link *link = new_link(node);
   return (node_link_t){node, link};
 }
@@ -139,11 +160,11 @@ node_link_t wasi_vfs_embed_linked_storage_new_dir(
     struct wasi_vfs_embed_linked_storage *self, const node_link_t *parent,
     char *name) {
   (void)self;
-  struct wasi_vfs_node *node = new_node(true);
+  struct wasi_vfs_node *node = new_node(WASI_VFS_NODE_DIR);
   struct wasi_vfs_link *link = new_link(node);
   link->parent = parent->link;
 
-  assert(parent->node->is_dir && "parent is not a dir");
+  assert(parent->node->kind == WASI_VFS_NODE_DIR && "parent is not a dir");
 
   struct wasi_vfs_dirent *dirent = new_dirent(link, name);
   insert_dirent(parent->node, dirent);
@@ -156,7 +177,7 @@ node_link_t wasi_vfs_embed_linked_storage_new_file(
     char *name, uint8_t *content, size_t content_len) {
 
   (void)self;
-  struct wasi_vfs_node *node = new_node(false);
+  struct wasi_vfs_node *node = new_node(WASI_VFS_NODE_FILE);
   node->count = content_len;
   node->data = content;
 
@@ -170,11 +191,122 @@ node_link_t wasi_vfs_embed_linked_storage_new_file(
   return (node_link_t){node, link};
 }
 
-wasi_errno_t wasi_vfs_embed_linked_storage_resolve_node_at(
-    struct wasi_vfs_embed_linked_storage *self, const node_link_t *base,
-    const char *path, node_link_t *out) {
+node_link_t wasi_vfs_embed_linked_storage_new_symlink(
+    struct wasi_vfs_embed_linked_storage *self, const node_link_t *parent,
+    char *name, const char *target) {
+
   (void)self;
+  assert(parent->node->kind == WASI_VFS_NODE_DIR && "parent is not a dir");
+
+  struct wasi_vfs_node *node = new_node(WASI_VFS_NODE_SYMLINK);
+  node->count = strlen(target);
+  node->data = (uint8_t *)strdup(target);
 
+  struct wasi_vfs_link *link = new_link(node);
+  link->parent = parent->link;
+
+  struct wasi_vfs_dirent *dirent = new_dirent(link, name);
+  insert_dirent(parent->node, dirent);
+
+  return (node_link_t){node, link};
+}
+
+node_link_t wasi_vfs_embed_linked_storage_new_hard_link(
+    struct wasi_vfs_embed_linked_storage *self, const node_link_t *parent,
+    char *name, struct wasi_vfs_node *node) {
+
+  (void)self;
+  assert(parent->node->kind == WASI_VFS_NODE_DIR && "parent is not a dir");
+
+  struct wasi_vfs_link *link = new_link(node);
+  link->parent = parent->link;
+
+  struct wasi_vfs_dirent *dirent = new_dirent(link, name);
+  insert_dirent(parent->node, dirent);
+
+  return (node_link_t){node, link};
+}
+
+static void unlink_dirent(struct wasi_vfs_node *parent,
+                          struct wasi_vfs_dirent **cursor);
+
+// Clear a node which is no longer linked from anywhere. File contents are
+// zeroed so that they don't survive in memory snapshots taken by packing,
+// and entries of directories are unlinked recursively. The node itself is
+// kept because it may be still referenced by open file descriptors.
+static void release_node(struct wasi_vfs_node *node) {
+  if (node->kind == WASI_VFS_NODE_DIR) {
+    while (node->dirents != NULL) {
+      unlink_dirent(node, &node->dirents);
+    }
+  } else if (node->data != NULL) {
+    memset(node->data, 0, node->count);
+  }
+}
+
+static void unlink_dirent(struct wasi_vfs_node *parent,
+                          struct wasi_vfs_dirent **cursor) {
+  struct wasi_vfs_dirent *dirent = *cursor;
+  struct wasi_vfs_node *node = dirent->link->node;
+  *cursor = dirent->next;
+  parent->count--;
+  free((void *)dirent->name);
+  free(dirent);
+  if (--node->nlink == 0) {
+    release_node(node);
+  }
+}
+
+// Remove an entry from the given directory. The node is released when it's
+// not linked from other directories.
+bool wasi_vfs_embed_linked_storage_unlink(
+    struct wasi_vfs_embed_linked_storage *self, const node_link_t *parent,
+    const char *name) {
+  (void)self;
+  struct wasi_vfs_dirent **cursor = &parent->node->dirents;
+  while (*cursor != NULL) {
+    if (strcmp((*cursor)->name, name) == 0) {
+      unlink_dirent(parent->node, cursor);
+      return true;
+    }
+    cursor = &(*cursor)->next;
+  }
+  return false;
+}
+
+void wasi_vfs_embed_linked_storage_set_mtime(
+    struct wasi_vfs_embed_linked_storage *self, struct wasi_vfs_node *node,
+    uint64_t mtime) {
+  (void)self;
+  node->mtime = mtime;
+}
+
+static wasi_errno_t resolve_node_at(const node_link_t *base, const char *path,
+                                    bool follow_final, int *depth,
+                                    node_link_t *out);
+
+// Resolve the target of a symbolic link placed in the `dir` directory.
+static wasi_errno_t resolve_symlink(const node_link_t *dir,
+                                    const struct wasi_vfs_node *symlink,
+                                    int *depth, node_link_t *out) {
+  if (++(*depth) > WASI_VFS_MAX_SYMLINK_DEPTH) {
+    return WASI_ERRNO_LOOP;
+  }
+  const char *target = (const char *)symlink->data;
+  node_link_t start = *dir;
+  if (target[0] == '/') {
+    // absolute paths are resolved from the root of the embedded tree
+    while (start.link->parent != NULL) {
+      struct wasi_vfs_link *parent = start.link->parent;
+      start = (node_link_t){.node = parent->node, .link = parent};
+    }
+  }
+  return resolve_node_at(&start, target, true, depth, out);
+}
+
+static wasi_errno_t resolve_node_at(const node_link_t *base, const char *path,
+                                    bool follow_final, int *depth,
+                                    node_link_t *out) {
   node_link_t current = *base;
 
 find_parent_node:
@@ -190,7 +322,7 @@ find_parent_node:
     const size_t component_len = path - component;
 
     // expect that the current node is a dir
-    if (!current.node->is_dir) {
+    if (current.node->kind != WASI_VFS_NODE_DIR) {
       return WASI_ERRNO_NOTDIR;
     }
 
@@ -203,8 +335,9 @@ find_parent_node:
     if (component_len == 2 && component[0] == '.' && component[1] == '.') {
       // '..' component, go up
       if (current.link->parent == NULL) {
-        // we are already at the root
-        return WASI_ERRNO_NOTDIR;
+        // we are already at the root, and escaping the mount is refused
+        // with EPERM like hosts do
+        return WASI_ERRNO_PERM;
       }
       struct wasi_vfs_link *parent = current.link->parent;
       current = (node_link_t){.node = parent->node, .link = parent};
@@ -217,8 +350,21 @@ find_parent_node:
       if (strncmp(dirent->name, component, component_len) == 0 &&
           dirent->name[component_len] == '\0') {
         // found the child
-        current =
-            (node_link_t){.node = dirent->link->node, .link = dirent->link};
+        struct wasi_vfs_node *child = dirent->link->node;
+        const char *rest = path;
+        while (rest[0] == '/') {
+          rest++;
+        }
+        bool is_final = rest[0] == '\0';
+        if (child->kind == WASI_VFS_NODE_SYMLINK &&
+            (!is_final || follow_final)) {
+          wasi_errno_t err = resolve_symlink(&current, child, depth, &current);
+          if (err != WASI_ERRNO_SUCCESS) {
+            return err;
+          }
+        } else {
+          current = (node_link_t){.node = child, .link = dirent->link};
+        }
         goto find_parent_node;
       }
       dirent = dirent->next;
@@ -228,3 +374,11 @@ find_parent_node:
   *out = current;
   return WASI_ERRNO_SUCCESS;
 }
+
+wasi_errno_t wasi_vfs_embed_linked_storage_resolve_node_at(
+    struct wasi_vfs_embed_linked_storage *self, const node_link_t *base,
+    const char *path, bool follow_symlinks, node_link_t *out) {
+  (void)self;
+  int depth = 0;
+  return resolve_node_at(base, path, follow_symlinks, &depth, out);
+}
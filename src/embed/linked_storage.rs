@@ -8,7 +8,10 @@ use std::{
     path::Path,
 };
 
-use super::{DirEntry, Link, Node, NodeDirBody, NodeFileBody, NodeIdTrait, Storage};
+use super::{
+    DirEntry, Link, Node, NodeDirBody, NodeFileBody, NodeIdTrait, NodeMetadata, NodeSymlinkBody,
+    Storage,
+};
 
 #[repr(transparent)]
 #[derive(Hash, Clone, Copy, PartialEq, Eq)]
@@ -40,6 +43,15 @@ impl NodeFileBody for InnerNode {
     }
 }
 
+impl NodeSymlinkBody for InnerNode {
+    fn target(&self) -> &str {
+        let target =
+            unsafe { std::slice::from_raw_parts(self.dir_or_file as *const u8, self.count) };
+        // Symlink targets are created only from Rust strings
+        std::str::from_utf8(target).unwrap_or_default()
+    }
+}
+
 impl NodeDirBody<LinkedStorage> for InnerNode {
     type Iter = LinkedStorageIterator;
     fn entries(&self) -> Self::Iter {
@@ -66,11 +78,16 @@ impl Iterator for LinkedStorageIterator {
     }
 }
 
+const NODE_KIND_DIR: u8 = 1;
+const NODE_KIND_SYMLINK: u8 = 2;
+
 #[repr(C)]
 pub(crate) struct InnerNode {
-    is_dir: bool,
+    kind: u8,
     count: usize,
     dir_or_file: *const std::ffi::c_void,
+    mtime: u64,
+    nlink: u32,
 }
 
 #[repr(C)]
@@ -102,10 +119,33 @@ unsafe extern "C" {
         content: *const u8,
         content_len: usize,
     ) -> NodeLink;
+    fn wasi_vfs_embed_linked_storage_new_symlink(
+        context: *mut std::ffi::c_void,
+        parent: *const NodeLink,
+        name: *const i8,
+        target: *const i8,
+    ) -> NodeLink;
+    fn wasi_vfs_embed_linked_storage_new_hard_link(
+        context: *mut std::ffi::c_void,
+        parent: *const NodeLink,
+        name: *const i8,
+        node: NodeId,
+    ) -> NodeLink;
+    fn wasi_vfs_embed_linked_storage_unlink(
+        context: *mut std::ffi::c_void,
+        parent: *const NodeLink,
+        name: *const i8,
+    ) -> bool;
+    fn wasi_vfs_embed_linked_storage_set_mtime(
+        context: *mut std::ffi::c_void,
+        node: NodeId,
+        mtime: u64,
+    );
     fn wasi_vfs_embed_linked_storage_resolve_node_at(
         context: *mut std::ffi::c_void,
         base: *const NodeLink,
         path: *const i8,
+        follow_symlinks: bool,
         ret: *mut NodeLink,
     ) -> wasi::Errno;
 }
@@ -129,6 +169,7 @@ impl Storage for LinkedStorage {
     type LinkId = LinkId;
     type NodeFileBody = InnerNode;
     type NodeDirBody = InnerNode;
+    type NodeSymlinkBody = InnerNode;
 
     fn new_root_dir(&mut self) -> (NodeId, LinkId) {
         unsafe {
@@ -174,13 +215,83 @@ impl Storage for LinkedStorage {
         }
     }
 
+    fn new_symlink(
+        &mut self,
+        parent: (NodeId, LinkId),
+        name: String,
+        target: String,
+    ) -> (NodeId, LinkId) {
+        unsafe {
+            let name = CString::new(name).unwrap();
+            let target = CString::new(target).unwrap();
+            let link = NodeLink {
+                node_id: parent.0,
+                link_id: parent.1,
+            };
+            let result = wasi_vfs_embed_linked_storage_new_symlink(
+                self.context,
+                &link,
+                name.as_ptr(),
+                target.as_ptr(),
+            );
+            (result.node_id, result.link_id)
+        }
+    }
+
+    fn new_hard_link(
+        &mut self,
+        parent: (NodeId, LinkId),
+        name: String,
+        node: NodeId,
+    ) -> (NodeId, LinkId) {
+        unsafe {
+            let name = CString::new(name).unwrap();
+            let link = NodeLink {
+                node_id: parent.0,
+                link_id: parent.1,
+            };
+            let result = wasi_vfs_embed_linked_storage_new_hard_link(
+                self.context,
+                &link,
+                name.as_ptr(),
+                node,
+            );
+            (result.node_id, result.link_id)
+        }
+    }
+
+    fn remove_entry(&mut self, parent: (NodeId, LinkId), name: &str) -> bool {
+        let Ok(name) = CString::new(name) else {
+            return false;
+        };
+        let link = NodeLink {
+            node_id: parent.0,
+            link_id: parent.1,
+        };
+        unsafe { wasi_vfs_embed_linked_storage_unlink(self.context, &link, name.as_ptr()) }
+    }
+
+    fn set_mtime(&mut self, node_id: &NodeId, mtime: wasi::Timestamp) {
+        unsafe { wasi_vfs_embed_linked_storage_set_mtime(self.context, *node_id, mtime) }
+    }
+
     fn get_inode(&self, node_id: &NodeId) -> Node<Self> {
         unsafe {
             let node = node_id.0 as *const InnerNode;
-            if (*node).is_dir {
-                Node::Dir(node.as_ref().unwrap())
-            } else {
-                Node::File(node.as_ref().unwrap())
+            match (*node).kind {
+                NODE_KIND_DIR => Node::Dir(node.as_ref().unwrap()),
+                NODE_KIND_SYMLINK => Node::Symlink(node.as_ref().unwrap()),
+                _ => Node::File(node.as_ref().unwrap()),
+            }
+        }
+    }
+
+    fn get_metadata(&self, node_id: &NodeId) -> NodeMetadata {
+        unsafe {
+            let node = &*(node_id.0 as *const InnerNode);
+            NodeMetadata {
+                mtime: node.mtime,
+                nlink: node.nlink as u64,
             }
         }
     }
@@ -206,9 +317,12 @@ impl Storage for LinkedStorage {
         base: NodeId,
         base_link: LinkId,
         path: &Path,
+        follow_symlinks: bool,
     ) -> Result<(NodeId, LinkId), wasi::Errno> {
         unsafe {
-            let path: &[i8] = std::mem::transmute(path.as_os_str());
+            let path: &[u8] = std::mem::transmute(path.as_os_str());
+            // `path` is not always NUL-terminated
+            let path = CString::new(path).map_err(|_| wasi::ERRNO_INVAL)?;
             let link = NodeLink {
                 node_id: base,
                 link_id: base_link,
@@ -218,6 +332,7 @@ impl Storage for LinkedStorage {
                 self.context,
                 &link,
                 path.as_ptr(),
+                follow_symlinks,
                 ret.as_mut_ptr(),
             );
             if errno == wasi::ERRNO_SUCCESS {
@@ -1,10 +1,18 @@
 //! This module provides an in-memory filesystem implementation.
 
+#[cfg(any(feature = "archive-storage", test))]
+mod archive_storage;
+#[cfg(any(not(feature = "archive-storage"), test))]
 mod linked_storage;
+mod tar;
+#[cfg(feature = "archive-storage")]
+pub use archive_storage::ArchiveStorage;
+#[cfg(any(not(feature = "archive-storage"), test))]
 pub use linked_storage::LinkedStorage;
 
 use crate::Vfd;
-use std::{collections::HashMap, path::Path};
+use crate::stream::{self, Record};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 pub(crate) trait NodeIdTrait {
     fn ino(&self) -> u64;
@@ -14,6 +22,19 @@ pub(crate) trait NodeFileBody {
     fn content(&self) -> &[u8];
 }
 
+pub(crate) trait NodeSymlinkBody {
+    fn target(&self) -> &str;
+}
+
+/// Metadata attached to each node.
+#[derive(Clone, Copy, Default, Debug)]
+pub(crate) struct NodeMetadata {
+    /// Last modification time in nanoseconds since the epoch.
+    pub(crate) mtime: wasi::Timestamp,
+    /// Number of hard links to the node.
+    pub(crate) nlink: u64,
+}
+
 pub(crate) struct DirEntry<S: Storage + ?Sized> {
     pub(crate) name: String,
     pub(crate) link_id: S::LinkId,
@@ -39,6 +60,7 @@ pub(crate) trait Storage {
     type LinkId: Clone + Copy;
     type NodeFileBody: NodeFileBody;
     type NodeDirBody: NodeDirBody<Self>;
+    type NodeSymlinkBody: NodeSymlinkBody;
 
     /// Creates a new root node.
     fn new_root_dir(&mut self) -> (Self::NodeId, Self::LinkId);
@@ -58,24 +80,66 @@ pub(crate) trait Storage {
         content: Vec<u8>,
     ) -> (Self::NodeId, Self::LinkId);
 
+    /// Creates a new symbolic link node under the given parent node.
+    fn new_symlink(
+        &mut self,
+        parent: (Self::NodeId, Self::LinkId),
+        name: String,
+        target: String,
+    ) -> (Self::NodeId, Self::LinkId);
+
+    /// Creates a new hard link to an existing node under the given parent node.
+    fn new_hard_link(
+        &mut self,
+        parent: (Self::NodeId, Self::LinkId),
+        name: String,
+        node: Self::NodeId,
+    ) -> (Self::NodeId, Self::LinkId);
+
+    /// Removes an entry from the given parent node. Returns false if not found.
+    fn remove_entry(&mut self, parent: (Self::NodeId, Self::LinkId), name: &str) -> bool;
+
+    /// Updates the modification time of a node.
+    fn set_mtime(&mut self, node_id: &Self::NodeId, mtime: wasi::Timestamp);
+
+    /// Serves an uncompressed tar archive in place under the given directory.
+    /// Returns the archive back if the storage doesn't support it.
+    fn add_archive(
+        &mut self,
+        _dir: (Self::NodeId, Self::LinkId),
+        archive: Vec<u8>,
+    ) -> Result<(), Vec<u8>> {
+        Err(archive)
+    }
+
+    /// Called once at runtime initialization before nodes are resolved.
+    fn prepare(&mut self) {}
+
     /// Resolve a node from its id.
     fn get_inode(&self, node_id: &Self::NodeId) -> Node<Self>;
 
+    /// Resolve metadata of a node from its id.
+    fn get_metadata(&self, node_id: &Self::NodeId) -> NodeMetadata;
+
     /// Resolve a link from its id.
     fn get_link(&self, link_id: &Self::LinkId) -> Link<Self>;
 
-    /// Resolve a node from base node and relative path.
+    /// Resolve a node from base node and relative path. Symbolic links in
+    /// intermediate components are always followed, and the last component
+    /// is followed only if `follow_symlinks` is true.
     fn resolve_node(
         &self,
         base: Self::NodeId,
         base_link: Self::LinkId,
         path: &Path,
+        follow_symlinks: bool,
     ) -> Result<(Self::NodeId, Self::LinkId), wasi::Errno>;
 }
 
 pub(crate) enum Node<'a, S: Storage + ?Sized> {
     File(&'a S::NodeFileBody),
     Dir(&'a S::NodeDirBody),
+    Symlink(&'a S::NodeSymlinkBody),
 }
 
 /// Represent a hard link to an inode
@@ -100,10 +164,20 @@ pub(crate) struct FdEntry<S: Storage + ?Sized> {
     pub(crate) link_id: S::LinkId,
     pub(crate) node_id: S::NodeId,
     pub(crate) flags: wasi::Fdflags,
+    /// Content of a generated file produced when it's opened, so that reads
+    /// and seeks through the fd see a consistent content.
+    snapshot: Option<Vec<u8>>,
 }
 
+/// Produces the content of a generated file each time it's opened.
+pub(crate) type Generator = Arc<dyn Fn() -> Vec<u8> + Send + Sync>;
+
 pub(crate) struct PreopenedDir {
+    vfd: Vfd,
     pub(crate) path: String,
+    /// Removed by a later packing. Removed directories are not registered
+    /// as preopens at runtime, but keep their vfds.
+    pub(crate) removed: bool,
 }
 
 pub(crate) struct EmbeddedFs<S: Storage> {
@@ -112,6 +186,11 @@ pub(crate) struct EmbeddedFs<S: Storage> {
 
     opens: HashMap<Vfd, FdEntry<S>>,
     fd_issuer: IdIssuer<Vfd>,
+    /// Mount built-in device nodes at `/dev` at runtime
+    devices: bool,
+    /// Generators of files whose content is produced on open, keyed by the
+    /// inode number of their node. The nodes are empty files in the storage.
+    generators: HashMap<u64, Generator>,
 }
 
 #[derive(Default)]
@@ -149,13 +228,26 @@ impl<S: Storage> EmbeddedFs<S> {
             storage,
             opens: HashMap::new(),
             fd_issuer: IdIssuer::new(0_u32),
+            devices: false,
+            generators: HashMap::new(),
         }
     }
 
+    pub(crate) fn set_devices(&mut self, devices: bool) {
+        self.devices = devices;
+    }
+
+    pub(crate) fn has_devices(&self) -> bool {
+        self.devices
+    }
+
     pub(crate) fn preopen_dir(&mut self, path: String) -> (Vfd, S::NodeId, S::LinkId) {
-        assert!(self.preopened_dirs.len() == self.opens.len());
         let fd = self.fd_issuer.issue();
-        self.preopened_dirs.push(PreopenedDir { path });
+        self.preopened_dirs.push(PreopenedDir {
+            vfd: fd,
+            path,
+            removed: false,
+        });
         let (node_id, link_id) = self.storage.new_root_dir();
         self.opens.insert(
             fd,
@@ -164,42 +256,336 @@ impl<S: Storage> EmbeddedFs<S> {
                 node_id,
                 link_id,
                 flags: 0,
+                snapshot: None,
             },
         );
         (fd, node_id, link_id)
     }
 
     pub(crate) fn get_preopened_dir_path(&self, vfd: Vfd) -> Option<&str> {
-        let vfd = vfd as usize;
-        if vfd >= self.preopened_dirs.len() {
-            return None;
+        match self.preopened_dirs.iter().find(|dir| dir.vfd == vfd) {
+            Some(dir) if !dir.removed => Some(&dir.path),
+            _ => None,
         }
-        Some(&self.preopened_dirs[vfd].path)
     }
 
+    /// Returns the preopened directory containing the absolute guest `path`
+    /// with the longest prefix, and the path relative to it.
+    pub(crate) fn find_preopened_dir<'path>(&self, path: &'path str) -> Option<(Vfd, &'path str)> {
+        self.preopened_dirs
+            .iter()
+            .filter(|dir| !dir.removed)
+            .filter_map(|dir| {
+                let prefix = dir.path.trim_end_matches('/');
+                let relpath = path.strip_prefix(prefix)?;
+                if !relpath.is_empty() && !relpath.starts_with('/') {
+                    return None;
+                }
+                Some((prefix.len(), dir.vfd, relpath.trim_start_matches('/')))
+            })
+            .max_by_key(|(prefix_len, _, _)| *prefix_len)
+            .map(|(_, vfd, relpath)| (vfd, relpath))
+    }
+
+    /// Returns the root directory of the preopened directory.
+    pub(crate) fn get_preopened_dir_root(&self, vfd: Vfd) -> Option<(S::NodeId, S::LinkId)> {
+        self.get_preopened_dir_path(vfd)?;
+        let entry = self.opens.get(&vfd)?;
+        Some((entry.node_id, entry.link_id))
+    }
+
+    /// Removes all entries of the preopened directory and marks it removed.
+    pub(crate) fn remove_preopened_dir(&mut self, vfd: Vfd) {
+        let Some(root) = self.get_preopened_dir_root(vfd) else {
+            return;
+        };
+        let names = match self.storage.get_inode(&root.0) {
+            Node::Dir(body) => body.entries().map(|entry| entry.name).collect::<Vec<_>>(),
+            _ => vec![],
+        };
+        for name in names {
+            self.storage.remove_entry(root, &name);
+        }
+        for dir in self.preopened_dirs.iter_mut().filter(|dir| dir.vfd == vfd) {
+            dir.removed = true;
+        }
+    }
+
+    /// Creates a directory at `relpath`. Returns the existing directory if
+    /// it's already there.
     pub(crate) fn create_dir(
         &mut self,
         dir_node: S::NodeId,
         dir_link: S::LinkId,
         relpath: &str,
-    ) -> Result<(), u16> {
+    ) -> Result<(S::NodeId, S::LinkId), u16> {
         let (cursor, filename) = self.create_intermediate_dirs(dir_node, dir_link, relpath)?;
-        self.storage.new_dir(cursor, filename.to_string());
-        Ok(())
+        if let Some(existing) = self.lookup_entry(cursor.0, filename) {
+            match self.storage.get_inode(&existing.0) {
+                Node::Dir { .. } => return Ok(existing),
+                _ => {
+                    self.storage.remove_entry(cursor, filename);
+                }
+            }
+        }
+        Ok(self.storage.new_dir(cursor, filename.to_string()))
     }
 
+    /// Creates a file at `relpath`. An existing entry at the path is replaced.
     pub(crate) fn create_file(
         &mut self,
         dir_node: S::NodeId,
         dir_link: S::LinkId,
         relpath: &str,
         content: Vec<u8>,
+    ) -> Result<(S::NodeId, S::LinkId), u16> {
+        let (cursor, filename) = self.prepare_new_entry(dir_node, dir_link, relpath)?;
+        Ok(self.storage.new_file(cursor, filename.to_string(), content))
+    }
+
+    /// Creates a file at `relpath` in the preopened directory whose content
+    /// is produced by `generator` each time it's opened. An existing entry at
+    /// the path is replaced.
+    pub(crate) fn create_generated_file(
+        &mut self,
+        vfd: Vfd,
+        relpath: &str,
+        generator: Generator,
     ) -> Result<(), u16> {
-        let (cursor, filename) = self.create_intermediate_dirs(dir_node, dir_link, relpath)?;
-        self.storage.new_file(cursor, filename.to_string(), content);
+        let (dir_node, dir_link) = self
+            .get_preopened_dir_root(vfd)
+            .ok_or(wasi::ERRNO_BADF.raw())?;
+        if let Ok((existing, _)) =
+            self.storage
+                .resolve_node(dir_node, dir_link, Path::new(relpath), false)
+        {
+            self.generators.remove(&existing.ino());
+        }
+        let (node_id, _) = self.create_file(dir_node, dir_link, relpath, vec![])?;
+        self.generators.insert(node_id.ino(), generator);
+        Ok(())
+    }
+
+    pub(crate) fn is_generated(&self, node_id: S::NodeId) -> bool {
+        self.generators.contains_key(&node_id.ino())
+    }
+
+    /// Creates a symbolic link at `relpath`. An existing entry at the path is replaced.
+    pub(crate) fn create_symlink(
+        &mut self,
+        dir_node: S::NodeId,
+        dir_link: S::LinkId,
+        relpath: &str,
+        target: String,
+    ) -> Result<(S::NodeId, S::LinkId), u16> {
+        let (cursor, filename) = self.prepare_new_entry(dir_node, dir_link, relpath)?;
+        Ok(self
+            .storage
+            .new_symlink(cursor, filename.to_string(), target))
+    }
+
+    /// Creates a hard link at `relpath` to the existing non-directory entry at
+    /// `target_relpath`. Both paths are relative to the given directory.
+    pub(crate) fn create_hard_link(
+        &mut self,
+        dir_node: S::NodeId,
+        dir_link: S::LinkId,
+        relpath: &str,
+        target_relpath: &str,
+    ) -> Result<(S::NodeId, S::LinkId), u16> {
+        let target_relpath = target_relpath.trim_start_matches('/');
+        let (target, _) = self
+            .storage
+            .resolve_node(dir_node, dir_link, Path::new(target_relpath), false)
+            .map_err(|e| e.raw())?;
+        self.link_node(dir_node, dir_link, relpath, target)
+    }
+
+    /// Creates a hard link at `relpath` to the given non-directory node. An
+    /// existing entry at the path is replaced.
+    pub(crate) fn link_node(
+        &mut self,
+        dir_node: S::NodeId,
+        dir_link: S::LinkId,
+        relpath: &str,
+        target: S::NodeId,
+    ) -> Result<(S::NodeId, S::LinkId), u16> {
+        if let Node::Dir { .. } = self.storage.get_inode(&target) {
+            return Err(wasi::ERRNO_PERM.raw());
+        }
+        let (cursor, filename) = self.prepare_new_entry(dir_node, dir_link, relpath)?;
+        Ok(self
+            .storage
+            .new_hard_link(cursor, filename.to_string(), target))
+    }
+
+    /// Returns true if the node is a linked file with the given content and
+    /// modification time.
+    pub(crate) fn is_same_file(
+        &self,
+        node_id: S::NodeId,
+        content: &[u8],
+        mtime: wasi::Timestamp,
+    ) -> bool {
+        let metadata = self.storage.get_metadata(&node_id);
+        if metadata.nlink == 0 || metadata.mtime != mtime {
+            return false;
+        }
+        match self.storage.get_inode(&node_id) {
+            Node::File(body) => body.content() == content,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_mtime(&mut self, node_id: S::NodeId, mtime: wasi::Timestamp) {
+        self.storage.set_mtime(&node_id, mtime);
+    }
+
+    /// Places entries of an uncompressed tar archive under the directory at
+    /// `relpath`. The archive is served in place if the storage supports it,
+    /// otherwise it's unpacked into the storage.
+    pub(crate) fn create_archive(
+        &mut self,
+        dir_node: S::NodeId,
+        dir_link: S::LinkId,
+        relpath: &str,
+        archive: Vec<u8>,
+    ) -> Result<(), u16> {
+        let dir = if relpath.trim_matches('/').is_empty() {
+            (dir_node, dir_link)
+        } else {
+            self.create_dir(dir_node, dir_link, relpath)?
+        };
+        let archive = match self.storage.add_archive(dir, archive) {
+            Ok(()) => return Ok(()),
+            Err(archive) => archive,
+        };
+        for entry in tar::TarReader::new(&archive) {
+            if entry.path.is_empty() {
+                continue;
+            }
+            let (node_id, _) = match entry.kind {
+                tar::TarEntryKind::Dir => self.create_dir(dir.0, dir.1, &entry.path)?,
+                tar::TarEntryKind::File(content) => {
+                    self.create_file(dir.0, dir.1, &entry.path, content.to_vec())?
+                }
+                tar::TarEntryKind::Symlink(target) => {
+                    self.create_symlink(dir.0, dir.1, &entry.path, target)?
+                }
+                tar::TarEntryKind::HardLink(target) => {
+                    self.create_hard_link(dir.0, dir.1, &entry.path, &target)?;
+                    continue;
+                }
+            };
+            self.set_mtime(node_id, entry.mtime.saturating_mul(1_000_000_000));
+        }
         Ok(())
     }
 
+    /// Serialize all preopened trees into the tree stream format. Nodes
+    /// reachable from multiple paths are written as hard links.
+    pub(crate) fn write_tree(&self, writer: &mut stream::Writer) {
+        let mut visited = HashMap::new();
+        for dir in &self.preopened_dirs {
+            if dir.removed {
+                continue;
+            }
+            writer.write(&Record::Mount {
+                guest_dir: &dir.path,
+            });
+            let root = &self.opens[&dir.vfd];
+            self.write_dir(writer, root.node_id, "", &mut visited);
+        }
+    }
+
+    fn write_dir(
+        &self,
+        writer: &mut stream::Writer,
+        dir_node: S::NodeId,
+        prefix: &str,
+        visited: &mut HashMap<u64, String>,
+    ) {
+        let entries = match self.storage.get_inode(&dir_node) {
+            Node::Dir(body) => body.entries(),
+            _ => return,
+        };
+        for entry in entries {
+            let path = format!("{}/{}", prefix, entry.name);
+            let node_id = self.storage.get_link(&entry.link_id).node;
+            if self.is_generated(node_id) {
+                // Generators are code in the module, not contents to dump
+                continue;
+            }
+            let metadata = self.storage.get_metadata(&node_id);
+            match self.storage.get_inode(&node_id) {
+                Node::Dir(_) => {
+                    writer.write(&Record::Dir {
+                        path: &path,
+                        mtime: metadata.mtime,
+                    });
+                    self.write_dir(writer, node_id, &path, visited);
+                    continue;
+                }
+                _ if metadata.nlink > 1 => {
+                    if let Some(target) = visited.get(&node_id.ino()) {
+                        writer.write(&Record::HardLink {
+                            path: &path,
+                            target,
+                        });
+                        continue;
+                    }
+                    visited.insert(node_id.ino(), path.clone());
+                }
+                _ => {}
+            }
+            match self.storage.get_inode(&node_id) {
+                Node::File(body) => writer.write(&Record::File {
+                    path: &path,
+                    mtime: metadata.mtime,
+                    content: body.content(),
+                }),
+                Node::Symlink(body) => writer.write(&Record::Symlink {
+                    path: &path,
+                    mtime: metadata.mtime,
+                    target: body.target(),
+                }),
+                Node::Dir(_) => unreachable!(),
+            }
+        }
+    }
+
+    /// Prepare the storage to serve the file system at runtime.
+    pub(crate) fn prepare(&mut self) {
+        self.storage.prepare();
+    }
+
+    /// Creates intermediate directories of `relpath` and removes an existing
+    /// entry at the path to be replaced with a new one.
+    fn prepare_new_entry<'path>(
+        &mut self,
+        dir_node: S::NodeId,
+        dir_link: S::LinkId,
+        relpath: &'path str,
+    ) -> Result<((S::NodeId, S::LinkId), &'path str), u16> {
+        let (cursor, filename) = self.create_intermediate_dirs(dir_node, dir_link, relpath)?;
+        if filename.is_empty() || filename == "." || filename == ".." {
+            return Err(wasi::ERRNO_INVAL.raw());
+        }
+        self.storage.remove_entry(cursor, filename);
+        Ok((cursor, filename))
+    }
+
+    fn lookup_entry(&self, dir_node: S::NodeId, name: &str) -> Option<(S::NodeId, S::LinkId)> {
+        let entries = match self.storage.get_inode(&dir_node) {
+            Node::Dir(body) => body.entries(),
+            _ => return None,
+        };
+        entries
+            .into_iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| (self.storage.get_link(&entry.link_id).node, entry.link_id))
+    }
+
     fn create_intermediate_dirs<'path>(
         &mut self,
         base_node: S::NodeId,
@@ -232,7 +618,10 @@ impl<S: Storage> EmbeddedFs<S> {
             for entry in entries {
                 if component == entry.name {
                     cursor = (self.storage.get_link(&entry.link_id).node, entry.link_id);
-                    continue 'find_parent_node;
+                    if let Node::Dir { .. } = self.storage.get_inode(&cursor.0) {
+                        continue 'find_parent_node;
+                    }
+                    return Err(wasi::ERRNO_NOTDIR.raw());
                 }
             }
             // create a new intermediate directory
@@ -248,6 +637,16 @@ impl<S: Storage> EmbeddedFs<S> {
         self.storage.get_link(&id).node
     }
 
+    /// Returns the node `..` refers to from an opened dir, which is the dir
+    /// itself at the root.
+    pub(crate) fn get_parent_node_id(&self, fd: Vfd) -> Result<S::NodeId, wasi::Errno> {
+        let entry = self.get_fd_entry(fd)?;
+        Ok(match self.storage.get_link(&entry.link_id).parent {
+            Some(parent) => self.storage.get_link(&parent).node,
+            None => entry.node_id,
+        })
+    }
+
     pub(crate) fn get_node(&self, fd: Vfd) -> Result<Node<S>, wasi::Errno> {
         match self.opens.get(&fd) {
             Some(entry) => Ok(self.storage.get_inode(&entry.node_id)),
@@ -278,6 +677,12 @@ impl<S: Storage> EmbeddedFs<S> {
                 fs_rights_base: READ_ONLY_RIGHTS,
                 fs_rights_inheriting: READ_ONLY_RIGHTS,
             },
+            Node::Symlink { .. } => wasi::Fdstat {
+                fs_filetype: wasi::FILETYPE_SYMBOLIC_LINK,
+                fs_flags: entry.flags,
+                fs_rights_base: READ_ONLY_RIGHTS,
+                fs_rights_inheriting: READ_ONLY_RIGHTS,
+            },
         })
     }
 
@@ -293,6 +698,11 @@ impl<S: Storage> EmbeddedFs<S> {
             ctim: Default::default(),
         };
         stat.ino = node_id.ino();
+        let metadata = self.storage.get_metadata(&node_id);
+        stat.nlink = metadata.nlink;
+        stat.atim = metadata.mtime;
+        stat.mtim = metadata.mtime;
+        stat.ctim = metadata.mtime;
         match self.storage.get_inode(&node_id) {
             Node::File(body) => {
                 stat.filetype = wasi::FILETYPE_REGULAR_FILE;
@@ -303,6 +713,11 @@ impl<S: Storage> EmbeddedFs<S> {
                 stat.filetype = wasi::FILETYPE_DIRECTORY;
                 stat
             }
+            Node::Symlink(body) => {
+                stat.filetype = wasi::FILETYPE_SYMBOLIC_LINK;
+                stat.size = body.target().len() as u64;
+                stat
+            }
         }
     }
 
@@ -331,12 +746,17 @@ impl<S: Storage> EmbeddedFs<S> {
         &mut self,
         base: Vfd,
         path: &Path,
+        follow_symlinks: bool,
         fdflags: wasi::Fdflags,
     ) -> Result<Vfd, wasi::Errno> {
         let base = &self.opens[&base];
-        let (node_id, link_id) = self
-            .storage
-            .resolve_node(base.node_id, base.link_id, path)?;
+        let (node_id, link_id) =
+            self.storage
+                .resolve_node(base.node_id, base.link_id, path, follow_symlinks)?;
+        if let Node::Symlink { .. } = self.storage.get_inode(&node_id) {
+            // Opening a symbolic link itself is not supported like O_NOFOLLOW
+            return Err(wasi::ERRNO_LOOP);
+        }
         let new_fd = self.fd_issuer.issue();
         self.opens.insert(
             new_fd,
@@ -345,30 +765,80 @@ impl<S: Storage> EmbeddedFs<S> {
                 node_id,
                 link_id,
                 flags: fdflags,
+                snapshot: None,
             },
         );
         Ok(new_fd)
     }
 
+    /// Returns the generator of the file opened at `fd`, if it's a generated
+    /// file. The caller runs it without the file system locked and installs
+    /// the content with `set_snapshot`.
+    pub(crate) fn get_generator(&self, fd: Vfd) -> Result<Option<Generator>, wasi::Errno> {
+        let entry = self.get_fd_entry(fd)?;
+        Ok(self.generators.get(&entry.node_id.ino()).cloned())
+    }
+
+    pub(crate) fn set_snapshot(&mut self, fd: Vfd, content: Vec<u8>) -> Result<(), wasi::Errno> {
+        let entry = self.get_fd_entry_mut(fd)?;
+        entry.snapshot = Some(content);
+        Ok(())
+    }
+
+    /// Returns the content of the file opened at `fd`. Generated files
+    /// return the content produced when they were opened.
+    pub(crate) fn get_content(&self, fd: Vfd) -> Result<&[u8], wasi::Errno> {
+        let entry = self.get_fd_entry(fd)?;
+        if let Some(snapshot) = &entry.snapshot {
+            return Ok(snapshot);
+        }
+        match self.storage.get_inode(&entry.node_id) {
+            Node::File(body) => Ok(body.content()),
+            Node::Dir { .. } => Err(wasi::ERRNO_ISDIR),
+            Node::Symlink { .. } => Err(wasi::ERRNO_INVAL),
+        }
+    }
+
+    pub(crate) fn get_fd_filestat(&self, fd: Vfd) -> Result<wasi::Filestat, wasi::Errno> {
+        let entry = self.get_fd_entry(fd)?;
+        let mut stat = self.get_filestat_from_node_id(entry.node_id);
+        if let Some(snapshot) = &entry.snapshot {
+            stat.size = snapshot.len() as u64;
+        }
+        Ok(stat)
+    }
+
     pub(crate) fn get_filestat_at_path(
         &self,
         base: Vfd,
         path: &Path,
+        follow_symlinks: bool,
     ) -> Result<wasi::Filestat, wasi::Errno> {
         let base = &self.opens[&base];
-        let (node_id, _) = self
-            .storage
-            .resolve_node(base.node_id, base.link_id, path)?;
+        let (node_id, _) =
+            self.storage
+                .resolve_node(base.node_id, base.link_id, path, follow_symlinks)?;
         let res = self.get_filestat_from_node_id(node_id);
         Ok(res)
     }
+
+    pub(crate) fn read_link(&self, base: Vfd, path: &Path) -> Result<&str, wasi::Errno> {
+        let base = self.opens.get(&base).ok_or(wasi::ERRNO_BADF)?;
+        let (node_id, _) = self
+            .storage
+            .resolve_node(base.node_id, base.link_id, path, false)?;
+        match self.storage.get_inode(&node_id) {
+            Node::Symlink(body) => Ok(body.target()),
+            _ => Err(wasi::ERRNO_INVAL),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::path::Path;
 
-    use super::{EmbeddedFs, LinkedStorage};
+    use super::{EmbeddedFs, LinkedStorage, Node, NodeFileBody, Storage};
 
     #[test]
     fn test_embedded_node_create_file() {
@@ -383,7 +853,144 @@ mod tests {
     fn test_get_filestat_at_path_for_non_existing() {
         let mut fs = EmbeddedFs::<LinkedStorage>::default();
         let (vfd, _, _) = fs.preopen_dir("/".to_string());
-        let result = fs.get_filestat_at_path(vfd, Path::new("/not-exist"));
+        let result = fs.get_filestat_at_path(vfd, Path::new("/not-exist"), true);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_symlink_and_hard_link() {
+        let mut fs = EmbeddedFs::<LinkedStorage>::default();
+        let (vfd, node_id, link_id) = fs.preopen_dir("/".to_string());
+        fs.create_file(node_id, link_id, "dir/hello.txt", b"Hello".to_vec())
+            .unwrap();
+        fs.create_symlink(node_id, link_id, "link", "dir/hello.txt".to_string())
+            .unwrap();
+        fs.create_hard_link(node_id, link_id, "hard", "dir/hello.txt")
+            .unwrap();
+
+        let stat = fs
+            .get_filestat_at_path(vfd, Path::new("link"), true)
+            .unwrap();
+        assert_eq!(stat.filetype, wasi::FILETYPE_REGULAR_FILE);
+        assert_eq!(stat.nlink, 2);
+        let stat = fs
+            .get_filestat_at_path(vfd, Path::new("link"), false)
+            .unwrap();
+        assert_eq!(stat.filetype, wasi::FILETYPE_SYMBOLIC_LINK);
+        assert_eq!(
+            fs.read_link(vfd, Path::new("link")).unwrap(),
+            "dir/hello.txt"
+        );
+        let stat = fs
+            .get_filestat_at_path(vfd, Path::new("hard"), true)
+            .unwrap();
+        assert_eq!(stat.size, 5);
+    }
+
+    #[test]
+    fn test_generated_file() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut fs = EmbeddedFs::<LinkedStorage>::default();
+        fs.preopen_dir("/".to_string());
+        let (vfd, _, _) = fs.preopen_dir("/proc/".to_string());
+        assert_eq!(
+            fs.find_preopened_dir("/proc/self/count"),
+            Some((vfd, "self/count"))
+        );
+        assert_eq!(
+            fs.find_preopened_dir("/process").map(|(_, p)| p),
+            Some("process")
+        );
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let generator = Arc::new(move || {
+            let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            count.to_string().into_bytes()
+        });
+        fs.create_generated_file(vfd, "self/count", generator)
+            .unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+
+        // Each open takes its own snapshot
+        let open = |fs: &mut EmbeddedFs<LinkedStorage>| {
+            let new_vfd = fs.open_file(vfd, Path::new("self/count"), true, 0).unwrap();
+            let generate = fs.get_generator(new_vfd).unwrap().unwrap();
+            fs.set_snapshot(new_vfd, generate()).unwrap();
+            new_vfd
+        };
+        let first = open(&mut fs);
+        let second = open(&mut fs);
+        assert_eq!(fs.get_content(first).unwrap(), b"1");
+        assert_eq!(fs.get_content(second).unwrap(), b"2");
+        assert_eq!(fs.get_fd_filestat(first).unwrap().size, 1);
+
+        let mut writer = crate::stream::Writer::new();
+        fs.write_tree(&mut writer);
+        let stream = writer.finish();
+        let mut reader = crate::stream::Reader::new(&stream).unwrap();
+        while let Some(record) = reader.next_record().unwrap() {
+            assert_ne!(record.path(), "/self/count");
+        }
+    }
+
+    #[test]
+    fn test_remove_preopened_dir() {
+        let mut fs = EmbeddedFs::<LinkedStorage>::default();
+        let (vfd, node_id, link_id) = fs.preopen_dir("/mnt".to_string());
+        let (file_id, _) = fs
+            .create_file(node_id, link_id, "dir/hello.txt", b"Hello".to_vec())
+            .unwrap();
+        fs.remove_preopened_dir(vfd);
+
+        assert!(fs.get_preopened_dir_path(vfd).is_none());
+        // The content doesn't survive in memory
+        match fs.storage.get_inode(&file_id) {
+            Node::File(body) => assert_eq!(body.content(), b"\0\0\0\0\0"),
+            _ => panic!("expected a file"),
+        }
+        let mut writer = crate::stream::Writer::new();
+        fs.write_tree(&mut writer);
+        let stream = writer.finish();
+        let mut reader = crate::stream::Reader::new(&stream).unwrap();
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_write_tree() {
+        use crate::stream::{Reader, Record, Writer};
+
+        let mut fs = EmbeddedFs::<LinkedStorage>::default();
+        let (_, node_id, link_id) = fs.preopen_dir("/mnt".to_string());
+        fs.create_file(node_id, link_id, "dir/hello.txt", b"Hello".to_vec())
+            .unwrap();
+        fs.create_hard_link(node_id, link_id, "hard", "dir/hello.txt")
+            .unwrap();
+
+        let mut writer = Writer::new();
+        fs.write_tree(&mut writer);
+        let stream = writer.finish();
+        let mut reader = Reader::new(&stream).unwrap();
+        let mut records = vec![];
+        while let Some(record) = reader.next_record().unwrap() {
+            let kind = match record {
+                Record::Mount { .. } => "mount",
+                Record::Dir { .. } => "dir",
+                Record::File { content, .. } => {
+                    assert_eq!(content, b"Hello");
+                    "file"
+                }
+                Record::HardLink { .. } => "hard link",
+                _ => "other",
+            };
+            records.push((kind, record.path().to_string()));
+        }
+        // Entries are written in the storage order
+        assert_eq!(records[0], ("mount", "".to_string()));
+        assert_eq!(records.len(), 4);
+        assert!(records.contains(&("dir", "/dir".to_string())));
+        assert!(records.iter().any(|(kind, _)| *kind == "hard link"));
+    }
 }
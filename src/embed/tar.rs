@@ -0,0 +1,233 @@
+//! A minimal reader of uncompressed tar archives (ustar, GNU and PAX).
+
+/// Size of a tar header and the unit of data blocks.
+const BLOCK_SIZE: usize = 512;
+
+pub(crate) enum TarEntryKind<'a> {
+    File(&'a [u8]),
+    Dir,
+    Symlink(String),
+    /// Hard link to a previous entry, normalized like entry paths.
+    HardLink(String),
+}
+
+pub(crate) struct TarEntry<'a> {
+    /// Path relative to the archive root normalized to start with `/`.
+    pub(crate) path: String,
+    pub(crate) kind: TarEntryKind<'a>,
+    /// Modification time in seconds since the epoch.
+    pub(crate) mtime: u64,
+}
+
+/// Iterates entries of a tar archive. Entries of unsupported types or
+/// escaping the archive root are skipped. The iteration stops at the end
+/// marker or at the first malformed header.
+pub(crate) struct TarReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> TarReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        TarReader { data, offset: 0 }
+    }
+
+    fn next_block(&mut self, size: usize) -> Option<&'a [u8]> {
+        let end = self.offset.checked_add(size)?;
+        let block = self.data.get(self.offset..end)?;
+        let padded = size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+        self.offset = self.offset.checked_add(padded)?;
+        Some(block)
+    }
+}
+
+#[derive(Default)]
+struct Overrides {
+    path: Option<String>,
+    link_path: Option<String>,
+    mtime: Option<u64>,
+    size: Option<usize>,
+}
+
+impl<'a> Iterator for TarReader<'a> {
+    type Item = TarEntry<'a>;
+
+    fn next(&mut self) -> Option<TarEntry<'a>> {
+        let mut overrides = Overrides::default();
+        loop {
+            let header = self.next_block(BLOCK_SIZE)?;
+            if header.iter().all(|b| *b == 0) {
+                return None;
+            }
+            let size = match overrides.size.take() {
+                Some(size) => size,
+                None => parse_number(&header[124..136])? as usize,
+            };
+            let data = self.next_block(size)?;
+            let typeflag = header[156];
+            match typeflag {
+                // GNU long name and long link name for the next entry
+                b'L' => {
+                    overrides.path = Some(c_string(data));
+                    continue;
+                }
+                b'K' => {
+                    overrides.link_path = Some(c_string(data));
+                    continue;
+                }
+                b'x' => {
+                    parse_pax_records(data, &mut overrides);
+                    continue;
+                }
+                _ => {}
+            }
+
+            let raw_path = overrides.path.take().unwrap_or_else(|| {
+                let name = c_string(&header[0..100]);
+                let prefix = if &header[257..262] == b"ustar" {
+                    c_string(&header[345..500])
+                } else {
+                    String::new()
+                };
+                if prefix.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", prefix, name)
+                }
+            });
+            let raw_link_path = overrides
+                .link_path
+                .take()
+                .unwrap_or_else(|| c_string(&header[157..257]));
+            let mtime = match overrides.mtime.take() {
+                Some(mtime) => mtime,
+                None => parse_number(&header[136..148]).unwrap_or(0),
+            };
+            let path = match normalize_path(&raw_path) {
+                Some(path) => path,
+                None => continue,
+            };
+            let kind = match typeflag {
+                b'0' | b'\0' | b'7' => TarEntryKind::File(data),
+                b'5' => TarEntryKind::Dir,
+                b'2' => TarEntryKind::Symlink(raw_link_path),
+                b'1' => match normalize_path(&raw_link_path) {
+                    Some(target) => TarEntryKind::HardLink(target),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            return Some(TarEntry { path, kind, mtime });
+        }
+    }
+}
+
+/// Parse a numeric header field in octal or GNU base-256 encoding.
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut value = (field[0] & 0x7f) as u64;
+        for b in &field[1..] {
+            value = value.checked_mul(256)?.checked_add(*b as u64)?;
+        }
+        return Some(value);
+    }
+    let mut value: u64 = 0;
+    for b in field {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)?.checked_add((b - b'0') as u64)?,
+            b' ' | b'\0' if value == 0 => continue,
+            b' ' | b'\0' => break,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+/// Parse PAX extended header records in the form of `<len> <key>=<value>\n`.
+fn parse_pax_records(mut data: &[u8], overrides: &mut Overrides) {
+    while let Some(space) = data.iter().position(|b| *b == b' ') {
+        let len = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok());
+        let record = match len.and_then(|len| data.get(space + 1..len)) {
+            Some(record) => record,
+            None => return,
+        };
+        data = &data[len.unwrap()..];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        let Some(eq) = record.iter().position(|b| *b == b'=') else {
+            continue;
+        };
+        let value = String::from_utf8_lossy(&record[eq + 1..]).into_owned();
+        match &record[..eq] {
+            b"path" => overrides.path = Some(value),
+            b"linkpath" => overrides.link_path = Some(value),
+            // fractional seconds are truncated
+            b"mtime" => overrides.mtime = value.split('.').next().and_then(|s| s.parse().ok()),
+            b"size" => overrides.size = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+fn c_string(field: &[u8]) -> String {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+/// Normalize a path in an archive to a `/`-prefixed path relative to the
+/// archive root, or `None` if it escapes the root. The archive root itself
+/// is normalized to an empty string.
+fn normalize_path(path: &str) -> Option<String> {
+    let mut normalized = String::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return None,
+            name => {
+                normalized.push('/');
+                normalized.push_str(name);
+            }
+        }
+    }
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TarEntryKind, TarReader};
+
+    fn header(name: &str, typeflag: u8, size: usize, link: &str) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", size).as_bytes());
+        header[136..147].copy_from_slice(format!("{:011o}", 1000000000).as_bytes());
+        header[156] = typeflag;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    #[test]
+    fn test_read_tar_entries() {
+        let mut archive = header("./", b'5', 0, "");
+        archive.extend(header("./dir/hello.txt", b'0', 5, ""));
+        archive.extend(b"Hello");
+        archive.extend(vec![0; 512 - 5]);
+        archive.extend(header("./dir/link", b'2', 0, "hello.txt"));
+        archive.extend(header("./dir/hard", b'1', 0, "./dir/hello.txt"));
+        archive.extend(header("../escape", b'0', 0, ""));
+        archive.extend(vec![0; 1024]);
+
+        let entries = TarReader::new(&archive).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].path, "");
+        assert!(matches!(entries[1].kind, TarEntryKind::File(b"Hello")));
+        assert_eq!(entries[1].path, "/dir/hello.txt");
+        assert_eq!(entries[1].mtime, 1000000000);
+        assert!(matches!(&entries[2].kind, TarEntryKind::Symlink(target) if target == "hello.txt"));
+        assert!(
+            matches!(&entries[3].kind, TarEntryKind::HardLink(target) if target == "/dir/hello.txt")
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Files whose content is produced by the application each time they are
+//! opened, like `/proc/self/environ`. Generators are registered at runtime
+//! through [`register_generated_file`] or the C API below.
+
+use std::ffi::{CString, c_char, c_void};
+use std::sync::Arc;
+
+use crate::{
+    __wasilibc_register_preopened_fd, Error, FileSystem,
+    embed::{EmbeddedFs, Generator},
+    lock_global_state,
+};
+
+/// Called with `userdata` given at registration to produce the content by
+/// passing `out` to `wasi_vfs_generated_file_write`.
+type GenerateFn = unsafe extern "C" fn(userdata: *mut c_void, out: *mut c_void);
+
+/// `userdata` given at registration. It's passed to the generator from
+/// whichever thread opens the file, as documented in the C API.
+struct Userdata(*mut c_void);
+
+// SAFETY: Synchronizing accesses through the pointer is up to the application.
+unsafe impl Send for Userdata {}
+unsafe impl Sync for Userdata {}
+
+impl Userdata {
+    fn get(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// Register a file at the absolute guest `path` whose content is appended to
+/// the given buffer by `generate` each time it's opened. A directory is
+/// mounted at the top-level directory of the path if no packed directory
+/// contains it. Register files at startup before opening other files.
+///
+/// `generate` is called from whichever thread opens the file, and it may
+/// access files and stdio. `stat` on the path reports a size of 0 without
+/// running it, while `fstat` on an open file reports the size of its content.
+///
+/// ```no_run
+/// wasi_vfs::register_generated_file("/proc/self/cmdline", |out| {
+///     for arg in std::env::args() {
+///         out.extend_from_slice(arg.as_bytes());
+///         out.push(0);
+///     }
+/// })
+/// .expect("failed to register /proc/self/cmdline");
+/// ```
+pub fn register_generated_file(
+    path: &str,
+    generate: impl Fn(&mut Vec<u8>) + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let generator: Generator = Arc::new(move || {
+        let mut content = Vec::new();
+        generate(&mut content);
+        content
+    });
+    // WASI errno values are the raw OS errors of wasi-libc
+    register(path, generator).map_err(|err| std::io::Error::from_raw_os_error(err.raw() as i32))
+}
+
+fn register(path: &str, generator: Generator) -> Result<(), Error> {
+    let mut state = lock_global_state();
+    if state.overlay_fs.is_none() {
+        // Nothing is packed, so the overlay is activated here. Files opened
+        // before that are unknown to it, so generated files are expected to
+        // be registered at startup.
+        drop(state);
+        let fs = FileSystem::create(EmbeddedFs::default(), &[]);
+        state = lock_global_state();
+        state.overlay_fs.get_or_insert(fs);
+    }
+    let fs = state.overlay_fs.as_mut().unwrap();
+    let new_mount = fs.create_generated_file(path, generator)?;
+    // Not locked while registering preopens to wasi-libc, which may call
+    // hooked functions.
+    drop(state);
+    if let Some((fd, prefix)) = new_mount {
+        let prefix = CString::new(prefix).unwrap();
+        unsafe {
+            __wasilibc_register_preopened_fd(fd as i32, prefix.as_ptr() as *const u8);
+        }
+    }
+    Ok(())
+}
+
+/// Register a file at the absolute guest `path` whose content is produced by
+/// `generate` each time it's opened. Returns 0 on success or a WASI errno.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wasi_vfs_register_generated_file(
+    path: *const c_char,
+    generate: GenerateFn,
+    userdata: *mut c_void,
+) -> i32 {
+    let path = unsafe { std::ffi::CStr::from_ptr(path) };
+    let Ok(path) = path.to_str() else {
+        return wasi::ERRNO_ILSEQ.raw() as i32;
+    };
+    let userdata = Userdata(userdata);
+    let generator: Generator = Arc::new(move || {
+        let mut content = Vec::new();
+        unsafe { generate(userdata.get(), &mut content as *mut Vec<u8> as *mut c_void) };
+        content
+    });
+    match register(path, generator) {
+        Ok(()) => 0,
+        Err(err) => err.raw() as i32,
+    }
+}
+
+/// Append `len` bytes at `data` to the content being generated.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn wasi_vfs_generated_file_write(
+    out: *mut c_void,
+    data: *const c_void,
+    len: usize,
+) {
+    let content = unsafe { &mut *(out as *mut Vec<u8>) };
+    let data = unsafe { std::slice::from_raw_parts(data as *const u8, len) };
+    content.extend_from_slice(data);
+}
@@ -9,6 +9,12 @@ void export_wasi_vfs_pack_fs(void) {
   __internal_wasi_vfs_pack_fs();
 }
 
+__attribute__((export_name("wasi_vfs_dump_fs")))
+void export_wasi_vfs_dump_fs(void) {
+  extern void __internal_wasi_vfs_dump_fs(void);
+  __internal_wasi_vfs_dump_fs();
+}
+
 __attribute__((export_name("__wasi_vfs_rt_init")))
 void __wasi_vfs_rt_init(void) {
   extern void __internal_wasi_vfs_rt_init(void);
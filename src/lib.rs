@@ -1,18 +1,35 @@
 //! wasi-vfs is a virtual file system compatible with WASI.
 
 mod alloc;
+mod control;
+mod dev;
 mod embed;
+#[cfg(feature = "trace-syscall")]
+mod errno_generated;
+mod generated;
+#[cfg(not(target_arch = "wasm32"))]
+mod mock_wasi;
+mod pack;
+mod stream;
 mod trace;
 mod trampoline_generated;
 #[allow(unused_variables)]
 mod wasi_snapshot_preview1;
 
+#[cfg(feature = "archive-storage")]
+use embed::ArchiveStorage as DefaultStorage;
+#[cfg(not(feature = "archive-storage"))]
 use embed::LinkedStorage as DefaultStorage;
-use embed::{EmbeddedFs, NodeIdTrait, Storage};
+use embed::{EmbeddedFs, Generator, Storage};
+use pack::FsPacker;
+
+pub use generated::register_generated_file;
 
 use std::{
     collections::HashMap,
     ffi::{CStr, CString},
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard},
 };
 use wasi::Fd;
 
@@ -28,6 +45,8 @@ pub(crate) enum BackingFd {
     Virtual(Vfd),
     /// File descriptor managed by a real WASI implementation
     Wasi(Fd),
+    /// Built-in device node under `/dev`
+    Device(dev::Device),
 }
 
 /// Map of user-facing file descriptors to internal file descriptors.
@@ -40,7 +59,8 @@ struct FileSystem<S: Storage> {
 }
 
 impl<S: Storage> FileSystem<S> {
-    fn create(embedded_fs: EmbeddedFs<S>, preopened_vfds: &[Vfd]) -> Self {
+    fn create(mut embedded_fs: EmbeddedFs<S>, preopened_vfds: &[Vfd]) -> Self {
+        embedded_fs.prepare();
         let mut fs = FileSystem {
             embedded_fs,
             fd_map: FdMap::new(),
@@ -51,10 +71,32 @@ impl<S: Storage> FileSystem<S> {
             fs.set_user_fd_at(BackingFd::Wasi(fd), fd);
         }
 
-        unsafe extern "C" {
-            fn __wasilibc_register_preopened_fd(fd: i32, name: *const u8) -> i32;
+        fs.register_host_preopens();
+        for vfd in preopened_vfds {
+            let vfd = *vfd;
+            let fd = fs.next_fd;
+            fs.next_fd += 1;
+            fs.fd_map.insert(fd, BackingFd::Virtual(vfd));
+            if let Some(prefix) = fs.embedded_fs.get_preopened_dir_path(vfd) {
+                let prefix = CString::new(prefix).unwrap();
+                unsafe {
+                    __wasilibc_register_preopened_fd(fd as i32, prefix.as_ptr() as *const u8);
+                }
+            }
         }
+        if fs.embedded_fs.has_devices() || dev::enabled_by_env() {
+            let fd = fs.issue_user_fd(BackingFd::Device(dev::Device::Dir));
+            let prefix = CString::new(dev::DEV_DIR).unwrap();
+            unsafe {
+                __wasilibc_register_preopened_fd(fd as i32, prefix.as_ptr() as *const u8);
+            }
+        }
+        fs
+    }
 
+    /// Issue user fds for directories preopened by the WASI implementation
+    /// and register them to wasi-libc.
+    fn register_host_preopens(&mut self) {
         for fd in 3.. {
             unsafe {
                 let prestat = match wasi::fd_prestat_get(fd) {
@@ -78,21 +120,8 @@ impl<S: Storage> FileSystem<S> {
                 }
                 __wasilibc_register_preopened_fd(fd as i32, prefix.as_ptr());
             }
-            fs.issue_user_fd(BackingFd::Wasi(fd));
+            self.issue_user_fd(BackingFd::Wasi(fd));
         }
-        for vfd in preopened_vfds {
-            let vfd = *vfd;
-            let fd = fs.next_fd;
-            fs.next_fd += 1;
-            fs.fd_map.insert(fd, BackingFd::Virtual(vfd));
-            if let Some(prefix) = fs.embedded_fs.get_preopened_dir_path(vfd) {
-                let prefix = CString::new(prefix).unwrap();
-                unsafe {
-                    __wasilibc_register_preopened_fd(fd as i32, prefix.as_ptr() as *const u8);
-                }
-            }
-        }
-        fs
     }
 
     fn set_user_fd_at(&mut self, backing_fd: BackingFd, fd: UserFd) {
@@ -111,6 +140,38 @@ impl<S: Storage> FileSystem<S> {
             None => Err(wasi::ERRNO_BADF.into()),
         }
     }
+
+    /// Create a generated file at the absolute guest `path`. If no virtual
+    /// directory contains it, a new one is mounted at the top-level directory
+    /// of the path. Returns the user fd and path of the new directory, which
+    /// have to be registered to wasi-libc.
+    fn create_generated_file(
+        &mut self,
+        path: &str,
+        generator: Generator,
+    ) -> Result<Option<(UserFd, String)>, Error> {
+        if !path.starts_with('/') {
+            return Err(wasi::ERRNO_INVAL.into());
+        }
+        let mut new_mount = None;
+        let (vfd, relpath) = match self.embedded_fs.find_preopened_dir(path) {
+            Some(found) => found,
+            None => {
+                let (top, relpath) = match path[1..].split_once('/') {
+                    Some((top, relpath)) => (format!("/{}", top), relpath),
+                    None => ("/".to_string(), &path[1..]),
+                };
+                let (vfd, _, _) = self.embedded_fs.preopen_dir(top.clone());
+                let fd = self.issue_user_fd(BackingFd::Virtual(vfd));
+                new_mount = Some((fd, top));
+                (vfd, relpath)
+            }
+        };
+        self.embedded_fs
+            .create_generated_file(vfd, relpath, generator)
+            .map_err(Error)?;
+        Ok(new_mount)
+    }
 }
 
 struct GlobalState<S: Storage> {
@@ -118,10 +179,88 @@ struct GlobalState<S: Storage> {
     overlay_fs: Option<FileSystem<S>>,
 }
 
-static mut GLOBAL_STATE: GlobalState<DefaultStorage> = GlobalState {
+// SAFETY: Storages point to embedded contents in the linear memory, which are
+// never freed and are shared by all threads. The state itself is only accessed
+// under the `GLOBAL_STATE` lock.
+unsafe impl<S: Storage> Send for GlobalState<S> {}
+
+unsafe extern "C" {
+    fn __wasilibc_register_preopened_fd(fd: i32, name: *const u8) -> i32;
+}
+
+/// Modules built for `wasm32-wasip1-threads` call into wasi-vfs from several
+/// threads sharing the linear memory, so the state is behind a lock.
+static GLOBAL_STATE: Mutex<GlobalState<DefaultStorage>> = Mutex::new(GlobalState {
     embedded_fs: None,
     overlay_fs: None,
-};
+});
+
+fn lock_global_state() -> MutexGuard<'static, GlobalState<DefaultStorage>> {
+    lock_state(&GLOBAL_STATE)
+}
+
+fn lock_state<S: Storage>(mutex: &Mutex<GlobalState<S>>) -> MutexGuard<'_, GlobalState<S>> {
+    match mutex.lock() {
+        Ok(state) => state,
+        Err(_) => {
+            // A thread panicked while holding the lock, possibly in the
+            // middle of updating fd tables, so the state can't be trusted
+            trace::eprint("wasi-vfs: the file system state is poisoned\n".to_string());
+            std::process::abort()
+        }
+    }
+}
+
+/// The active overlay file system, locked for the duration of a hooked call.
+/// Drop it before blocking on the host to let other threads in.
+pub(crate) struct OverlayFs<'a, S: Storage> {
+    mutex: &'a Mutex<GlobalState<S>>,
+    state: MutexGuard<'a, GlobalState<S>>,
+}
+
+impl OverlayFs<'static, DefaultStorage> {
+    /// Lock the global state if the overlay file system is active.
+    pub(crate) fn lock() -> Option<Self> {
+        Self::lock_in(&GLOBAL_STATE)
+    }
+}
+
+impl<'a, S: Storage> OverlayFs<'a, S> {
+    fn lock_in(mutex: &'a Mutex<GlobalState<S>>) -> Option<Self> {
+        let state = lock_state(mutex);
+        if state.overlay_fs.is_some() {
+            Some(OverlayFs { mutex, state })
+        } else {
+            None
+        }
+    }
+
+    /// Release the lock while running `f`, e.g. to call application code
+    /// which may access files, and lock again. Other threads may change the
+    /// file system meanwhile.
+    pub(crate) fn unlocked<R>(self, f: impl FnOnce() -> R) -> (Self, R) {
+        let mutex = self.mutex;
+        drop(self);
+        let result = f();
+        // The overlay file system is never deactivated once it's active
+        let fs = Self::lock_in(mutex).expect("overlay file system is active");
+        (fs, result)
+    }
+}
+
+impl<S: Storage> Deref for OverlayFs<'_, S> {
+    type Target = FileSystem<S>;
+
+    fn deref(&self) -> &Self::Target {
+        self.state.overlay_fs.as_ref().unwrap()
+    }
+}
+
+impl<S: Storage> DerefMut for OverlayFs<'_, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.state.overlay_fs.as_mut().unwrap()
+    }
+}
 
 // `__internal_wasi_vfs_rt_init` is processed before the wasi-libc's initialization, which
 // loads envirnoment variables and preopened directories. `getenv` is not available at this
@@ -161,13 +300,35 @@ unsafe extern "C" fn __internal_wasi_vfs_rt_init() {
     if env_var("__WASI_VFS_PACKING").is_some() {
         return;
     }
-    if let Some((embedded_fs, preopened_vfds)) =
-        unsafe { (*std::ptr::addr_of_mut!(GLOBAL_STATE)).embedded_fs.take() }
-    {
+    #[cfg(feature = "trace-syscall")]
+    trace::init();
+    // Not locked while registering preopens to wasi-libc, which may call
+    // hooked functions.
+    let embedded_fs = lock_global_state().embedded_fs.take();
+    let overlay_fs = lock_global_state().overlay_fs.take();
+    if let Some((embedded_fs, preopened_vfds)) = embedded_fs {
         let fs = FileSystem::create(embedded_fs, &preopened_vfds);
-        unsafe {
-            (*std::ptr::addr_of_mut!(GLOBAL_STATE)).overlay_fs = Some(fs);
+        lock_global_state().overlay_fs = Some(fs);
+    } else if let Some(mut fs) = overlay_fs {
+        // Activated at packing time to run an application init function, so
+        // only directories preopened by the runtime are new.
+        fs.register_host_preopens();
+        lock_global_state().overlay_fs = Some(fs);
+    } else if dev::enabled_by_env() {
+        // Nothing is packed, but the overlay is still needed to serve `/dev`
+        let fs = FileSystem::create(EmbeddedFs::default(), &[]);
+        lock_global_state().overlay_fs = Some(fs);
+    }
+}
+
+/// Close directories preopened by the CLI for packing, so that they are not
+/// visible once the virtual file system is activated at packing time.
+fn close_host_preopens() {
+    for fd in 3.. {
+        if unsafe { wasi::fd_prestat_get(fd) }.is_err() {
+            break;
         }
+        let _ = unsafe { wasi::fd_close(fd) };
     }
 }
 
@@ -177,20 +338,36 @@ unsafe extern "C" fn __internal_wasi_vfs_pack_fs() {
     std::panic::set_hook(Box::new(|info| {
         trace::print(format!("{}\n", info));
     }));
-    let (mut fs, preopened_vfds) = if let Some((fs, vfds)) =
-        unsafe { (*std::ptr::addr_of_mut!(GLOBAL_STATE)).embedded_fs.take() }
-    {
+    if lock_global_state().overlay_fs.is_some() {
+        trace::eprint(
+            "wasi-vfs: the module is pre-initialized by an application init function and can't be packed again\n"
+                .to_string(),
+        );
+        unsafe { wasi::proc_exit(1) };
+    }
+    let (fs, preopened_vfds) = if let Some((fs, vfds)) = lock_global_state().embedded_fs.take() {
         (fs, vfds)
     } else {
         (EmbeddedFs::default(), vec![])
     };
 
-    let (preopened_vfds, prestats) =
-        FsPacker::scan_preopened_dirs(&mut fs, preopened_vfds).unwrap();
-    let packer = FsPacker::new(fs, preopened_vfds).unwrap();
-    let fs = packer.pack(prestats).unwrap();
-    unsafe {
-        (*std::ptr::addr_of_mut!(GLOBAL_STATE)).embedded_fs = Some(fs);
+    let fs = match FsPacker::new(fs, preopened_vfds).pack() {
+        Ok(fs) => fs,
+        Err(err) => {
+            err.report();
+            unsafe { wasi::proc_exit(1) };
+            unreachable!("proc_exit returned");
+        }
+    };
+    if env_var("__WASI_VFS_PACK_ACTIVATE").is_some() {
+        // An application init function runs next in the same snapshot, and
+        // it should see the packed file system as it's seen at runtime.
+        close_host_preopens();
+        let (fs, preopened_vfds) = fs;
+        let fs = FileSystem::create(fs, &preopened_vfds);
+        lock_global_state().overlay_fs = Some(fs);
+    } else {
+        lock_global_state().embedded_fs = Some(fs);
     }
 
     #[cfg(not(feature = "module-linking"))]
@@ -204,259 +381,25 @@ unsafe extern "C" fn __internal_wasi_vfs_pack_fs() {
     }
 }
 
-struct Prestat<S: Storage> {
-    real_fd: u32,
-    node_id: S::NodeId,
-    link_id: S::LinkId,
-}
-
-struct FsPacker<S: Storage> {
-    fs: EmbeddedFs<S>,
-    preopened_vfds: Vec<Vfd>,
-    verbose: bool,
-}
-
-trait DirVisitor<S: Storage> {
-    fn visit_file(
-        &mut self,
-        path: &str,
-        fd: u32,
-        preopened_id: (S::NodeId, S::LinkId),
-    ) -> Result<(), u16>;
-
-    fn visit_dir(
-        &mut self,
-        prefix: &str,
-        fd: u32,
-        preopened_id: (S::NodeId, S::LinkId),
-    ) -> Result<(), u16>;
-}
-
-fn walk_dir<S: Storage, V: DirVisitor<S>>(
-    visitor: &mut V,
-    prefix: &str,
-    fd: u32,
-    preopened_id: (S::NodeId, S::LinkId),
-) -> Result<(), u16> {
-    const DIRENT_DEFAULT_BUFFER_SIZE: usize = 4096;
-    let mut offset = 0;
-    let mut capacity = 0;
-    let mut cookie = wasi::DIRCOOKIE_START;
-    let mut buffer = vec![0; DIRENT_DEFAULT_BUFFER_SIZE];
-    loop {
-        if offset == capacity {
-            capacity = unsafe { wasi::fd_readdir(fd, buffer.as_mut_ptr(), buffer.len(), cookie) }
-                .expect("failed to readdir");
-            offset = 0;
-            if capacity == 0 {
-                break;
-            }
-        }
-        let data = &buffer[offset..capacity];
-        let dirent_size = core::mem::size_of::<wasi::Dirent>();
-
-        // when dirent is truncated, re-read it
-        if data.len() < dirent_size {
-            offset = capacity;
-            continue;
-        }
-
-        let (dirent, data) = data.split_at(dirent_size);
-        let dirent = unsafe { core::ptr::read_unaligned(dirent.as_ptr() as *const wasi::Dirent) };
-
-        // when entry name is truncated
-        if data.len() < dirent.d_namlen as usize {
-            // when the buffer is not enough to read an big entry, realloc the buffer and re-read the entry
-            if offset == 0 {
-                let amt_to_add = buffer.capacity();
-                buffer.extend(core::iter::repeat(0).take(amt_to_add));
-            }
-            offset = capacity;
-            continue;
-        }
-        cookie = dirent.d_next;
-        offset += dirent_size + dirent.d_namlen as usize;
-        let name = &data[..dirent.d_namlen as usize];
-        if name == b"." || name == b".." {
-            continue;
-        }
-
-        let name = String::from_utf8(name.to_vec()).unwrap();
-        let path = format!("{}/{}", prefix, name);
-        let rights = wasi::RIGHTS_FD_READ
-            | wasi::RIGHTS_FD_READDIR
-            | wasi::RIGHTS_FD_FILESTAT_GET
-            | wasi::RIGHTS_PATH_OPEN;
-
-        match dirent.d_type {
-            wasi::FILETYPE_DIRECTORY => {
-                let oflags = wasi::OFLAGS_DIRECTORY;
-                let child_fd = unsafe {
-                    wasi::path_open(
-                        fd,
-                        wasi::LOOKUPFLAGS_SYMLINK_FOLLOW,
-                        &name,
-                        oflags,
-                        rights,
-                        rights,
-                        0,
-                    )
-                }
-                .map_err(|e| e.raw())
-                .unwrap();
-
-                visitor.visit_dir(&path, child_fd, preopened_id)?;
-                walk_dir(visitor, &path, child_fd, preopened_id)?;
-
-                unsafe {
-                    wasi::fd_close(child_fd).expect("failed to close fd");
-                }
-            }
-            wasi::FILETYPE_REGULAR_FILE => {
-                let oflags = 0;
-                let child_fd = unsafe {
-                    wasi::path_open(
-                        fd,
-                        wasi::LOOKUPFLAGS_SYMLINK_FOLLOW,
-                        &name,
-                        oflags,
-                        rights,
-                        rights,
-                        0,
-                    )
-                }
-                .map_err(|e| e.raw())
-                .unwrap();
-                visitor.visit_file(&path, child_fd, preopened_id)?;
-                unsafe {
-                    wasi::fd_close(child_fd).expect("failed to close fd");
-                }
-            }
-            _ => {}
-        }
-    }
-    Ok(())
-}
-
-impl<S: Storage> FsPacker<S> {
-    fn scan_preopened_dirs(
-        fs: &mut EmbeddedFs<S>,
-        mut preopened_vfds: Vec<Vfd>,
-    ) -> Result<(Vec<Vfd>, Vec<Prestat<S>>), u16> {
-        let mut preopened_dirs = Vec::new();
-        'scan: for fd in 3.. {
-            let stat = match unsafe { wasi::fd_prestat_get(fd) } {
-                Ok(stat) => stat,
-                Err(wasi::ERRNO_BADF) => break 'scan,
-                Err(other) => {
-                    return Err(other.raw());
-                }
-            };
-            if stat.tag == wasi::PREOPENTYPE_DIR.raw() {
-                preopened_dirs.push((fd, stat));
-            }
-        }
-
-        let mut prestats = Vec::new();
-        for (real_fd, stat) in preopened_dirs {
-            let dir = unsafe {
-                let mut prefix = vec![0; stat.u.dir.pr_name_len + 1];
-                wasi::fd_prestat_dir_name(real_fd, prefix.as_mut_ptr(), stat.u.dir.pr_name_len)
-                    .map_err(|e| e.raw())
-                    .expect("failed to get dir name");
-                let dir = CStr::from_bytes_with_nul(&prefix).unwrap();
-                dir.to_string_lossy().to_string()
-            };
-            let (vfd, node_id, link_id) = fs.preopen_dir(dir);
-            prestats.push(Prestat {
-                real_fd,
-                node_id,
-                link_id,
-            });
-            preopened_vfds.push(vfd);
-        }
-        Ok((preopened_vfds, prestats))
-    }
-
-    fn new(fs: EmbeddedFs<S>, preopened_vfds: Vec<Vfd>) -> Result<Self, u16> {
-        Ok(FsPacker {
-            fs,
-            preopened_vfds,
-            verbose: env_var("WASI_VFS_VERBOSE")
-                .map(|v| v == "1")
-                .unwrap_or(false),
-        })
-    }
-
-    fn pack(mut self, prestats: Vec<Prestat<S>>) -> Result<(EmbeddedFs<S>, Vec<Vfd>), u16> {
-        for stat in prestats {
-            walk_dir(&mut self, "", stat.real_fd, (stat.node_id, stat.link_id))?;
-        }
-        Ok((self.fs, self.preopened_vfds))
-    }
-}
-
-impl<S: Storage> DirVisitor<S> for FsPacker<S> {
-    fn visit_dir(
-        &mut self,
-        path: &str,
-        _fd: u32,
-        preopened_id: (S::NodeId, S::LinkId),
-    ) -> Result<(), u16> {
-        self.fs
-            .create_dir(preopened_id.0, preopened_id.1, path)
-            .unwrap();
-        Ok(())
+/// Inspection entry point to dump the embedded file system into the control
+/// directory, used by the CLI to inspect packed modules.
+#[unsafe(no_mangle)]
+unsafe extern "C" fn __internal_wasi_vfs_dump_fs() {
+    let mut state = lock_global_state();
+    let state = &mut *state;
+    let embedded_fs = match (&mut state.embedded_fs, &mut state.overlay_fs) {
+        (Some((fs, _)), _) => Some(fs),
+        (None, Some(fs)) => Some(&mut fs.embedded_fs),
+        (None, None) => None,
+    };
+    let mut writer = stream::Writer::new();
+    if let Some(fs) = embedded_fs {
+        fs.prepare();
+        fs.write_tree(&mut writer);
     }
-
-    fn visit_file(
-        &mut self,
-        path: &str,
-        fd: u32,
-        preopened_id: (S::NodeId, S::LinkId),
-    ) -> Result<(), u16> {
-        let stat = unsafe { wasi::fd_filestat_get(fd) }
-            .map_err(|e| e.raw())
-            .unwrap();
-        if stat.size >= u32::MAX as u64 {
-            // ignore too big files
-            if self.verbose {
-                trace::print(format!("too large file: {} (size {})\n", path, stat.size));
-            }
-            return Ok(());
-        }
-        let mut buf = vec![0; stat.size as usize];
-        let mut offset = 0;
-        loop {
-            let read = unsafe {
-                wasi::fd_read(
-                    fd,
-                    &[wasi::Iovec {
-                        buf: buf[offset..].as_mut_ptr(),
-                        buf_len: buf.len(),
-                    }],
-                )
-            }
-            .map_err(|e| e.raw())
-            .unwrap();
-
-            offset += read;
-            if offset == stat.size as usize {
-                break;
-            }
-        }
-        if self.verbose {
-            trace::print(format!(
-                "pack file: {} under node-id={} (size {})\n",
-                path,
-                preopened_id.0.ino(),
-                buf.len()
-            ));
-        }
-        self.fs
-            .create_file(preopened_id.0, preopened_id.1, path, buf)
-            .unwrap();
-        Ok(())
+    if !matches!(control::write_file("dump", &writer.finish()), Ok(true)) {
+        trace::eprint("wasi-vfs: failed to dump the embedded file system\n".to_string());
+        unsafe { wasi::proc_exit(1) };
     }
 }
 
@@ -0,0 +1,93 @@
+//! Mock WASI host for native builds, which exist to unit test the syscall
+//! layer with `cargo test`. It defines the symbols imported from
+//! `wasi_snapshot_preview1` and `__wasilibc_register_preopened_fd`.
+//!
+//! The mock host has no preopened directories. WASI imports take pointers as
+//! `i32`, which can't hold native pointers, so the other imports fail with
+//! `ERRNO_NOSYS` without touching memory. Tests cover file descriptors backed
+//! by the virtual file system, not the ones forwarded to the host.
+
+use std::{cell::RefCell, ffi::CStr};
+
+thread_local! {
+    static REGISTERED_PREOPENS: RefCell<Vec<(i32, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Directories registered to wasi-libc by the current thread, paired with
+/// their fds.
+#[cfg(test)]
+pub(crate) fn registered_preopens() -> Vec<(i32, String)> {
+    REGISTERED_PREOPENS.with(|preopens| preopens.borrow().clone())
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn __wasilibc_register_preopened_fd(fd: i32, name: *const u8) -> i32 {
+    let name = unsafe { CStr::from_ptr(name as *const _) };
+    let name = name.to_string_lossy().into_owned();
+    REGISTERED_PREOPENS.with(|preopens| preopens.borrow_mut().push((fd, name)));
+    0
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn fd_prestat_get(_fd: i32, _prestat: i32) -> i32 {
+    wasi::ERRNO_BADF.raw() as i32
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn proc_exit(code: i32) -> ! {
+    panic!("proc_exit({}) called", code);
+}
+
+macro_rules! unsupported_imports {
+    ($($name:ident($($ty:ty),*);)*) => {
+        $(
+            #[unsafe(no_mangle)]
+            extern "C" fn $name($(_: $ty),*) -> i32 {
+                wasi::ERRNO_NOSYS.raw() as i32
+            }
+        )*
+    };
+}
+
+// `sched_yield` is left out as libc defines it
+unsupported_imports! {
+    args_get(i32, i32);
+    args_sizes_get(i32, i32);
+    environ_get(i32, i32);
+    environ_sizes_get(i32, i32);
+    clock_res_get(i32, i32);
+    clock_time_get(i32, i64, i32);
+    fd_advise(i32, i64, i64, i32);
+    fd_allocate(i32, i64, i64);
+    fd_close(i32);
+    fd_datasync(i32);
+    fd_fdstat_get(i32, i32);
+    fd_fdstat_set_flags(i32, i32);
+    fd_fdstat_set_rights(i32, i64, i64);
+    fd_filestat_get(i32, i32);
+    fd_filestat_set_size(i32, i64);
+    fd_filestat_set_times(i32, i64, i64, i32);
+    fd_pread(i32, i32, i32, i64, i32);
+    fd_prestat_dir_name(i32, i32, i32);
+    fd_pwrite(i32, i32, i32, i64, i32);
+    fd_read(i32, i32, i32, i32);
+    fd_readdir(i32, i32, i32, i64, i32);
+    fd_renumber(i32, i32);
+    fd_seek(i32, i64, i32, i32);
+    fd_sync(i32);
+    fd_tell(i32, i32);
+    fd_write(i32, i32, i32, i32);
+    path_create_directory(i32, i32, i32);
+    path_filestat_get(i32, i32, i32, i32, i32);
+    path_filestat_set_times(i32, i32, i32, i32, i64, i64, i32);
+    path_link(i32, i32, i32, i32, i32, i32, i32);
+    path_open(i32, i32, i32, i32, i32, i64, i64, i32, i32);
+    path_readlink(i32, i32, i32, i32, i32, i32);
+    path_remove_directory(i32, i32, i32);
+    path_rename(i32, i32, i32, i32, i32, i32);
+    path_symlink(i32, i32, i32, i32, i32);
+    path_unlink_file(i32, i32, i32);
+    poll_oneoff(i32, i32, i32, i32);
+    proc_raise(i32);
+    random_get(i32, i32);
+}
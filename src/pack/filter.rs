@@ -0,0 +1,248 @@
+//! Include/exclude rules to select which host entries are packed.
+//!
+//! Patterns follow the `.gitignore` conventions:
+//! - `*` and `?` match anything but `/`, `**` matches across directories
+//! - `[a-z]` and `[!a-z]` match a character class
+//! - a pattern without `/` matches the entry name at any depth
+//! - a pattern with a leading or middle `/` is anchored to the mount root
+//! - a trailing `/` matches directories only
+//! - a leading `!` in an ignore file re-includes a previously excluded entry
+
+/// Name of the ignore file read from the root of each mapped directory.
+pub(crate) const IGNORE_FILE_NAME: &str = ".wasivfsignore";
+
+struct Pattern {
+    glob: String,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let mut glob = pattern.trim();
+        if glob.is_empty() {
+            return None;
+        }
+        let dir_only = glob.ends_with('/');
+        glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        glob = glob.trim_start_matches('/');
+        if glob.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            glob: glob.to_string(),
+            anchored,
+            dir_only,
+        })
+    }
+
+    /// Match against a path relative to the mount root without leading `/`.
+    fn matches(&self, relpath: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), relpath.as_bytes())
+        } else {
+            let name = relpath.rsplit('/').next().unwrap_or(relpath);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+}
+
+/// A set of rules deciding whether a host entry should be packed.
+#[derive(Default)]
+pub(crate) struct PackFilter {
+    /// Exclude rules, the last matching rule wins.
+    excludes: Vec<Rule>,
+    /// If not empty, only regular files matching one of them are packed.
+    includes: Vec<Pattern>,
+}
+
+impl PackFilter {
+    /// Build a filter from newline separated exclude and include patterns.
+    pub(crate) fn new(excludes: &str, includes: &str) -> Self {
+        let mut filter = PackFilter::default();
+        filter.add_excludes(excludes);
+        filter.includes = includes.lines().filter_map(Pattern::parse).collect();
+        filter
+    }
+
+    /// Add exclude rules written in the ignore file format. Empty lines and
+    /// lines starting with `#` are ignored.
+    pub(crate) fn add_excludes(&mut self, excludes: &str) {
+        for line in excludes.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let (line, negated) = match line.strip_prefix('!') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if let Some(pattern) = Pattern::parse(line) {
+                self.excludes.push(Rule { pattern, negated });
+            }
+        }
+    }
+
+    /// Returns true if the entry at `path` (relative to the mount root) is
+    /// excluded. Excluded directories are not descended into.
+    pub(crate) fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        let relpath = path.trim_start_matches('/');
+        if relpath == IGNORE_FILE_NAME {
+            return true;
+        }
+        self.excludes
+            .iter()
+            .rev()
+            .find(|rule| rule.pattern.matches(relpath, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Returns true if the entry at `path` is selected by include patterns.
+    /// An entry is selected if it or one of its parent directories matches.
+    pub(crate) fn is_included(&self, path: &str, is_dir: bool) -> bool {
+        if self.includes.is_empty() {
+            return true;
+        }
+        let relpath = path.trim_start_matches('/');
+        let matches = |relpath: &str, is_dir: bool| {
+            self.includes
+                .iter()
+                .any(|pattern| pattern.matches(relpath, is_dir))
+        };
+        if matches(relpath, is_dir) {
+            return true;
+        }
+        relpath
+            .match_indices('/')
+            .any(|(idx, _)| matches(&relpath[..idx], true))
+    }
+
+    pub(crate) fn has_includes(&self) -> bool {
+        !self.includes.is_empty()
+    }
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let mut rest = &pattern[2..];
+            // `**/` also matches zero directories
+            if rest.first() == Some(&b'/') {
+                rest = &rest[1..];
+                if glob_match(rest, text) {
+                    return true;
+                }
+                return text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| *c == b'/' && glob_match(rest, &text[i + 1..]));
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(c) if *c != b'/' => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => {
+            let c = match text.first() {
+                Some(c) if *c != b'/' => *c,
+                _ => return false,
+            };
+            match match_class(&pattern[1..], c) {
+                Some((true, rest)) => glob_match(rest, &text[1..]),
+                Some((false, _)) => false,
+                // unterminated class, treat `[` literally
+                None => c == b'[' && glob_match(&pattern[1..], &text[1..]),
+            }
+        }
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(p) => text.first() == Some(p) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Match a character against a class body following `[`. Returns whether it
+/// matched and the pattern after the closing `]`.
+fn match_class(class: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut class) = match class.first() {
+        Some(b'!') | Some(b'^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match class {
+            [] => return None,
+            [b']', rest @ ..] if !first => return Some((matched != negated, rest)),
+            [lo, b'-', hi, rest @ ..] if *hi != b']' => {
+                matched |= *lo <= c && c <= *hi;
+                class = rest;
+            }
+            [x, rest @ ..] => {
+                matched |= *x == c;
+                class = rest;
+            }
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PackFilter, glob_match};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.txt", b"hello.txt"));
+        assert!(!glob_match(b"*.txt", b"dir/hello.txt"));
+        assert!(glob_match(b"**/*.txt", b"hello.txt"));
+        assert!(glob_match(b"**/*.txt", b"a/b/hello.txt"));
+        assert!(glob_match(b"a/**", b"a/b/c"));
+        assert!(glob_match(b"file?.[ch]", b"file1.c"));
+        assert!(!glob_match(b"file[!0-9].c", b"file1.c"));
+    }
+
+    #[test]
+    fn test_pack_filter_excludes() {
+        let mut filter = PackFilter::new("", "");
+        filter.add_excludes("# comment\n.git/\n__pycache__\n*.swp\n!keep.swp\n/build\n");
+        assert!(filter.is_excluded("/.git", true));
+        assert!(!filter.is_excluded("/.git", false));
+        assert!(filter.is_excluded("/lib/__pycache__", true));
+        assert!(filter.is_excluded("/src/.main.c.swp", false));
+        assert!(!filter.is_excluded("/src/keep.swp", false));
+        assert!(filter.is_excluded("/build", true));
+        assert!(!filter.is_excluded("/src/build", true));
+        assert!(filter.is_excluded("/.wasivfsignore", false));
+    }
+
+    #[test]
+    fn test_pack_filter_includes() {
+        let filter = PackFilter::new("", "*.py\nassets");
+        assert!(filter.is_included("/lib/os.py", false));
+        assert!(filter.is_included("/assets/logo.png", false));
+        assert!(!filter.is_included("/lib/os.pyc", false));
+    }
+}
@@ -0,0 +1,718 @@
+//! Packing-time scanner that copies preopened host directories into `EmbeddedFs`.
+
+mod filter;
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use filter::{IGNORE_FILE_NAME, PackFilter};
+
+use crate::control::{self, CONTROL_DIR_ENV, prestat_dir_name, read_to_end};
+use crate::embed::{EmbeddedFs, NodeIdTrait, Storage};
+use crate::stream::{self, Record};
+use crate::{Vfd, env_var, trace};
+
+/// An error raised while scanning the host file system at packing time.
+#[derive(Debug)]
+pub(crate) struct PackError {
+    /// Raw WASI errno that caused the failure.
+    pub(crate) errno: u16,
+    /// Guest directory of the preopen being scanned, empty if not known yet.
+    pub(crate) mount: String,
+    /// Path of the failed entry relative to `mount`.
+    pub(crate) path: String,
+}
+
+impl PackError {
+    fn new(errno: u16, mount: &str, path: &str) -> Self {
+        Self {
+            errno,
+            mount: mount.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    /// Write the error into the control directory so that the CLI can
+    /// report it in a structured way. Falls back to stderr when the module
+    /// is packed without a control directory.
+    pub(crate) fn report(&self) {
+        let report = format!(
+            "errno={}\nmount={}\npath={}\n",
+            self.errno, self.mount, self.path
+        );
+        let written = control::write_file("error", report.as_bytes()).is_ok_and(|written| written);
+        if !written {
+            trace::eprint(format!(
+                "wasi-vfs: failed to pack {}{}: errno {}\n",
+                self.mount, self.path, self.errno
+            ));
+        }
+    }
+}
+
+/// Compares guest directories ignoring trailing slashes.
+fn same_guest_dir(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+/// Read the ignore file at the root of a preopened directory, if any.
+fn read_ignore_file(dir_fd: u32) -> Result<Option<String>, wasi::Errno> {
+    let rights = wasi::RIGHTS_FD_READ | wasi::RIGHTS_FD_FILESTAT_GET;
+    let fd = match unsafe { wasi::path_open(dir_fd, 0, IGNORE_FILE_NAME, 0, rights, 0, 0) } {
+        Ok(fd) => fd,
+        Err(wasi::ERRNO_NOENT) => return Ok(None),
+        Err(other) => return Err(other),
+    };
+    let content = read_to_end(fd);
+    unsafe { wasi::fd_close(fd)? };
+    Ok(Some(String::from_utf8_lossy(&content?).into_owned()))
+}
+
+pub(crate) struct Prestat<S: Storage> {
+    real_fd: u32,
+    mount: String,
+    node_id: S::NodeId,
+    link_id: S::LinkId,
+}
+
+/// How mounts packed by the current run treat mounts at the same guest
+/// directory packed by previous runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PackMode {
+    /// Add a new mount shadowing the previous ones.
+    Append,
+    /// Remove the previous mounts before adding a new one.
+    Replace,
+    /// Add entries into the last previous mount, replacing existing paths.
+    Merge,
+}
+
+impl PackMode {
+    fn from_env() -> Self {
+        match env_var("__WASI_VFS_PACK_MODE").as_deref() {
+            Some("replace") => PackMode::Replace,
+            Some("merge") => PackMode::Merge,
+            _ => PackMode::Append,
+        }
+    }
+}
+
+pub(crate) struct FsPacker<S: Storage> {
+    fs: EmbeddedFs<S>,
+    preopened_vfds: Vec<Vfd>,
+    /// Mounts packed by previous runs.
+    previous_vfds: Vec<Vfd>,
+    mode: PackMode,
+    /// Guest directory of the preopen currently being scanned.
+    current_mount: String,
+    /// Filter for the preopen currently being scanned.
+    filter: PackFilter,
+    /// Timestamp given to every packed entry instead of the host's one.
+    mtime: Option<wasi::Timestamp>,
+    /// Files packed in this run keyed by the hash of their content, if
+    /// identical files are deduplicated.
+    packed_files: Option<HashMap<u64, Vec<S::NodeId>>>,
+    verbose: bool,
+    strict: bool,
+}
+
+trait DirVisitor<S: Storage> {
+    fn visit_file(
+        &mut self,
+        path: &str,
+        fd: u32,
+        preopened_id: (S::NodeId, S::LinkId),
+    ) -> Result<(), u16>;
+
+    fn visit_dir(
+        &mut self,
+        prefix: &str,
+        fd: u32,
+        preopened_id: (S::NodeId, S::LinkId),
+    ) -> Result<(), u16>;
+
+    /// Called for symbolic links with their target and timestamp, which are
+    /// packed as is without following them.
+    fn visit_symlink(
+        &mut self,
+        path: &str,
+        target: String,
+        mtime: wasi::Timestamp,
+        preopened_id: (S::NodeId, S::LinkId),
+    ) -> Result<(), u16>;
+
+    /// Called for entries which can't be packed. Returns an error to abort
+    /// the scan, or `Ok` to skip the entry.
+    fn visit_unsupported(
+        &mut self,
+        path: &str,
+        reason: &str,
+        errno: wasi::Errno,
+    ) -> Result<(), u16>;
+
+    /// Returns true if the entry should not be visited at all.
+    fn is_excluded(&self, path: &str, is_dir: bool) -> bool;
+}
+
+/// Error raised while walking a directory tree, carrying the path of the
+/// entry which caused it.
+struct WalkError {
+    errno: u16,
+    path: String,
+}
+
+fn walk_dir<S: Storage, V: DirVisitor<S>>(
+    visitor: &mut V,
+    prefix: &str,
+    fd: u32,
+    preopened_id: (S::NodeId, S::LinkId),
+) -> Result<(), WalkError> {
+    const DIRENT_DEFAULT_BUFFER_SIZE: usize = 4096;
+    let mut offset = 0;
+    let mut capacity = 0;
+    let mut cookie = wasi::DIRCOOKIE_START;
+    let mut buffer = vec![0; DIRENT_DEFAULT_BUFFER_SIZE];
+    let walk_error = |errno: u16, path: &str| WalkError {
+        errno,
+        path: path.to_string(),
+    };
+    loop {
+        if offset == capacity {
+            capacity = unsafe { wasi::fd_readdir(fd, buffer.as_mut_ptr(), buffer.len(), cookie) }
+                .map_err(|e| walk_error(e.raw(), prefix))?;
+            offset = 0;
+            if capacity == 0 {
+                break;
+            }
+        }
+        let data = &buffer[offset..capacity];
+        let dirent_size = core::mem::size_of::<wasi::Dirent>();
+
+        // when dirent is truncated, re-read it
+        if data.len() < dirent_size {
+            offset = capacity;
+            continue;
+        }
+
+        let (dirent, data) = data.split_at(dirent_size);
+        let dirent = unsafe { core::ptr::read_unaligned(dirent.as_ptr() as *const wasi::Dirent) };
+
+        // when entry name is truncated
+        if data.len() < dirent.d_namlen as usize {
+            // when the buffer is not enough to read an big entry, realloc the buffer and re-read the entry
+            if offset == 0 {
+                let amt_to_add = buffer.capacity();
+                buffer.extend(core::iter::repeat(0).take(amt_to_add));
+            }
+            offset = capacity;
+            continue;
+        }
+        cookie = dirent.d_next;
+        offset += dirent_size + dirent.d_namlen as usize;
+        let name = &data[..dirent.d_namlen as usize];
+        if name == b"." || name == b".." {
+            continue;
+        }
+
+        let name = match String::from_utf8(name.to_vec()) {
+            Ok(name) => name,
+            Err(_) => {
+                let path = format!("{}/{}", prefix, String::from_utf8_lossy(name));
+                visitor
+                    .visit_unsupported(&path, "non UTF-8 file name", wasi::ERRNO_ILSEQ)
+                    .map_err(|errno| walk_error(errno, &path))?;
+                continue;
+            }
+        };
+        let path = format!("{}/{}", prefix, name);
+        if visitor.is_excluded(&path, dirent.d_type == wasi::FILETYPE_DIRECTORY) {
+            continue;
+        }
+        let rights = wasi::RIGHTS_FD_READ
+            | wasi::RIGHTS_FD_READDIR
+            | wasi::RIGHTS_FD_FILESTAT_GET
+            | wasi::RIGHTS_PATH_OPEN;
+
+        match dirent.d_type {
+            wasi::FILETYPE_DIRECTORY => {
+                let oflags = wasi::OFLAGS_DIRECTORY;
+                let child_fd = unsafe {
+                    wasi::path_open(
+                        fd,
+                        wasi::LOOKUPFLAGS_SYMLINK_FOLLOW,
+                        &name,
+                        oflags,
+                        rights,
+                        rights,
+                        0,
+                    )
+                }
+                .map_err(|e| walk_error(e.raw(), &path))?;
+
+                let result = visitor
+                    .visit_dir(&path, child_fd, preopened_id)
+                    .map_err(|errno| walk_error(errno, &path))
+                    .and_then(|_| walk_dir(visitor, &path, child_fd, preopened_id));
+
+                unsafe {
+                    wasi::fd_close(child_fd).map_err(|e| walk_error(e.raw(), &path))?;
+                }
+                result?;
+            }
+            wasi::FILETYPE_REGULAR_FILE => {
+                let oflags = 0;
+                let child_fd = unsafe {
+                    wasi::path_open(
+                        fd,
+                        wasi::LOOKUPFLAGS_SYMLINK_FOLLOW,
+                        &name,
+                        oflags,
+                        rights,
+                        rights,
+                        0,
+                    )
+                }
+                .map_err(|e| walk_error(e.raw(), &path))?;
+                let result = visitor
+                    .visit_file(&path, child_fd, preopened_id)
+                    .map_err(|errno| walk_error(errno, &path));
+                unsafe {
+                    wasi::fd_close(child_fd).map_err(|e| walk_error(e.raw(), &path))?;
+                }
+                result?;
+            }
+            wasi::FILETYPE_SYMBOLIC_LINK => {
+                let (target, mtime) =
+                    read_symlink(fd, &name).map_err(|e| walk_error(e.raw(), &path))?;
+                let target = match String::from_utf8(target) {
+                    Ok(target) => target,
+                    Err(_) => {
+                        visitor
+                            .visit_unsupported(&path, "non UTF-8 link target", wasi::ERRNO_ILSEQ)
+                            .map_err(|errno| walk_error(errno, &path))?;
+                        continue;
+                    }
+                };
+                visitor
+                    .visit_symlink(&path, target, mtime, preopened_id)
+                    .map_err(|errno| walk_error(errno, &path))?;
+            }
+            other => {
+                visitor
+                    .visit_unsupported(&path, other.name(), wasi::ERRNO_NOTSUP)
+                    .map_err(|errno| walk_error(errno, &path))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the target and the timestamp of the symbolic link `name` in `fd`.
+fn read_symlink(fd: u32, name: &str) -> Result<(Vec<u8>, wasi::Timestamp), wasi::Errno> {
+    let stat = unsafe { wasi::path_filestat_get(fd, 0, name) }?;
+    let mut target = vec![0; stat.size as usize];
+    let len = unsafe { wasi::path_readlink(fd, name, target.as_mut_ptr(), target.len()) }?;
+    target.truncate(len);
+    Ok((target, stat.mtim))
+}
+
+impl<S: Storage> FsPacker<S> {
+    pub(crate) fn new(fs: EmbeddedFs<S>, preopened_vfds: Vec<Vfd>) -> Self {
+        let flag = |name| env_var(name).map(|v| v == "1").unwrap_or(false);
+        FsPacker {
+            fs,
+            previous_vfds: preopened_vfds.clone(),
+            preopened_vfds,
+            mode: PackMode::from_env(),
+            current_mount: String::new(),
+            filter: PackFilter::default(),
+            mtime: env_var("__WASI_VFS_PACK_MTIME").and_then(|v| v.parse().ok()),
+            packed_files: flag("__WASI_VFS_PACK_DEDUP").then(HashMap::new),
+            verbose: flag("WASI_VFS_VERBOSE"),
+            strict: flag("__WASI_VFS_PACK_STRICT"),
+        }
+    }
+
+    fn scan_preopened_dirs(&mut self) -> Result<Vec<Prestat<S>>, PackError> {
+        let control_dir = env_var(CONTROL_DIR_ENV);
+        let mut preopened_dirs = Vec::new();
+        'scan: for fd in 3.. {
+            let stat = match unsafe { wasi::fd_prestat_get(fd) } {
+                Ok(stat) => stat,
+                Err(wasi::ERRNO_BADF) => break 'scan,
+                Err(other) => {
+                    return Err(PackError::new(other.raw(), "", ""));
+                }
+            };
+            if stat.tag == wasi::PREOPENTYPE_DIR.raw() {
+                preopened_dirs.push((fd, stat));
+            }
+        }
+
+        let mut prestats = Vec::new();
+        for (real_fd, stat) in preopened_dirs {
+            let dir =
+                prestat_dir_name(real_fd, &stat).map_err(|e| PackError::new(e.raw(), "", ""))?;
+            if control_dir.as_ref() == Some(&dir) {
+                continue;
+            }
+            let (node_id, link_id) = self.mount(&dir);
+            prestats.push(Prestat {
+                real_fd,
+                mount: dir,
+                node_id,
+                link_id,
+            });
+        }
+        Ok(prestats)
+    }
+
+    /// Returns the root directory to pack a tree at `guest_dir` into. Trees
+    /// packed in this run at the same guest directory share a root, e.g. files
+    /// given by `--file` join the directory containing them. Otherwise mounts
+    /// packed by previous runs are handled following the pack mode.
+    fn mount(&mut self, guest_dir: &str) -> (S::NodeId, S::LinkId) {
+        if let Some(root) = self
+            .preopened_vfds
+            .iter()
+            .filter(|vfd| !self.previous_vfds.contains(vfd))
+            .find(|vfd| {
+                self.fs
+                    .get_preopened_dir_path(**vfd)
+                    .is_some_and(|path| same_guest_dir(path, guest_dir))
+            })
+            .and_then(|vfd| self.fs.get_preopened_dir_root(*vfd))
+        {
+            return root;
+        }
+        let previous = self.previous_mounts(guest_dir);
+        match self.mode {
+            PackMode::Append => {}
+            PackMode::Replace => {
+                for vfd in previous {
+                    self.remove_mount(vfd);
+                }
+            }
+            PackMode::Merge => {
+                if let Some(root) = previous
+                    .last()
+                    .and_then(|vfd| self.fs.get_preopened_dir_root(*vfd))
+                {
+                    return root;
+                }
+            }
+        }
+        let (vfd, node_id, link_id) = self.fs.preopen_dir(guest_dir.to_string());
+        self.preopened_vfds.push(vfd);
+        (node_id, link_id)
+    }
+
+    /// Mounts packed by previous runs at `guest_dir` in registration order.
+    fn previous_mounts(&self, guest_dir: &str) -> Vec<Vfd> {
+        self.previous_vfds
+            .iter()
+            .copied()
+            .filter(|vfd| {
+                self.fs
+                    .get_preopened_dir_path(*vfd)
+                    .is_some_and(|path| same_guest_dir(path, guest_dir))
+            })
+            .collect()
+    }
+
+    fn remove_mount(&mut self, vfd: Vfd) {
+        self.fs.remove_preopened_dir(vfd);
+        self.preopened_vfds.retain(|other| *other != vfd);
+    }
+
+    /// Remove mounts packed by previous runs at the guest directories listed
+    /// by the CLI. Missing guest directories are ignored.
+    fn remove_listed_mounts(&mut self) {
+        let guest_dirs = env_var("__WASI_VFS_PACK_REMOVE").unwrap_or_default();
+        for guest_dir in guest_dirs.lines() {
+            for vfd in self.previous_mounts(guest_dir) {
+                self.remove_mount(vfd);
+            }
+        }
+    }
+
+    pub(crate) fn pack(mut self) -> Result<(EmbeddedFs<S>, Vec<Vfd>), PackError> {
+        self.remove_listed_mounts();
+        // Kept enabled by later packing without the flag, like mounts
+        if env_var("__WASI_VFS_PACK_DEV").is_some_and(|v| v == "1") {
+            self.fs.set_devices(true);
+        }
+        let prestats = self.scan_preopened_dirs()?;
+        let excludes = env_var("__WASI_VFS_PACK_EXCLUDE").unwrap_or_default();
+        let includes = env_var("__WASI_VFS_PACK_INCLUDE").unwrap_or_default();
+        for stat in prestats {
+            self.current_mount = stat.mount.clone();
+            // Rules given by the CLI take precedence over the ignore file
+            self.filter = PackFilter::new("", &includes);
+            if let Some(ignore_file) = read_ignore_file(stat.real_fd)
+                .map_err(|e| PackError::new(e.raw(), &stat.mount, IGNORE_FILE_NAME))?
+            {
+                self.filter.add_excludes(&ignore_file);
+            }
+            self.filter.add_excludes(&excludes);
+            walk_dir(&mut self, "", stat.real_fd, (stat.node_id, stat.link_id))
+                .map_err(|e| PackError::new(e.errno, &stat.mount, &e.path))?;
+        }
+        // Trees which are not host directories (e.g. archives) are streamed
+        // by the CLI through the control directory.
+        if let Some(feed) =
+            control::read_file("feed").map_err(|e| PackError::new(e.raw(), "", ""))?
+        {
+            self.filter = PackFilter::new(&excludes, &includes);
+            self.pack_feed(&feed)?;
+        }
+        Ok((self.fs, self.preopened_vfds))
+    }
+
+    fn pack_feed(&mut self, feed: &[u8]) -> Result<(), PackError> {
+        let mut reader = stream::Reader::new(feed).map_err(|e| PackError::new(e, "", ""))?;
+        let mut root = None;
+        loop {
+            let record = reader
+                .next_record()
+                .map_err(|e| PackError::new(e, &self.current_mount, ""))?;
+            let record = match record {
+                Some(Record::Mount { guest_dir }) => {
+                    root = Some(self.mount(guest_dir));
+                    self.current_mount = guest_dir.to_string();
+                    continue;
+                }
+                Some(record) => record,
+                None => break,
+            };
+            let root = root.ok_or_else(|| PackError::new(wasi::ERRNO_ILSEQ.raw(), "", ""))?;
+            let path = record.path();
+            self.visit_record(record, root)
+                .map_err(|e| PackError::new(e, &self.current_mount, path))?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if the entry or one of its parent directories is excluded.
+    /// Unlike `walk_dir`, entries in a stream are not nested, so parents have
+    /// to be checked for each entry.
+    fn is_excluded_in_feed(&self, path: &str, is_dir: bool) -> bool {
+        path.match_indices('/')
+            .skip(1)
+            .any(|(idx, _)| self.filter.is_excluded(&path[..idx], true))
+            || self.is_excluded(path, is_dir)
+    }
+
+    fn visit_record(&mut self, record: Record, root: (S::NodeId, S::LinkId)) -> Result<(), u16> {
+        let path = record.path();
+        let is_dir = matches!(record, Record::Dir { .. });
+        // Archives are placed as is, so filters are not applied to them
+        let filtered = !matches!(record, Record::Archive { .. })
+            && (self.is_excluded_in_feed(path, is_dir) || !self.filter.is_included(path, is_dir));
+        if filtered {
+            return Ok(());
+        }
+        let (node_id, mtime) = match record {
+            Record::Mount { .. } => unreachable!("mount records are handled by pack_feed"),
+            Record::Dir { path, mtime } => (self.fs.create_dir(root.0, root.1, path)?.0, mtime),
+            Record::File {
+                path,
+                mtime,
+                content,
+            } => {
+                if self.verbose {
+                    trace::print(format!(
+                        "pack file: {}{} (size {})\n",
+                        self.current_mount.trim_end_matches('/'),
+                        path,
+                        content.len()
+                    ));
+                }
+                return self.create_file(root, path, content.to_vec(), mtime);
+            }
+            Record::Symlink {
+                path,
+                mtime,
+                target,
+            } => {
+                let (node_id, _) =
+                    self.fs
+                        .create_symlink(root.0, root.1, path, target.to_string())?;
+                (node_id, mtime)
+            }
+            Record::Archive { path, content } => {
+                if self.verbose {
+                    trace::print(format!(
+                        "pack archive: {}{} (size {})\n",
+                        self.current_mount.trim_end_matches('/'),
+                        path,
+                        content.len()
+                    ));
+                }
+                self.fs
+                    .create_archive(root.0, root.1, path, content.to_vec())?;
+                return Ok(());
+            }
+            Record::HardLink { path, target } => {
+                // The link target may have been filtered out
+                if self.is_excluded_in_feed(target, false)
+                    || !self.filter.is_included(target, false)
+                {
+                    return Ok(());
+                }
+                self.fs.create_hard_link(root.0, root.1, path, target)?;
+                return Ok(());
+            }
+        };
+        self.set_mtime(node_id, mtime);
+        Ok(())
+    }
+
+    /// Creates a file at `path`. With deduplication, a hard link to a file
+    /// packed in this run with the same content and timestamp is created
+    /// instead, so that the content is embedded once.
+    fn create_file(
+        &mut self,
+        dir: (S::NodeId, S::LinkId),
+        path: &str,
+        content: Vec<u8>,
+        mtime: wasi::Timestamp,
+    ) -> Result<(), u16> {
+        let mtime = self.mtime.unwrap_or(mtime);
+        let Some(packed_files) = &mut self.packed_files else {
+            let (node_id, _) = self.fs.create_file(dir.0, dir.1, path, content)?;
+            self.fs.set_mtime(node_id, mtime);
+            return Ok(());
+        };
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let candidates = packed_files.entry(hasher.finish()).or_default();
+        // Candidates may have been replaced by later entries since packed
+        let same_file = candidates
+            .iter()
+            .find(|node_id| self.fs.is_same_file(**node_id, &content, mtime));
+        if let Some(node_id) = same_file {
+            if self.verbose {
+                trace::print(format!(
+                    "dedup file: {}{} (size {})\n",
+                    self.current_mount.trim_end_matches('/'),
+                    path,
+                    content.len()
+                ));
+            }
+            self.fs.link_node(dir.0, dir.1, path, *node_id)?;
+            return Ok(());
+        }
+        let (node_id, _) = self.fs.create_file(dir.0, dir.1, path, content)?;
+        self.fs.set_mtime(node_id, mtime);
+        candidates.push(node_id);
+        Ok(())
+    }
+
+    fn set_mtime(&mut self, node_id: S::NodeId, mtime: wasi::Timestamp) {
+        self.fs.set_mtime(node_id, self.mtime.unwrap_or(mtime));
+    }
+}
+
+impl<S: Storage> DirVisitor<S> for FsPacker<S> {
+    fn visit_dir(
+        &mut self,
+        path: &str,
+        fd: u32,
+        preopened_id: (S::NodeId, S::LinkId),
+    ) -> Result<(), u16> {
+        // When include patterns are given, directories are created only
+        // as parents of included files unless they are included explicitly.
+        if self.filter.has_includes() && !self.filter.is_included(path, true) {
+            return Ok(());
+        }
+        let stat = unsafe { wasi::fd_filestat_get(fd) }.map_err(|e| e.raw())?;
+        let (node_id, _) = self.fs.create_dir(preopened_id.0, preopened_id.1, path)?;
+        self.set_mtime(node_id, stat.mtim);
+        Ok(())
+    }
+
+    fn visit_file(
+        &mut self,
+        path: &str,
+        fd: u32,
+        preopened_id: (S::NodeId, S::LinkId),
+    ) -> Result<(), u16> {
+        if !self.filter.is_included(path, false) {
+            return Ok(());
+        }
+        let stat = unsafe { wasi::fd_filestat_get(fd) }.map_err(|e| e.raw())?;
+        if stat.size >= u32::MAX as u64 {
+            return self.visit_unsupported(
+                path,
+                &format!("too large file (size {})", stat.size),
+                wasi::ERRNO_FBIG,
+            );
+        }
+        let buf = read_to_end(fd).map_err(|e| e.raw())?;
+        if self.verbose {
+            trace::print(format!(
+                "pack file: {} under node-id={} (size {})\n",
+                path,
+                preopened_id.0.ino(),
+                buf.len()
+            ));
+        }
+        self.create_file(preopened_id, path, buf, stat.mtim)
+    }
+
+    fn visit_symlink(
+        &mut self,
+        path: &str,
+        target: String,
+        mtime: wasi::Timestamp,
+        preopened_id: (S::NodeId, S::LinkId),
+    ) -> Result<(), u16> {
+        if !self.filter.is_included(path, false) {
+            return Ok(());
+        }
+        if self.verbose {
+            trace::print(format!(
+                "pack symlink: {} under node-id={} (-> {})\n",
+                path,
+                preopened_id.0.ino(),
+                target
+            ));
+        }
+        let (node_id, _) = self
+            .fs
+            .create_symlink(preopened_id.0, preopened_id.1, path, target)?;
+        self.set_mtime(node_id, mtime);
+        Ok(())
+    }
+
+    fn visit_unsupported(
+        &mut self,
+        path: &str,
+        reason: &str,
+        errno: wasi::Errno,
+    ) -> Result<(), u16> {
+        if self.strict {
+            return Err(errno.raw());
+        }
+        trace::eprint(format!(
+            "wasi-vfs: warning: skipping {}{} ({})\n",
+            self.current_mount.trim_end_matches('/'),
+            path,
+            reason.to_lowercase()
+        ));
+        Ok(())
+    }
+
+    fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        let excluded = self.filter.is_excluded(path, is_dir);
+        if excluded && self.verbose {
+            trace::print(format!(
+                "exclude: {}{}\n",
+                self.current_mount.trim_end_matches('/'),
+                path
+            ));
+        }
+        excluded
+    }
+}
@@ -0,0 +1,219 @@
+//! Codec of the tree stream format, a flat serialization of directory trees
+//! exchanged with the CLI through the control directory.
+//!
+//! The format must be kept in sync with `crates/wasi-vfs-cli/src/stream.rs`.
+//!
+//! ```text
+//! stream  := MAGIC version:u32 record*
+//! record  := 'M' path                     ; start of a new mount at a guest dir
+//!          | 'D' path mtime:u64           ; directory
+//!          | 'F' path mtime:u64 content   ; regular file
+//!          | 'L' path mtime:u64 target    ; symbolic link
+//!          | 'H' path target              ; hard link to a previous entry
+//!          | 'T' path content             ; uncompressed tar archive placed at a dir
+//! path    := string                       ; relative to the mount, starts with '/'
+//! string  := len:u32 utf8-bytes
+//! content := len:u64 bytes
+//! ```
+//!
+//! All integers are little-endian.
+
+const MAGIC: &[u8; 8] = b"WVFSTREE";
+const VERSION: u32 = 1;
+
+pub(crate) enum Record<'a> {
+    Mount {
+        guest_dir: &'a str,
+    },
+    Dir {
+        path: &'a str,
+        mtime: u64,
+    },
+    File {
+        path: &'a str,
+        mtime: u64,
+        content: &'a [u8],
+    },
+    Symlink {
+        path: &'a str,
+        mtime: u64,
+        target: &'a str,
+    },
+    HardLink {
+        path: &'a str,
+        target: &'a str,
+    },
+    Archive {
+        path: &'a str,
+        content: &'a [u8],
+    },
+}
+
+impl<'a> Record<'a> {
+    /// The path of the entry relative to the current mount.
+    pub(crate) fn path(&self) -> &'a str {
+        match self {
+            Record::Mount { .. } => "",
+            Record::Dir { path, .. }
+            | Record::File { path, .. }
+            | Record::Symlink { path, .. }
+            | Record::HardLink { path, .. }
+            | Record::Archive { path, .. } => path,
+        }
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Returns `ERRNO_ILSEQ` if `data` doesn't start with a valid header.
+    pub(crate) fn new(data: &'a [u8]) -> Result<Self, u16> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+            return Err(wasi::ERRNO_ILSEQ.raw());
+        }
+        Ok(reader)
+    }
+
+    /// Returns the next record, or `None` at the end of the stream.
+    pub(crate) fn next_record(&mut self) -> Result<Option<Record<'a>>, u16> {
+        let kind = match self.data.first() {
+            Some(kind) => *kind,
+            None => return Ok(None),
+        };
+        self.data = &self.data[1..];
+        let record = match kind {
+            b'M' => Record::Mount {
+                guest_dir: self.string()?,
+            },
+            b'D' => Record::Dir {
+                path: self.string()?,
+                mtime: self.u64()?,
+            },
+            b'F' => Record::File {
+                path: self.string()?,
+                mtime: self.u64()?,
+                content: self.content()?,
+            },
+            b'L' => Record::Symlink {
+                path: self.string()?,
+                mtime: self.u64()?,
+                target: self.string()?,
+            },
+            b'H' => Record::HardLink {
+                path: self.string()?,
+                target: self.string()?,
+            },
+            b'T' => Record::Archive {
+                path: self.string()?,
+                content: self.content()?,
+            },
+            _ => return Err(wasi::ERRNO_ILSEQ.raw()),
+        };
+        Ok(Some(record))
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], u16> {
+        if self.data.len() < len {
+            return Err(wasi::ERRNO_ILSEQ.raw());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, u16> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, u16> {
+        let bytes = self.bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn content(&mut self) -> Result<&'a [u8], u16> {
+        let len = usize::try_from(self.u64()?).map_err(|_| wasi::ERRNO_FBIG.raw())?;
+        self.bytes(len)
+    }
+
+    fn string(&mut self) -> Result<&'a str, u16> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.bytes(len)?).map_err(|_| wasi::ERRNO_ILSEQ.raw())
+    }
+}
+
+/// Encodes records into an in-memory buffer.
+pub(crate) struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new() -> Self {
+        let mut buffer = MAGIC.to_vec();
+        buffer.extend_from_slice(&VERSION.to_le_bytes());
+        Writer { buffer }
+    }
+
+    pub(crate) fn write(&mut self, record: &Record) {
+        match record {
+            Record::Mount { guest_dir } => {
+                self.buffer.push(b'M');
+                self.string(guest_dir);
+            }
+            Record::Dir { path, mtime } => {
+                self.buffer.push(b'D');
+                self.string(path);
+                self.buffer.extend_from_slice(&mtime.to_le_bytes());
+            }
+            Record::File {
+                path,
+                mtime,
+                content,
+            } => {
+                self.buffer.push(b'F');
+                self.string(path);
+                self.buffer.extend_from_slice(&mtime.to_le_bytes());
+                self.content(content);
+            }
+            Record::Symlink {
+                path,
+                mtime,
+                target,
+            } => {
+                self.buffer.push(b'L');
+                self.string(path);
+                self.buffer.extend_from_slice(&mtime.to_le_bytes());
+                self.string(target);
+            }
+            Record::HardLink { path, target } => {
+                self.buffer.push(b'H');
+                self.string(path);
+                self.string(target);
+            }
+            Record::Archive { path, content } => {
+                self.buffer.push(b'T');
+                self.string(path);
+                self.content(content);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    fn content(&mut self, content: &[u8]) {
+        self.buffer
+            .extend_from_slice(&(content.len() as u64).to_le_bytes());
+        self.buffer.extend_from_slice(content);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.buffer
+            .extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.buffer.extend_from_slice(s.as_bytes());
+    }
+}
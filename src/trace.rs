@@ -1,51 +1,636 @@
+//! Tracing of hooked syscalls enabled by `WASI_VFS_TRACE` with the
+//! `trace-syscall` feature. Each call is printed after it returns, like
+//! `fd_read(fd: 5<virtual>, iovs: [1024]) = 6`, or as a JSON line, and a
+//! summary of the traced calls is printed at exit. Arguments and results are
+//! decoded by the generated trampolines with the helpers below.
+
+#[cfg(feature = "trace-syscall")]
+use crate::{BackingFd, DefaultStorage, FileSystem, UserFd, lock_global_state};
 #[cfg(feature = "trace-syscall")]
-fn is_tracing_enabled() -> bool {
-    extern "C" {
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Mutex, MutexGuard, OnceLock},
+};
+
+#[cfg(feature = "trace-syscall")]
+type Overlay<'a> = Option<&'a FileSystem<DefaultStorage>>;
+
+#[cfg(feature = "trace-syscall")]
+const STDERR: wasi::Fd = 2;
+
+#[cfg(feature = "trace-syscall")]
+fn getenv(name: &str) -> Option<String> {
+    unsafe extern "C" {
         fn getenv(name: *const i8) -> *const i8;
     }
-    let wasi_vfs_trace = std::ffi::CString::new("WASI_VFS_TRACE").unwrap();
-    unsafe { !getenv(wasi_vfs_trace.as_ptr()).is_null() }
+    let name = std::ffi::CString::new(name).unwrap();
+    let value = unsafe { getenv(name.as_ptr()) };
+    if value.is_null() {
+        return None;
+    }
+    let value = unsafe { std::ffi::CStr::from_ptr(value) };
+    Some(value.to_string_lossy().into_owned())
+}
+
+/// Options given by `WASI_VFS_TRACE` as a comma-separated list. `json`
+/// selects the JSON-lines format, `fd=N` keeps calls on the fd N, and other
+/// items keep calls to the syscall of that name. `1` or `all` traces every
+/// call.
+#[cfg(feature = "trace-syscall")]
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    json: bool,
+    syscalls: Vec<String>,
+    fds: Vec<UserFd>,
+}
+
+#[cfg(feature = "trace-syscall")]
+impl Options {
+    fn parse(value: &str) -> Self {
+        let mut options = Self::default();
+        for item in value.split(',').map(str::trim) {
+            match item {
+                "" | "1" | "all" => {}
+                "json" => options.json = true,
+                _ => match item.strip_prefix("fd=") {
+                    Some(fd) => options.fds.extend(fd.parse::<UserFd>()),
+                    None => options.syscalls.push(item.to_string()),
+                },
+            }
+        }
+        options
+    }
+
+    /// Returns the names kept which are not hooked syscalls, so that no call
+    /// matches them.
+    fn unknown_syscalls(&self) -> impl Iterator<Item = &str> {
+        self.syscalls
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !crate::trampoline_generated::SYSCALL_NAMES.contains(name))
+    }
+
+    /// Name and fd filters are combined, so a call has to match both kinds
+    /// if both are given.
+    fn matches(&self, name: &str, fd: Option<UserFd>) -> bool {
+        (self.syscalls.is_empty() || self.syscalls.iter().any(|syscall| syscall == name))
+            && (self.fds.is_empty() || fd.is_some_and(|fd| self.fds.contains(&fd)))
+    }
+}
+
+/// Options read by `init`, or `None` if tracing is disabled. Calls made
+/// before `init` are not traced.
+#[cfg(feature = "trace-syscall")]
+static OPTIONS: OnceLock<Option<Options>> = OnceLock::new();
+
+/// Read `WASI_VFS_TRACE` once at startup, not on each hooked call. It's
+/// called before wasi-libc loads environment variables.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn init() {
+    OPTIONS.get_or_init(|| {
+        let options = Options::parse(&crate::env_var("WASI_VFS_TRACE")?);
+        for name in options.unknown_syscalls() {
+            eprint(format!(
+                "wasi-vfs: WASI_VFS_TRACE: `{}` is not a traced syscall\n",
+                name
+            ));
+        }
+        Some(options)
+    });
+}
+
+/// Where a call is served. Calls without an fd known to wasi-vfs are
+/// attributed to the host.
+#[cfg(feature = "trace-syscall")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Backend {
+    Virtual,
+    Host,
+}
+
+#[cfg(feature = "trace-syscall")]
+impl Backend {
+    fn of(fs: Overlay<'_>, fd: Option<UserFd>) -> Self {
+        match fs.zip(fd).map(|(fs, fd)| fs.get_backing_fd(fd)) {
+            Some(Ok(BackingFd::Virtual(_) | BackingFd::Device(_))) => Backend::Virtual,
+            _ => Backend::Host,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Virtual => "virtual",
+            Backend::Host => "host",
+        }
+    }
+}
+
+/// A traced call in progress, returned by `enter`.
+#[cfg(feature = "trace-syscall")]
+pub(crate) struct Trace {
+    name: &'static str,
+    fd: Option<UserFd>,
+    backend: Backend,
+    args: String,
+    json: bool,
+    /// Wall-clock time when the call was made, in nanoseconds
+    timestamp: u64,
+    /// Monotonic time when the call was made, in nanoseconds
+    start: u64,
+}
+
+#[cfg(feature = "trace-syscall")]
+impl Trace {
+    /// Render the line printed for the call, with its formatted result or
+    /// the name of its errno.
+    fn render(&self, result: &Result<String, String>, duration: u64) -> String {
+        if !self.json {
+            let result = match result {
+                Ok(result) | Err(result) => result,
+            };
+            return format!("{}({}) = {}\n", self.name, self.args, result);
+        }
+        let mut line = format!(
+            "{{\"event\":\"call\",\"timestamp_ns\":{},\"duration_ns\":{},\"syscall\":\"{}\"",
+            self.timestamp, duration, self.name
+        );
+        if let Some(fd) = self.fd {
+            write!(line, ",\"fd\":{}", fd).unwrap();
+        }
+        write!(
+            line,
+            ",\"backend\":\"{}\",\"args\":{}",
+            self.backend.name(),
+            json_string(&self.args)
+        )
+        .unwrap();
+        match result {
+            Ok(result) => writeln!(line, ",\"result\":{}}}", json_string(result)).unwrap(),
+            Err(errno) => writeln!(line, ",\"errno\":\"{}\"}}", errno).unwrap(),
+        }
+        line
+    }
+}
+
+#[cfg(feature = "trace-syscall")]
+fn now(clock: wasi::Clockid) -> u64 {
+    unsafe { wasi::clock_time_get(clock, 1) }.unwrap_or(0)
+}
+
+/// Format arguments of a call before it's made, as fds may be closed by it.
+/// Returns `None` if the call is not traced.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn enter(
+    name: &'static str,
+    fd: Option<UserFd>,
+    format_args: impl FnOnce(Overlay<'_>) -> String,
+) -> Option<Trace> {
+    let options = OPTIONS.get()?.as_ref()?;
+    if !options.matches(name, fd) {
+        return None;
+    }
+    let (args, backend) = {
+        let state = lock_global_state();
+        let fs = state.overlay_fs.as_ref();
+        (format_args(fs), Backend::of(fs, fd))
+    };
+    Some(Trace {
+        name,
+        fd,
+        backend,
+        args,
+        json: options.json,
+        timestamp: now(wasi::CLOCKID_REALTIME),
+        start: now(wasi::CLOCKID_MONOTONIC),
+    })
 }
 
+/// Print a call traced by `enter`, with the result formatted by
+/// `format_result` if it succeeded or the errno otherwise, and count it in
+/// the summary.
 #[cfg(feature = "trace-syscall")]
-pub(crate) fn trace_syscall_entry(args: std::fmt::Arguments<'_>) {
-    if !is_tracing_enabled() {
+pub(crate) fn exit(trace: Option<Trace>, ret: i32, format_result: impl FnOnce() -> String) {
+    let Some(trace) = trace else {
         return;
+    };
+    let duration = now(wasi::CLOCKID_MONOTONIC).saturating_sub(trace.start);
+    let result = if ret == 0 {
+        Ok(format_result())
+    } else {
+        Err(errno_name(ret))
+    };
+    let line = trace.render(&result, duration);
+    let mut tracer = lock_tracer();
+    tracer.record(&trace, &result);
+    tracer.write(&line);
+}
+
+#[cfg(feature = "trace-syscall")]
+#[derive(Debug, Default, PartialEq)]
+struct Stats {
+    calls: u64,
+    errors: u64,
+    bytes_read: u64,
+}
+
+/// Output and statistics of traced calls, shared by threads.
+#[cfg(feature = "trace-syscall")]
+struct Tracer {
+    /// Host fd opened on the first traced call
+    output: Option<wasi::Fd>,
+    json: bool,
+    stats: BTreeMap<(&'static str, Backend), Stats>,
+}
+
+#[cfg(feature = "trace-syscall")]
+static TRACER: Mutex<Tracer> = Mutex::new(Tracer {
+    output: None,
+    json: false,
+    stats: BTreeMap::new(),
+});
+
+#[cfg(feature = "trace-syscall")]
+fn lock_tracer() -> MutexGuard<'static, Tracer> {
+    TRACER
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(feature = "trace-syscall")]
+impl Tracer {
+    fn record(&mut self, trace: &Trace, result: &Result<String, String>) {
+        if self.stats.is_empty() {
+            unsafe extern "C" {
+                fn atexit(function: extern "C" fn()) -> i32;
+            }
+            unsafe {
+                atexit(print_summary);
+            }
+        }
+        self.json = trace.json;
+        let stats = self.stats.entry((trace.name, trace.backend)).or_default();
+        stats.calls += 1;
+        match result {
+            // Reads succeed with the number of bytes read
+            Ok(result) if is_read(trace.name) => stats.bytes_read += result.parse().unwrap_or(0),
+            Ok(_) => {}
+            Err(_) => stats.errors += 1,
+        }
     }
-    let message = std::fmt::format(args);
-    let data = [wasi::Ciovec {
-        buf: message.as_ptr(),
-        buf_len: message.len(),
-    }];
-    let stderr = 2;
+
+    fn write(&mut self, message: &str) {
+        let fd = *self.output.get_or_insert_with(open_output);
+        let data = [wasi::Ciovec {
+            buf: message.as_ptr(),
+            buf_len: message.len(),
+        }];
+        // Tracing must not make the traced application fail
+        let _ = unsafe { wasi::fd_write(fd, &data) };
+    }
+}
+
+#[cfg(feature = "trace-syscall")]
+fn is_read(name: &str) -> bool {
+    matches!(name, "fd_read" | "fd_pread")
+}
+
+#[cfg(feature = "trace-syscall")]
+extern "C" fn print_summary() {
+    let mut tracer = lock_tracer();
+    let summary = render_summary(&tracer.stats, tracer.json);
+    tracer.write(&summary);
+}
+
+/// Render the summary of traced calls as a table, or as a JSON line per row.
+#[cfg(feature = "trace-syscall")]
+fn render_summary(stats: &BTreeMap<(&'static str, Backend), Stats>, json: bool) -> String {
+    let mut summary = String::new();
+    if json {
+        for ((name, backend), stats) in stats {
+            writeln!(
+                summary,
+                "{{\"event\":\"summary\",\"syscall\":\"{}\",\"backend\":\"{}\",\"calls\":{},\"errors\":{},\"bytes_read\":{}}}",
+                name,
+                backend.name(),
+                stats.calls,
+                stats.errors,
+                stats.bytes_read
+            )
+            .unwrap();
+        }
+        return summary;
+    }
+    let mut rows = vec![["syscall", "backend", "calls", "errors", "bytes read"].map(String::from)];
+    let mut total = Stats::default();
+    for ((name, backend), stats) in stats {
+        let bytes = if is_read(name) {
+            stats.bytes_read.to_string()
+        } else {
+            "-".to_string()
+        };
+        rows.push([
+            name.to_string(),
+            backend.name().to_string(),
+            stats.calls.to_string(),
+            stats.errors.to_string(),
+            bytes,
+        ]);
+        total.calls += stats.calls;
+        total.errors += stats.errors;
+        total.bytes_read += stats.bytes_read;
+    }
+    rows.push([
+        "total".to_string(),
+        String::new(),
+        total.calls.to_string(),
+        total.errors.to_string(),
+        total.bytes_read.to_string(),
+    ]);
+    summary.push_str("wasi-vfs: summary of traced calls\n");
+    for [name, backend, calls, errors, bytes] in rows {
+        writeln!(
+            summary,
+            "{:<24}{:<8}{:>8}{:>8}{:>12}",
+            name, backend, calls, errors, bytes
+        )
+        .unwrap();
+    }
+    summary
+}
+
+/// Open the output given by `WASI_VFS_TRACE_FILE`, which is either a host fd
+/// number or an absolute path in a directory preopened by the host. Traces
+/// go to stderr by default.
+#[cfg(feature = "trace-syscall")]
+fn open_output() -> wasi::Fd {
+    let Some(file) = getenv("WASI_VFS_TRACE_FILE") else {
+        return STDERR;
+    };
+    if let Ok(fd) = file.parse::<wasi::Fd>() {
+        return fd;
+    }
+    match open_host_file(&file) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            eprint(format!(
+                "wasi-vfs: failed to open trace file {}: {}\n",
+                file, errno
+            ));
+            STDERR
+        }
+    }
+}
+
+/// Create a file at the absolute guest `path` with the host directly, since
+/// hooked calls would be traced in turn.
+#[cfg(feature = "trace-syscall")]
+fn open_host_file(path: &str) -> Result<wasi::Fd, wasi::Errno> {
+    let mut found: Option<(wasi::Fd, usize, String)> = None;
+    for fd in 3.. {
+        let Ok(prestat) = (unsafe { wasi::fd_prestat_get(fd) }) else {
+            break;
+        };
+        let mut prefix = vec![0; unsafe { prestat.u.dir.pr_name_len }];
+        unsafe { wasi::fd_prestat_dir_name(fd, prefix.as_mut_ptr(), prefix.len())? };
+        let prefix = String::from_utf8_lossy(&prefix);
+        let prefix = prefix.trim_end_matches('/');
+        // The longest prefix wins like in wasi-libc
+        let Some(relpath) = path
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+        else {
+            continue;
+        };
+        if found.as_ref().is_none_or(|(_, len, _)| prefix.len() > *len) {
+            found = Some((fd, prefix.len(), relpath.to_string()));
+        }
+    }
+    let (dirfd, _, relpath) = found.ok_or(wasi::ERRNO_NOENT)?;
     unsafe {
-        wasi::fd_write(stderr, &data).unwrap();
+        wasi::path_open(
+            dirfd,
+            0,
+            &relpath,
+            wasi::OFLAGS_CREAT | wasi::OFLAGS_TRUNC,
+            wasi::RIGHTS_FD_WRITE,
+            0,
+            0,
+        )
     }
 }
 
 #[cfg(feature = "trace-syscall")]
-pub(crate) fn trace_syscall_error(name: &str, errno: crate::Error) {
-    if !is_tracing_enabled() {
-        return;
+fn errno_name(errno: i32) -> String {
+    use crate::errno_generated::ERRNO_NAMES;
+    match ERRNO_NAMES.get(errno as usize) {
+        Some(name) => name.to_string(),
+        None => format!("errno {}", errno),
+    }
+}
+
+/// Quote a string for JSON.
+#[cfg(feature = "trace-syscall")]
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Format a result containing fds, whose backings are looked up in the
+/// overlay. Other results are formatted without locking it.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn with_overlay(format: impl FnOnce(Overlay<'_>) -> String) -> String {
+    format(lock_global_state().overlay_fs.as_ref())
+}
+
+/// Format an fd with its backing, like `5<virtual>` or `4<host:3>` if the
+/// host fd differs.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn fd(fs: Overlay<'_>, fd: UserFd) -> String {
+    let Some(fs) = fs else {
+        // Every fd is forwarded to the host as is
+        return format!("{}<host>", fd);
+    };
+    match fs.get_backing_fd(fd) {
+        Ok(BackingFd::Virtual(_)) => format!("{}<virtual>", fd),
+        Ok(BackingFd::Wasi(host_fd)) if host_fd == fd => format!("{}<host>", fd),
+        Ok(BackingFd::Wasi(host_fd)) => format!("{}<host:{}>", fd, host_fd),
+        Ok(BackingFd::Device(_)) => format!("{}<device>", fd),
+        Err(_) => format!("{}<unknown>", fd),
+    }
+}
+
+/// Format a string in the linear memory with quotes.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn string(ptr: *const u8, len: usize) -> String {
+    let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+    format!("{:?}", String::from_utf8_lossy(bytes))
+}
+
+/// Format lengths of buffers in an iovec array.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn buf_lens(lens: impl Iterator<Item = usize>) -> String {
+    let lens = lens.map(|len| len.to_string()).collect::<Vec<_>>();
+    format!("[{}]", lens.join(", "))
+}
+
+/// Format an enum value with the names of its cases.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn enum_name(value: u64, names: &[&str]) -> String {
+    match names.get(value as usize) {
+        Some(name) => name.to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Format flags with the names of their bits, and unknown bits in hex.
+#[cfg(feature = "trace-syscall")]
+pub(crate) fn flags(value: u64, names: &[&str]) -> String {
+    if value == 0 {
+        return "0".to_string();
     }
-    let message = format!("{} returns {}\n", name, errno.raw());
+    let mut set = vec![];
+    let mut unknown = value;
+    for (bit, name) in names.iter().enumerate() {
+        if value & (1 << bit) != 0 {
+            set.push(name.to_string());
+            unknown &= !(1 << bit);
+        }
+    }
+    if unknown != 0 {
+        set.push(format!("{:#x}", unknown));
+    }
+    set.join("|")
+}
+
+pub(crate) fn print(message: String) {
     let data = [wasi::Ciovec {
         buf: message.as_ptr(),
         buf_len: message.len(),
     }];
-    let stderr = 2;
+    let stdout = 1;
     unsafe {
-        wasi::fd_write(stderr, &data).unwrap();
+        wasi::fd_write(stdout, &data).unwrap();
     }
 }
 
-pub(crate) fn print(message: String) {
+pub(crate) fn eprint(message: String) {
     let data = [wasi::Ciovec {
         buf: message.as_ptr(),
         buf_len: message.len(),
     }];
-    let stdout = 1;
+    let stderr = 2;
     unsafe {
-        wasi::fd_write(stdout, &data).unwrap();
+        wasi::fd_write(stderr, &data).unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "trace-syscall"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_values() {
+        let names = ["OFLAGS_CREAT", "OFLAGS_DIRECTORY", "OFLAGS_EXCL"];
+        assert_eq!(flags(0, &names), "0");
+        assert_eq!(flags(0b101, &names), "OFLAGS_CREAT|OFLAGS_EXCL");
+        assert_eq!(flags(0b10010, &names), "OFLAGS_DIRECTORY|0x10");
+        let names = ["WHENCE_SET", "WHENCE_CUR", "WHENCE_END"];
+        assert_eq!(enum_name(2, &names), "WHENCE_END");
+        assert_eq!(enum_name(3, &names), "3");
+        assert_eq!(errno_name(44), "ENOENT");
+        assert_eq!(buf_lens([16, 1024].into_iter()), "[16, 1024]");
+        let path = b"dir/\"a\"";
+        assert_eq!(string(path.as_ptr(), path.len()), r#""dir/\"a\"""#);
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = Options::parse("1");
+        assert_eq!(options, Options::default());
+        assert!(options.matches("fd_read", None));
+        assert_eq!(Options::parse("all"), Options::default());
+
+        let options = Options::parse("path_open,fd_read");
+        assert!(!options.json);
+        assert!(options.matches("fd_read", Some(5)));
+        assert!(!options.matches("fd_write", Some(5)));
+
+        let options = Options::parse("json,fd=5,fd_read");
+        assert!(options.json);
+        assert!(options.matches("fd_read", Some(5)));
+        assert!(!options.matches("fd_read", Some(6)));
+        assert!(!options.matches("fd_read", None));
+        assert!(!options.matches("path_open", Some(5)));
+
+        let options = Options::parse("0,fd_read,path_opne");
+        assert!(options.unknown_syscalls().eq(["0", "path_opne"]));
+    }
+
+    #[test]
+    fn test_render_trace() {
+        let mut trace = Trace {
+            name: "path_open",
+            fd: Some(4),
+            backend: Backend::Virtual,
+            args: r#"fd: 4<virtual>, path: "a\"b""#.to_string(),
+            json: false,
+            timestamp: 1000,
+            start: 0,
+        };
+        assert_eq!(
+            trace.render(&Err("ENOENT".to_string()), 20),
+            "path_open(fd: 4<virtual>, path: \"a\\\"b\") = ENOENT\n"
+        );
+        trace.json = true;
+        assert_eq!(
+            trace.render(&Ok("5<virtual>".to_string()), 20),
+            r#"{"event":"call","timestamp_ns":1000,"duration_ns":20,"syscall":"path_open","fd":4,"backend":"virtual","args":"fd: 4<virtual>, path: \"a\\\"b\"","result":"5<virtual>"}"#
+                .to_string()
+                + "\n"
+        );
+        assert_eq!(json_string("a\n\u{1}"), r#""a\n\u0001""#);
+    }
+
+    #[test]
+    fn test_render_summary() {
+        let mut stats = BTreeMap::new();
+        let read = Stats {
+            calls: 3,
+            errors: 1,
+            bytes_read: 18,
+        };
+        stats.insert(("fd_read", Backend::Virtual), read);
+        let open = Stats {
+            calls: 2,
+            errors: 0,
+            bytes_read: 0,
+        };
+        stats.insert(("path_open", Backend::Host), open);
+        assert_eq!(
+            render_summary(&stats, false),
+            "\
+wasi-vfs: summary of traced calls
+syscall                 backend    calls  errors  bytes read
+fd_read                 virtual        3       1          18
+path_open               host           2       0           -
+total                                  5       1          18
+"
+        );
+        assert_eq!(
+            render_summary(&stats, true).lines().next().unwrap(),
+            r#"{"event":"summary","syscall":"fd_read","backend":"virtual","calls":3,"errors":1,"bytes_read":18}"#
+        );
     }
 }
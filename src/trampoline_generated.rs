@@ -13,34 +13,44 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_advise(
     arg3: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_advise(fd: {}, offset: {}, len: {}, advice: {})\n",
-        arg0, arg1, arg2, arg3
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_advise", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, offset: {}, len: {}, advice: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            (arg1 as u64),
+            (arg2 as u64),
+            crate::trace::enum_name(
+                arg3 as u64,
+                &[
+                    "ADVICE_NORMAL",
+                    "ADVICE_SEQUENTIAL",
+                    "ADVICE_RANDOM",
+                    "ADVICE_WILLNEED",
+                    "ADVICE_DONTNEED",
+                    "ADVICE_NOREUSE"
+                ]
+            )
+        )
+    });
+    let ret = unsafe { hook_fd_advise(arg0, arg1, arg2, arg3) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_advise(arg0: i32, arg1: i64, arg2: i64, arg3: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_advise(arg0, arg1, arg2, arg3) };
     };
     {
         match crate::wasi_snapshot_preview1::fd_advise(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as u64,
             arg2 as u64,
             arg3,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_advise", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -51,83 +61,76 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_allocate(
     arg2: i64,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_allocate(fd: {}, offset: {}, len: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_allocate", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, offset: {}, len: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            (arg1 as u64),
+            (arg2 as u64)
+        )
+    });
+    let ret = unsafe { hook_fd_allocate(arg0, arg1, arg2) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_allocate(arg0: i32, arg1: i64, arg2: i64) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_allocate(arg0, arg1, arg2) };
     };
     {
         match crate::wasi_snapshot_preview1::fd_allocate(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as u64,
             arg2 as u64,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_allocate", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_close(arg0: i32) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_close(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_close", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_close(arg0) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_close(arg0: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_close(arg0) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_close(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_close(&mut fs, arg0 as UserFd) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_close", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_datasync(arg0: i32) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_datasync(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_datasync", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_datasync(arg0) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_datasync(arg0: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_datasync(arg0) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_datasync(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_datasync(&mut fs, arg0 as UserFd) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_datasync", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -137,28 +140,55 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_fdstat_get(
     arg1: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_fdstat_get(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_fdstat_get", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_fdstat_get(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg1 as *const Fdstat) };
+        format!(
+            "{{fs_filetype: {}, fs_flags: {}, fs_rights_base: {:#x}, fs_rights_inheriting: {:#x}}}",
+            crate::trace::enum_name(
+                value.fs_filetype.raw() as u64,
+                &[
+                    "FILETYPE_UNKNOWN",
+                    "FILETYPE_BLOCK_DEVICE",
+                    "FILETYPE_CHARACTER_DEVICE",
+                    "FILETYPE_DIRECTORY",
+                    "FILETYPE_REGULAR_FILE",
+                    "FILETYPE_SOCKET_DGRAM",
+                    "FILETYPE_SOCKET_STREAM",
+                    "FILETYPE_SYMBOLIC_LINK"
+                ]
+            ),
+            crate::trace::flags(
+                value.fs_flags as u64,
+                &[
+                    "FDFLAGS_APPEND",
+                    "FDFLAGS_DSYNC",
+                    "FDFLAGS_NONBLOCK",
+                    "FDFLAGS_RSYNC",
+                    "FDFLAGS_SYNC"
+                ]
+            ),
+            value.fs_rights_base,
+            value.fs_rights_inheriting
+        )
+    });
+    ret
+}
+unsafe fn hook_fd_fdstat_get(arg0: i32, arg1: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_fdstat_get(arg0, arg1) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_fdstat_get(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_fdstat_get(&mut fs, arg0 as UserFd) {
             Ok(e) => {
                 core::ptr::write(arg1 as *mut Fdstat, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_fdstat_get", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -168,32 +198,39 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_fdstat_set_flags(
     arg1: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_fdstat_set_flags(fd: {}, flags: {})\n",
-        arg0, arg1
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_fdstat_set_flags", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, flags: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::flags(
+                arg1 as u64,
+                &[
+                    "FDFLAGS_APPEND",
+                    "FDFLAGS_DSYNC",
+                    "FDFLAGS_NONBLOCK",
+                    "FDFLAGS_RSYNC",
+                    "FDFLAGS_SYNC"
+                ]
+            )
+        )
+    });
+    let ret = unsafe { hook_fd_fdstat_set_flags(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_fdstat_set_flags(arg0: i32, arg1: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_fdstat_set_flags(arg0, arg1) };
     };
     {
         match crate::wasi_snapshot_preview1::fd_fdstat_set_flags(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as Fdflags,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_fdstat_set_flags", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -204,33 +241,32 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_fdstat_set_rights(
     arg2: i64,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_fdstat_set_rights(fd: {}, fs_rights_base: {}, fs_rights_inheriting: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_fdstat_set_rights", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, fs_rights_base: {:#x}, fs_rights_inheriting: {:#x}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            arg1 as u64,
+            arg2 as u64
+        )
+    });
+    let ret = unsafe { hook_fd_fdstat_set_rights(arg0, arg1, arg2) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_fdstat_set_rights(arg0: i32, arg1: i64, arg2: i64) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_fdstat_set_rights(arg0, arg1, arg2) };
     };
     {
         match crate::wasi_snapshot_preview1::fd_fdstat_set_rights(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as Rights,
             arg2 as Rights,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_fdstat_set_rights", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -240,28 +276,50 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_filestat_get(
     arg1: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_filestat_get(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_filestat_get", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_filestat_get(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg1 as *const Filestat) };
+        format!(
+            "{{dev: {}, ino: {}, filetype: {}, nlink: {}, size: {}, atim: {}, mtim: {}, ctim: {}}}",
+            value.dev,
+            value.ino,
+            crate::trace::enum_name(
+                value.filetype.raw() as u64,
+                &[
+                    "FILETYPE_UNKNOWN",
+                    "FILETYPE_BLOCK_DEVICE",
+                    "FILETYPE_CHARACTER_DEVICE",
+                    "FILETYPE_DIRECTORY",
+                    "FILETYPE_REGULAR_FILE",
+                    "FILETYPE_SOCKET_DGRAM",
+                    "FILETYPE_SOCKET_STREAM",
+                    "FILETYPE_SYMBOLIC_LINK"
+                ]
+            ),
+            value.nlink,
+            value.size,
+            value.atim,
+            value.mtim,
+            value.ctim
+        )
+    });
+    ret
+}
+unsafe fn hook_fd_filestat_get(arg0: i32, arg1: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_filestat_get(arg0, arg1) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_filestat_get(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_filestat_get(&mut fs, arg0 as UserFd) {
             Ok(e) => {
                 core::ptr::write(arg1 as *mut Filestat, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_filestat_get", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -271,28 +329,30 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_filestat_set_size(
     arg1: i64,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_filestat_set_size(fd: {}, size: {})\n",
-        arg0, arg1
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_filestat_set_size", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, size: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            (arg1 as u64)
+        )
+    });
+    let ret = unsafe { hook_fd_filestat_set_size(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_filestat_set_size(arg0: i32, arg1: i64) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_filestat_set_size(arg0, arg1) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_filestat_set_size(fs, arg0 as UserFd, arg1 as u64) {
+        match crate::wasi_snapshot_preview1::fd_filestat_set_size(
+            &mut fs,
+            arg0 as UserFd,
+            arg1 as u64,
+        ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_filestat_set_size", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -304,36 +364,44 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_filestat_set_times(
     arg3: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_filestat_set_times(fd: {}, atim: {}, mtim: {}, fst_flags: {})\n",
-        arg0, arg1, arg2, arg3
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_filestat_set_times", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, atim: {}, mtim: {}, fst_flags: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            (arg1 as u64),
+            (arg2 as u64),
+            crate::trace::flags(
+                arg3 as u64,
+                &[
+                    "FSTFLAGS_ATIM",
+                    "FSTFLAGS_ATIM_NOW",
+                    "FSTFLAGS_MTIM",
+                    "FSTFLAGS_MTIM_NOW"
+                ]
+            )
+        )
+    });
+    let ret = unsafe { hook_fd_filestat_set_times(arg0, arg1, arg2, arg3) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_filestat_set_times(arg0: i32, arg1: i64, arg2: i64, arg3: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::fd_filestat_set_times(arg0, arg1, arg2, arg3)
         };
     };
     {
         match crate::wasi_snapshot_preview1::fd_filestat_set_times(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as u64,
             arg2 as u64,
             arg3 as Fstflags,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_filestat_set_times", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -346,17 +414,28 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_pread(
     arg4: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_pread(fd: {}, iovs: {}, iovs_len: {}, offset: {})\n",
-        arg0, arg1, arg2, arg3
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_pread", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, iovs: {}, offset: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::buf_lens(
+                unsafe { core::slice::from_raw_parts(arg1 as *const Iovec, arg2 as usize) }
+                    .iter()
+                    .map(|buf| buf.buf_len)
+            ),
+            (arg3 as u64)
+        )
+    });
+    let ret = unsafe { hook_fd_pread(arg0, arg1, arg2, arg3, arg4) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg4 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_pread(arg0: i32, arg1: i32, arg2: i32, arg3: i64, arg4: i32) -> i32 {
+    let Some(fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_pread(arg0, arg1, arg2, arg3, arg4) };
     };
     {
@@ -370,12 +449,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_pread(
                 core::ptr::write(arg4 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_pread", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -385,28 +459,34 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_prestat_get(
     arg1: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_prestat_get(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_prestat_get", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_prestat_get(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg1 as *const Prestat) };
+        match value.tag {
+            0 => format!(
+                "{{dir: {{pr_name_len: {}}}}}",
+                unsafe { value.u.dir }.pr_name_len
+            ),
+            tag => format!("{{tag: {}}}", tag),
+        }
+    });
+    ret
+}
+unsafe fn hook_fd_prestat_get(arg0: i32, arg1: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_prestat_get(arg0, arg1) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_prestat_get(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_prestat_get(&mut fs, arg0 as UserFd) {
             Ok(e) => {
                 core::ptr::write(arg1 as *mut Prestat, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_prestat_get", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -417,33 +497,32 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_prestat_dir_name(
     arg2: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_prestat_dir_name(fd: {}, path: {}, path_len: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_prestat_dir_name", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, path: {:#x}, path_len: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            arg1 as u32,
+            (arg2 as u32)
+        )
+    });
+    let ret = unsafe { hook_fd_prestat_dir_name(arg0, arg1, arg2) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_prestat_dir_name(arg0: i32, arg1: i32, arg2: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_prestat_dir_name(arg0, arg1, arg2) };
     };
     {
         match crate::wasi_snapshot_preview1::fd_prestat_dir_name(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as *mut u8,
             arg2 as u32,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_prestat_dir_name", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -456,17 +535,28 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_pwrite(
     arg4: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_pwrite(fd: {}, iovs: {}, iovs_len: {}, offset: {})\n",
-        arg0, arg1, arg2, arg3
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_pwrite", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, iovs: {}, offset: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::buf_lens(
+                unsafe { core::slice::from_raw_parts(arg1 as *const Ciovec, arg2 as usize) }
+                    .iter()
+                    .map(|buf| buf.buf_len)
+            ),
+            (arg3 as u64)
+        )
+    });
+    let ret = unsafe { hook_fd_pwrite(arg0, arg1, arg2, arg3, arg4) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg4 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_pwrite(arg0: i32, arg1: i32, arg2: i32, arg3: i64, arg4: i32) -> i32 {
+    let Some(fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_pwrite(arg0, arg1, arg2, arg3, arg4) };
     };
     {
@@ -480,12 +570,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_pwrite(
                 core::ptr::write(arg4 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_pwrite", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -497,17 +582,27 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_read(
     arg3: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_read(fd: {}, iovs: {}, iovs_len: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_read", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, iovs: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::buf_lens(
+                unsafe { core::slice::from_raw_parts(arg1 as *const Iovec, arg2 as usize) }
+                    .iter()
+                    .map(|buf| buf.buf_len)
+            )
+        )
+    });
+    let ret = unsafe { hook_fd_read(arg0, arg1, arg2, arg3) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg3 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_read(arg0: i32, arg1: i32, arg2: i32, arg3: i32) -> i32 {
+    let Some(fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_read(arg0, arg1, arg2, arg3) };
     };
     {
@@ -520,12 +615,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_read(
                 core::ptr::write(arg3 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_read", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -538,22 +628,30 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_readdir(
     arg4: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_readdir(fd: {}, buf: {}, buf_len: {}, cookie: {})\n",
-        arg0, arg1, arg2, arg3
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_readdir", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, buf: {:#x}, buf_len: {}, cookie: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            arg1 as u32,
+            (arg2 as u32),
+            (arg3 as u64)
+        )
+    });
+    let ret = unsafe { hook_fd_readdir(arg0, arg1, arg2, arg3, arg4) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg4 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_readdir(arg0: i32, arg1: i32, arg2: i32, arg3: i64, arg4: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_readdir(arg0, arg1, arg2, arg3, arg4) };
     };
     {
         match crate::wasi_snapshot_preview1::fd_readdir(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as *mut u8,
             arg2 as u32,
@@ -563,37 +661,33 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_readdir(
                 core::ptr::write(arg4 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_readdir", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_renumber(arg0: i32, arg1: i32) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_renumber(fd: {}, to: {})\n", arg0, arg1));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_renumber", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, to: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::fd(fs, arg1 as UserFd)
+        )
+    });
+    let ret = unsafe { hook_fd_renumber(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_renumber(arg0: i32, arg1: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_renumber(arg0, arg1) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_renumber(fs, arg0 as UserFd, arg1 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_renumber(&mut fs, arg0 as UserFd, arg1 as UserFd) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_renumber", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -605,84 +699,83 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_seek(
     arg3: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_seek(fd: {}, offset: {}, whence: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_seek", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, offset: {}, whence: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            arg1,
+            crate::trace::enum_name(arg2 as u64, &["WHENCE_SET", "WHENCE_CUR", "WHENCE_END"])
+        )
+    });
+    let ret = unsafe { hook_fd_seek(arg0, arg1, arg2, arg3) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg3 as *const Filesize) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_seek(arg0: i32, arg1: i64, arg2: i32, arg3: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_seek(arg0, arg1, arg2, arg3) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_seek(fs, arg0 as UserFd, arg1 as i64, arg2) {
+        match crate::wasi_snapshot_preview1::fd_seek(&mut fs, arg0 as UserFd, arg1 as i64, arg2) {
             Ok(e) => {
                 core::ptr::write(arg3 as *mut Filesize, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_seek", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_sync(arg0: i32) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_sync(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_sync", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_sync(arg0) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_fd_sync(arg0: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_sync(arg0) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_sync(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_sync(&mut fs, arg0 as UserFd) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_sync", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_tell(arg0: i32, arg1: i32) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!("fd_tell(fd: {})\n", arg0));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_tell", Some(arg0 as UserFd), |fs| {
+        format!("fd: {}", crate::trace::fd(fs, arg0 as UserFd))
+    });
+    let ret = unsafe { hook_fd_tell(arg0, arg1) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg1 as *const Filesize) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_tell(arg0: i32, arg1: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_tell(arg0, arg1) };
     };
     {
-        match crate::wasi_snapshot_preview1::fd_tell(fs, arg0 as UserFd) {
+        match crate::wasi_snapshot_preview1::fd_tell(&mut fs, arg0 as UserFd) {
             Ok(e) => {
                 core::ptr::write(arg1 as *mut Filesize, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_tell", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -694,17 +787,27 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_write(
     arg3: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "fd_write(fd: {}, iovs: {}, iovs_len: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("fd_write", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, iovs: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::buf_lens(
+                unsafe { core::slice::from_raw_parts(arg1 as *const Ciovec, arg2 as usize) }
+                    .iter()
+                    .map(|buf| buf.buf_len)
+            )
+        )
+    });
+    let ret = unsafe { hook_fd_write(arg0, arg1, arg2, arg3) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg3 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_fd_write(arg0: i32, arg1: i32, arg2: i32, arg3: i32) -> i32 {
+    let Some(fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::fd_write(arg0, arg1, arg2, arg3) };
     };
     {
@@ -717,12 +820,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_fd_write(
                 core::ptr::write(arg3 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("fd_write", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -733,36 +831,29 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_create_directory(
     arg2: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_create_directory(fd: {}, path: {})\n",
-        arg0,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_create_directory", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, path: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::string(arg1 as *const u8, arg2 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_create_directory(arg0, arg1, arg2) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_create_directory(arg0: i32, arg1: i32, arg2: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::path_create_directory(arg0, arg1, arg2) };
     };
     {
-        match crate::wasi_snapshot_preview1::path_create_directory(fs, arg0 as UserFd, {
+        match crate::wasi_snapshot_preview1::path_create_directory(&mut fs, arg0 as UserFd, {
             let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
             std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes)
         }) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_create_directory", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -775,30 +866,53 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_filestat_get(
     arg4: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_filestat_get(fd: {}, flags: {}, path: {})\n",
-        arg0,
-        arg1,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg2 as *const u8, (arg3 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_filestat_get", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, flags: {}, path: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::flags(arg1 as u64, &["LOOKUPFLAGS_SYMLINK_FOLLOW"]),
+            crate::trace::string(arg2 as *const u8, arg3 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_filestat_get(arg0, arg1, arg2, arg3, arg4) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg4 as *const Filestat) };
+        format!(
+            "{{dev: {}, ino: {}, filetype: {}, nlink: {}, size: {}, atim: {}, mtim: {}, ctim: {}}}",
+            value.dev,
+            value.ino,
+            crate::trace::enum_name(
+                value.filetype.raw() as u64,
+                &[
+                    "FILETYPE_UNKNOWN",
+                    "FILETYPE_BLOCK_DEVICE",
+                    "FILETYPE_CHARACTER_DEVICE",
+                    "FILETYPE_DIRECTORY",
+                    "FILETYPE_REGULAR_FILE",
+                    "FILETYPE_SOCKET_DGRAM",
+                    "FILETYPE_SOCKET_STREAM",
+                    "FILETYPE_SYMBOLIC_LINK"
+                ]
+            ),
+            value.nlink,
+            value.size,
+            value.atim,
+            value.mtim,
+            value.ctim
+        )
+    });
+    ret
+}
+unsafe fn hook_path_filestat_get(arg0: i32, arg1: i32, arg2: i32, arg3: i32, arg4: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::path_filestat_get(arg0, arg1, arg2, arg3, arg4)
         };
     };
     {
         match crate::wasi_snapshot_preview1::path_filestat_get(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as Lookupflags,
             {
@@ -810,12 +924,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_filestat_get(
                 core::ptr::write(arg4 as *mut Filestat, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_filestat_get", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -830,26 +939,40 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_filestat_set_times
     arg6: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_filestat_set_times(fd: {}, flags: {}, path: {}, atim: {}, mtim: {}, fst_flags: {})\n",
-        arg0,
-        arg1,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg2 as *const u8, (arg3 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        },
-        arg4,
-        arg5,
-        arg6
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_filestat_set_times", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, flags: {}, path: {}, atim: {}, mtim: {}, fst_flags: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::flags(arg1 as u64, &["LOOKUPFLAGS_SYMLINK_FOLLOW"]),
+            crate::trace::string(arg2 as *const u8, arg3 as usize),
+            (arg4 as u64),
+            (arg5 as u64),
+            crate::trace::flags(
+                arg6 as u64,
+                &[
+                    "FSTFLAGS_ATIM",
+                    "FSTFLAGS_ATIM_NOW",
+                    "FSTFLAGS_MTIM",
+                    "FSTFLAGS_MTIM_NOW"
+                ]
+            )
+        )
+    });
+    let ret = unsafe { hook_path_filestat_set_times(arg0, arg1, arg2, arg3, arg4, arg5, arg6) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_filestat_set_times(
+    arg0: i32,
+    arg1: i32,
+    arg2: i32,
+    arg3: i32,
+    arg4: i64,
+    arg5: i64,
+    arg6: i32,
+) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::path_filestat_set_times(
                 arg0, arg1, arg2, arg3, arg4, arg5, arg6,
@@ -858,7 +981,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_filestat_set_times
     };
     {
         match crate::wasi_snapshot_preview1::path_filestat_set_times(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as Lookupflags,
             {
@@ -870,12 +993,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_filestat_set_times
             arg6 as Fstflags,
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_filestat_set_times", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -890,36 +1008,38 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_link(
     arg6: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_link(old_fd: {}, old_flags: {}, old_path: {}, new_fd: {}, new_path: {})\n",
-        arg0,
-        arg1,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg2 as *const u8, (arg3 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        },
-        arg4,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg5 as *const u8, (arg6 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_link", None, |fs| {
+        format!(
+            "old_fd: {}, old_flags: {}, old_path: {}, new_fd: {}, new_path: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::flags(arg1 as u64, &["LOOKUPFLAGS_SYMLINK_FOLLOW"]),
+            crate::trace::string(arg2 as *const u8, arg3 as usize),
+            crate::trace::fd(fs, arg4 as UserFd),
+            crate::trace::string(arg5 as *const u8, arg6 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_link(arg0, arg1, arg2, arg3, arg4, arg5, arg6) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_link(
+    arg0: i32,
+    arg1: i32,
+    arg2: i32,
+    arg3: i32,
+    arg4: i32,
+    arg5: i32,
+    arg6: i32,
+) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::path_link(arg0, arg1, arg2, arg3, arg4, arg5, arg6)
         };
     };
     {
         match crate::wasi_snapshot_preview1::path_link(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             arg1 as Lookupflags,
             {
@@ -933,12 +1053,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_link(
             },
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_link", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -955,27 +1070,55 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_open(
     arg8: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_open(fd: {}, dirflags: {}, path: {}, oflags: {}, fs_rights_base: {}, fs_rights_inheriting: {}, fdflags: {})\n",
-        arg0,
-        arg1,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg2 as *const u8, (arg3 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        },
-        arg4,
-        arg5,
-        arg6,
-        arg7
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_open", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, dirflags: {}, path: {}, oflags: {}, fs_rights_base: {:#x}, fs_rights_inheriting: {:#x}, fdflags: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::flags(arg1 as u64, &["LOOKUPFLAGS_SYMLINK_FOLLOW"]),
+            crate::trace::string(arg2 as *const u8, arg3 as usize),
+            crate::trace::flags(
+                arg4 as u64,
+                &[
+                    "OFLAGS_CREAT",
+                    "OFLAGS_DIRECTORY",
+                    "OFLAGS_EXCL",
+                    "OFLAGS_TRUNC"
+                ]
+            ),
+            arg5 as u64,
+            arg6 as u64,
+            crate::trace::flags(
+                arg7 as u64,
+                &[
+                    "FDFLAGS_APPEND",
+                    "FDFLAGS_DSYNC",
+                    "FDFLAGS_NONBLOCK",
+                    "FDFLAGS_RSYNC",
+                    "FDFLAGS_SYNC"
+                ]
+            )
+        )
+    });
+    let ret = unsafe { hook_path_open(arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg8 as *const Fd) };
+        crate::trace::with_overlay(|fs| crate::trace::fd(fs, value))
+    });
+    ret
+}
+unsafe fn hook_path_open(
+    arg0: i32,
+    arg1: i32,
+    arg2: i32,
+    arg3: i32,
+    arg4: i32,
+    arg5: i64,
+    arg6: i64,
+    arg7: i32,
+    arg8: i32,
+) -> i32 {
+    let Some(fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::path_open(
                 arg0, arg1, arg2, arg3, arg4, arg5, arg6, arg7, arg8,
@@ -1000,12 +1143,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_open(
                 core::ptr::write(arg8 as *mut Fd, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_open", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -1019,31 +1157,39 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_readlink(
     arg5: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_readlink(fd: {}, path: {}, buf: {}, buf_len: {})\n",
-        arg0,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        },
-        arg3,
-        arg4
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_readlink", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, path: {}, buf: {:#x}, buf_len: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::string(arg1 as *const u8, arg2 as usize),
+            arg3 as u32,
+            (arg4 as u32)
+        )
+    });
+    let ret = unsafe { hook_path_readlink(arg0, arg1, arg2, arg3, arg4, arg5) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg5 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_path_readlink(
+    arg0: i32,
+    arg1: i32,
+    arg2: i32,
+    arg3: i32,
+    arg4: i32,
+    arg5: i32,
+) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::path_readlink(arg0, arg1, arg2, arg3, arg4, arg5)
         };
     };
     {
         match crate::wasi_snapshot_preview1::path_readlink(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             {
                 let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
@@ -1056,12 +1202,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_readlink(
                 core::ptr::write(arg5 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_readlink", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -1072,36 +1213,29 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_remove_directory(
     arg2: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_remove_directory(fd: {}, path: {})\n",
-        arg0,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_remove_directory", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, path: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::string(arg1 as *const u8, arg2 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_remove_directory(arg0, arg1, arg2) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_remove_directory(arg0: i32, arg1: i32, arg2: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::path_remove_directory(arg0, arg1, arg2) };
     };
     {
-        match crate::wasi_snapshot_preview1::path_remove_directory(fs, arg0 as UserFd, {
+        match crate::wasi_snapshot_preview1::path_remove_directory(&mut fs, arg0 as UserFd, {
             let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
             std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes)
         }) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_remove_directory", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -1115,35 +1249,36 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_rename(
     arg5: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_rename(fd: {}, old_path: {}, new_fd: {}, new_path: {})\n",
-        arg0,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        },
-        arg3,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg4 as *const u8, (arg5 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_rename", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, old_path: {}, new_fd: {}, new_path: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::string(arg1 as *const u8, arg2 as usize),
+            crate::trace::fd(fs, arg3 as UserFd),
+            crate::trace::string(arg4 as *const u8, arg5 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_rename(arg0, arg1, arg2, arg3, arg4, arg5) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_rename(
+    arg0: i32,
+    arg1: i32,
+    arg2: i32,
+    arg3: i32,
+    arg4: i32,
+    arg5: i32,
+) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe {
             wasi::wasi_snapshot_preview1::path_rename(arg0, arg1, arg2, arg3, arg4, arg5)
         };
     };
     {
         match crate::wasi_snapshot_preview1::path_rename(
-            fs,
+            &mut fs,
             arg0 as UserFd,
             {
                 let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
@@ -1156,12 +1291,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_rename(
             },
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_rename", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -1174,32 +1304,26 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_symlink(
     arg4: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_symlink(old_path: {}, fd: {}, new_path: {})\n",
-        {
-            let str_bytes = core::slice::from_raw_parts(arg0 as *const u8, (arg1 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        },
-        arg2,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg3 as *const u8, (arg4 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_symlink", None, |fs| {
+        format!(
+            "old_path: {}, fd: {}, new_path: {}",
+            crate::trace::string(arg0 as *const u8, arg1 as usize),
+            crate::trace::fd(fs, arg2 as UserFd),
+            crate::trace::string(arg3 as *const u8, arg4 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_symlink(arg0, arg1, arg2, arg3, arg4) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_symlink(arg0: i32, arg1: i32, arg2: i32, arg3: i32, arg4: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::path_symlink(arg0, arg1, arg2, arg3, arg4) };
     };
     {
         match crate::wasi_snapshot_preview1::path_symlink(
-            fs,
+            &mut fs,
             {
                 let str_bytes = core::slice::from_raw_parts(arg0 as *const u8, (arg1 + 1) as usize);
                 std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes)
@@ -1211,12 +1335,7 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_symlink(
             },
         ) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_symlink", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -1227,36 +1346,29 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_path_unlink_file(
     arg2: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "path_unlink_file(fd: {}, path: {})\n",
-        arg0,
-        {
-            let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
-            let cstr = std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes);
-            cstr.to_str().unwrap().to_string()
-        }
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("path_unlink_file", Some(arg0 as UserFd), |fs| {
+        format!(
+            "fd: {}, path: {}",
+            crate::trace::fd(fs, arg0 as UserFd),
+            crate::trace::string(arg1 as *const u8, arg2 as usize)
+        )
+    });
+    let ret = unsafe { hook_path_unlink_file(arg0, arg1, arg2) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || 0.to_string());
+    ret
+}
+unsafe fn hook_path_unlink_file(arg0: i32, arg1: i32, arg2: i32) -> i32 {
+    let Some(mut fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::path_unlink_file(arg0, arg1, arg2) };
     };
     {
-        match crate::wasi_snapshot_preview1::path_unlink_file(fs, arg0 as UserFd, {
+        match crate::wasi_snapshot_preview1::path_unlink_file(&mut fs, arg0 as UserFd, {
             let str_bytes = core::slice::from_raw_parts(arg1 as *const u8, (arg2 + 1) as usize);
             std::ffi::CStr::from_bytes_with_nul_unchecked(str_bytes)
         }) {
             Ok(e) => wasi::ERRNO_SUCCESS.raw() as i32,
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("path_unlink_file", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
@@ -1268,17 +1380,24 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_poll_oneoff(
     arg3: i32,
 ) -> i32 {
     #[cfg(feature = "trace-syscall")]
-    crate::trace::trace_syscall_entry(format_args!(
-        "poll_oneoff(in: {}, out: {}, nsubscriptions: {})\n",
-        arg0, arg1, arg2
-    ));
-    let fs = if let Some(fs) = unsafe {
-        (*std::ptr::addr_of_mut!(crate::GLOBAL_STATE))
-            .overlay_fs
-            .as_mut()
-    } {
-        fs
-    } else {
+    let trace = crate::trace::enter("poll_oneoff", None, |fs| {
+        format!(
+            "in: {:#x}, out: {:#x}, nsubscriptions: {}",
+            arg0 as u32,
+            arg1 as u32,
+            (arg2 as u32)
+        )
+    });
+    let ret = unsafe { hook_poll_oneoff(arg0, arg1, arg2, arg3) };
+    #[cfg(feature = "trace-syscall")]
+    crate::trace::exit(trace, ret, || {
+        let value = unsafe { core::ptr::read(arg3 as *const Size) };
+        value.to_string()
+    });
+    ret
+}
+unsafe fn hook_poll_oneoff(arg0: i32, arg1: i32, arg2: i32, arg3: i32) -> i32 {
+    let Some(fs) = crate::OverlayFs::lock() else {
         return unsafe { wasi::wasi_snapshot_preview1::poll_oneoff(arg0, arg1, arg2, arg3) };
     };
     {
@@ -1292,12 +1411,44 @@ pub unsafe extern "C" fn wasi_vfs_wasi_snapshot_preview1_poll_oneoff(
                 core::ptr::write(arg3 as *mut Size, e);
                 wasi::ERRNO_SUCCESS.raw() as i32
             }
-            Err(e) => {
-                #[cfg(feature = "trace-syscall")]
-                crate::trace::trace_syscall_error("poll_oneoff", e.clone());
-
-                e.raw() as i32
-            }
+            Err(e) => e.raw() as i32,
         }
     }
 }
+
+/// Names of the hooked syscalls, which are the ones that can be traced.
+#[cfg(feature = "trace-syscall")]
+pub(crate) const SYSCALL_NAMES: &[&str] = &[
+    "fd_advise",
+    "fd_allocate",
+    "fd_close",
+    "fd_datasync",
+    "fd_fdstat_get",
+    "fd_fdstat_set_flags",
+    "fd_fdstat_set_rights",
+    "fd_filestat_get",
+    "fd_filestat_set_size",
+    "fd_filestat_set_times",
+    "fd_pread",
+    "fd_prestat_get",
+    "fd_prestat_dir_name",
+    "fd_pwrite",
+    "fd_read",
+    "fd_readdir",
+    "fd_renumber",
+    "fd_seek",
+    "fd_sync",
+    "fd_tell",
+    "fd_write",
+    "path_create_directory",
+    "path_filestat_get",
+    "path_filestat_set_times",
+    "path_link",
+    "path_open",
+    "path_readlink",
+    "path_remove_directory",
+    "path_rename",
+    "path_symlink",
+    "path_unlink_file",
+    "poll_oneoff",
+];
@@ -17,8 +17,9 @@ use wasi::{
 };
 
 use crate::{
-    BackingFd, Error, FileSystem, UserFd,
-    embed::{Node, NodeDirBody, NodeFileBody, NodeIdTrait, Storage},
+    BackingFd, Error, FileSystem, OverlayFs, UserFd,
+    dev::{DEV_DIR, Device},
+    embed::{Node, NodeDirBody, NodeIdTrait, Storage},
 };
 
 pub(crate) unsafe fn fd_advise<S: Storage>(
@@ -30,7 +31,7 @@ pub(crate) unsafe fn fd_advise<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_advise(
                 fd as i32,
@@ -54,7 +55,7 @@ pub(crate) unsafe fn fd_allocate<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret =
                 wasi::wasi_snapshot_preview1::fd_allocate(fd as i32, offset as i64, len as i64);
@@ -70,6 +71,7 @@ pub(crate) unsafe fn fd_close<S: Storage>(fs: &mut FileSystem<S>, fd: UserFd) ->
     let fd = fs.get_backing_fd(fd)?;
     match fd {
         BackingFd::Virtual(vfd) => Ok(fs.embedded_fs.close_file(vfd)?),
+        BackingFd::Device(_) => Ok(()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_close(fd as i32);
             match ret {
@@ -86,7 +88,7 @@ pub(crate) unsafe fn fd_datasync<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_datasync(fd as i32);
             match ret {
@@ -107,6 +109,7 @@ pub(crate) unsafe fn fd_fdstat_get<S: Storage>(
             let stat = fs.embedded_fs.get_fd_stat(vfd)?;
             Ok(stat)
         }
+        BackingFd::Device(device) => Ok(device.fdstat()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Fdstat>::uninit();
             let ret =
@@ -131,6 +134,8 @@ pub(crate) unsafe fn fd_fdstat_set_flags<S: Storage>(
             entry.flags = flags;
             Ok(())
         }
+        // Device nodes never block and have no offset to append at
+        BackingFd::Device(_) => Ok(()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_fdstat_set_flags(fd as i32, flags as i32);
             match ret {
@@ -149,7 +154,7 @@ pub(crate) unsafe fn fd_fdstat_set_rights<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_fdstat_set_rights(
                 fd as i32,
@@ -170,10 +175,8 @@ pub(crate) unsafe fn fd_filestat_get<S: Storage>(
 ) -> Result<Filestat, Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => {
-            let fd_entry = fs.embedded_fs.get_fd_entry(vfd)?;
-            Ok(fs.embedded_fs.get_filestat_from_node_id(fd_entry.node_id))
-        }
+        BackingFd::Virtual(vfd) => Ok(fs.embedded_fs.get_fd_filestat(vfd)?),
+        BackingFd::Device(device) => Ok(device.filestat()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Filestat>::uninit();
             let ret =
@@ -193,7 +196,7 @@ pub(crate) unsafe fn fd_filestat_set_size<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_filestat_set_size(fd as i32, size as i64);
             match ret {
@@ -213,7 +216,7 @@ pub(crate) unsafe fn fd_filestat_set_times<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_filestat_set_times(
                 fd as i32,
@@ -230,15 +233,26 @@ pub(crate) unsafe fn fd_filestat_set_times<S: Storage>(
 }
 
 pub(crate) unsafe fn fd_pread<S: Storage>(
-    fs: &mut FileSystem<S>,
+    fs: OverlayFs<'_, S>,
     fd: UserFd,
     iovs: IovecArray<'_>,
     offset: Filesize,
 ) -> Result<Size, Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(vfd) => {
+            let content = fs.embedded_fs.get_content(vfd)?;
+            let mut cursor = std::io::Cursor::new(content);
+            cursor.set_position(offset);
+            Ok(read_bytes(cursor, iovs)?)
+        }
+        BackingFd::Device(device) => {
+            // SAFETY: `iovs` point to buffers in the linear memory given by
+            // the caller.
+            Ok(unsafe { device.read(iovs) }?)
+        }
         BackingFd::Wasi(fd) => {
+            drop(fs);
             let mut rp0 = MaybeUninit::<Size>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_pread(
                 fd as i32,
@@ -276,6 +290,15 @@ pub(crate) unsafe fn fd_prestat_get<S: Storage>(
                 Err(wasi::ERRNO_BADF.into())
             }
         }
+        BackingFd::Device(Device::Dir) => Ok(Prestat {
+            tag: wasi::PREOPENTYPE_DIR.raw(),
+            u: PrestatU {
+                dir: PrestatDir {
+                    pr_name_len: DEV_DIR.len(),
+                },
+            },
+        }),
+        BackingFd::Device(_) => Err(wasi::ERRNO_BADF.into()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Prestat>::uninit();
             let ret =
@@ -307,6 +330,14 @@ pub(crate) unsafe fn fd_prestat_dir_name<S: Storage>(
                 Err(wasi::ERRNO_BADF.into())
             }
         }
+        BackingFd::Device(Device::Dir) => {
+            let path = slice::from_raw_parts_mut(path, path_len as usize);
+            for (offset, byte) in DEV_DIR.as_bytes().iter().enumerate() {
+                path[offset] = *byte;
+            }
+            Ok(())
+        }
+        BackingFd::Device(_) => Err(wasi::ERRNO_BADF.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_prestat_dir_name(
                 fd as i32,
@@ -322,7 +353,7 @@ pub(crate) unsafe fn fd_prestat_dir_name<S: Storage>(
 }
 
 pub(crate) unsafe fn fd_pwrite<S: Storage>(
-    fs: &mut FileSystem<S>,
+    fs: OverlayFs<'_, S>,
     fd: UserFd,
     iovs: CiovecArray<'_>,
     offset: Filesize,
@@ -330,7 +361,9 @@ pub(crate) unsafe fn fd_pwrite<S: Storage>(
     let fd = fs.get_backing_fd(fd)?;
     match fd {
         BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Device(device) => Ok(device.write(iovs)?),
         BackingFd::Wasi(fd) => {
+            drop(fs);
             let mut rp0 = MaybeUninit::<Size>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_pwrite(
                 fd as i32,
@@ -348,28 +381,30 @@ pub(crate) unsafe fn fd_pwrite<S: Storage>(
 }
 
 pub(crate) unsafe fn fd_read<S: Storage>(
-    fs: &mut FileSystem<S>,
+    mut fs: OverlayFs<'_, S>,
     fd: UserFd,
     iovs: IovecArray<'_>,
 ) -> Result<Size, Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
         BackingFd::Virtual(vfd) => {
-            let node = fs.embedded_fs.get_node(vfd)?;
-            match node {
-                Node::Dir { .. } => Err(wasi::ERRNO_ISDIR.into()),
-                Node::File(body) => {
-                    let open = fs.embedded_fs.get_fd_entry(vfd)?;
-                    let mut cursor = std::io::Cursor::new(body.content());
-                    cursor.set_position(open.offset as u64);
-                    let read_bytes = read_bytes(cursor, iovs)?;
-                    let open = fs.embedded_fs.get_fd_entry_mut(vfd)?;
-                    open.offset += read_bytes;
-                    Ok(read_bytes)
-                }
-            }
+            let content = fs.embedded_fs.get_content(vfd)?;
+            let open = fs.embedded_fs.get_fd_entry(vfd)?;
+            let mut cursor = std::io::Cursor::new(content);
+            cursor.set_position(open.offset as u64);
+            let read_bytes = read_bytes(cursor, iovs)?;
+            let open = fs.embedded_fs.get_fd_entry_mut(vfd)?;
+            open.offset += read_bytes;
+            Ok(read_bytes)
+        }
+        BackingFd::Device(device) => {
+            // SAFETY: `iovs` point to buffers in the linear memory given by
+            // the caller.
+            Ok(unsafe { device.read(iovs) }?)
         }
         BackingFd::Wasi(fd) => {
+            // Other threads can use the file system while blocking on the host
+            drop(fs);
             let mut rp0 = MaybeUninit::<Size>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_read(
                 fd as i32,
@@ -398,52 +433,30 @@ pub(crate) unsafe fn fd_readdir<S: Storage>(
             let node = fs.embedded_fs.get_node(vfd)?;
             let entries = match node {
                 Node::Dir(body) => body.entries(),
-                Node::File { .. } => {
+                Node::File { .. } | Node::Symlink { .. } => {
                     return Err(wasi::ERRNO_NOTDIR.into());
                 }
             };
-            let mut bufused = 0;
-            let mut current_cookie = cookie;
-            let mut buf = buf;
-            let buf_len = buf_len as usize;
-            for entry in entries.skip(cookie as usize) {
-                current_cookie += 1;
-                let name_len = entry.name.len();
+            let dir_ino = fs.embedded_fs.get_fd_entry(vfd)?.node_id.ino();
+            let parent_ino = fs.embedded_fs.get_parent_node_id(vfd)?.ino();
+            let entries = entries.map(|entry| {
                 let node_id = fs.embedded_fs.get_node_id_by_link(entry.link_id);
                 let node_stat = fs.embedded_fs.get_filestat_from_node_id(node_id);
-                let dirent = wasi::Dirent {
-                    d_next: current_cookie,
-                    d_ino: node_id.ino() as u64,
-                    d_namlen: name_len as u32,
-                    d_type: node_stat.filetype,
-                };
-
-                // 1. Copy dirent to the buffer
-                let dirent_len = std::mem::size_of::<wasi::Dirent>();
-                let dirent_copy_len = std::cmp::min(dirent_len, buf_len - bufused);
-                // copy dirent even though the buffer doesn't have enough remaining space
-                std::ptr::copy(&dirent as *const _ as *const u8, buf, dirent_copy_len);
-                // bail out if the remaining buffer space is not enough
-                if dirent_copy_len < dirent_len {
-                    // return the number of bytes stored in the buffer
-                    return Ok(buf_len);
-                }
-                buf = buf.add(dirent_copy_len);
-                bufused += dirent_copy_len;
-
-                // 2. Copy name string to the buffer
-                let name_copy_len = std::cmp::min(name_len, buf_len - bufused);
-                // same truncation rule applied as above
-                std::ptr::copy(entry.name.as_ptr(), buf, name_copy_len);
-
-                if name_copy_len < name_len {
-                    return Ok(buf_len);
-                }
-                buf = buf.add(name_len);
-                bufused += name_copy_len;
-            }
-            Ok(bufused)
+                (entry.name, node_id.ino() as u64, node_stat.filetype)
+            });
+            let entries = dot_entries(dir_ino, parent_ino).chain(entries);
+            // SAFETY: `buf` points to `buf_len` bytes given by the caller.
+            Ok(unsafe { write_dirents(entries, buf, buf_len as usize, cookie) })
+        }
+        BackingFd::Device(Device::Dir) => {
+            let dir_ino = Device::Dir.filestat().ino;
+            let entries =
+                Device::entries().map(|(name, ino, filetype)| (name.to_string(), ino, filetype));
+            let entries = dot_entries(dir_ino, dir_ino).chain(entries);
+            // SAFETY: `buf` points to `buf_len` bytes given by the caller.
+            Ok(unsafe { write_dirents(entries, buf, buf_len as usize, cookie) })
         }
+        BackingFd::Device(_) => Err(wasi::ERRNO_NOTDIR.into()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Size>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_readdir(
@@ -516,22 +529,22 @@ pub(crate) unsafe fn fd_seek<S: Storage>(
                     Ok(absolute_offset as Filesize)
                 }
                 wasi::WHENCE_END => {
-                    let fd_entry = fs.embedded_fs.get_fd_entry(vfd)?;
-                    let node = fs.embedded_fs.get_node(vfd)?;
-                    match node {
-                        Node::File(body) => {
-                            let content_len = body.content().len();
-                            let fd_entry = fs.embedded_fs.get_fd_entry_mut(vfd)?;
-                            let absolute_offset = compute_new_offset(content_len, offset)?;
-                            fd_entry.offset = absolute_offset;
-                            Ok(absolute_offset as Filesize)
-                        }
-                        Node::Dir { .. } => Err(wasi::ERRNO_INVAL.into()),
-                    }
+                    let content_len = match fs.embedded_fs.get_content(vfd) {
+                        Ok(content) => content.len(),
+                        Err(wasi::ERRNO_BADF) => return Err(wasi::ERRNO_BADF.into()),
+                        Err(_) => return Err(wasi::ERRNO_INVAL.into()),
+                    };
+                    let fd_entry = fs.embedded_fs.get_fd_entry_mut(vfd)?;
+                    let absolute_offset = compute_new_offset(content_len, offset)?;
+                    fd_entry.offset = absolute_offset;
+                    Ok(absolute_offset as Filesize)
                 }
                 _ => Err(wasi::ERRNO_INVAL.into()),
             }
         }
+        BackingFd::Device(Device::Dir) => Err(wasi::ERRNO_ISDIR.into()),
+        // Seeking a character device succeeds but has no effect
+        BackingFd::Device(_) => Ok(0),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Filesize>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_seek(
@@ -551,7 +564,7 @@ pub(crate) unsafe fn fd_seek<S: Storage>(
 pub(crate) unsafe fn fd_sync<S: Storage>(fs: &mut FileSystem<S>, fd: UserFd) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::fd_sync(fd as i32);
             match ret {
@@ -573,6 +586,8 @@ pub(crate) unsafe fn fd_tell<S: Storage>(
             let open = fs.embedded_fs.get_fd_entry_mut(vfd)?;
             Ok(open.offset as u64)
         }
+        BackingFd::Device(Device::Dir) => Err(wasi::ERRNO_ISDIR.into()),
+        BackingFd::Device(_) => Ok(0),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Filesize>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_tell(fd as i32, rp0.as_mut_ptr() as i32);
@@ -585,14 +600,16 @@ pub(crate) unsafe fn fd_tell<S: Storage>(
 }
 
 pub(crate) unsafe fn fd_write<S: Storage>(
-    fs: &mut FileSystem<S>,
+    fs: OverlayFs<'_, S>,
     fd: UserFd,
     iovs: CiovecArray<'_>,
 ) -> Result<Size, Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
         BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Device(device) => Ok(device.write(iovs)?),
         BackingFd::Wasi(fd) => {
+            drop(fs);
             let mut rp0 = MaybeUninit::<Size>::uninit();
             let ret = wasi::wasi_snapshot_preview1::fd_write(
                 fd as i32,
@@ -615,7 +632,7 @@ pub(crate) unsafe fn path_create_directory<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::path_create_directory(
                 fd as i32,
@@ -640,8 +657,13 @@ pub(crate) unsafe fn path_filestat_get<S: Storage>(
     match fd {
         BackingFd::Virtual(vfd) => {
             let path = cstr_to_path(path)?;
-            Ok(fs.embedded_fs.get_filestat_at_path(vfd, path)?)
+            let follow_symlinks = flags & wasi::LOOKUPFLAGS_SYMLINK_FOLLOW != 0;
+            Ok(fs
+                .embedded_fs
+                .get_filestat_at_path(vfd, path, follow_symlinks)?)
         }
+        BackingFd::Device(Device::Dir) => Ok(Device::lookup(path.to_bytes())?.filestat()),
+        BackingFd::Device(_) => Err(wasi::ERRNO_NOTDIR.into()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Filestat>::uninit();
             let ret = wasi::wasi_snapshot_preview1::path_filestat_get(
@@ -670,7 +692,7 @@ pub(crate) unsafe fn path_filestat_set_times<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::path_filestat_set_times(
                 fd as i32,
@@ -719,8 +741,14 @@ pub(crate) unsafe fn path_link<S: Storage>(
     }
 }
 
+/// EXCL only takes effect with CREAT, as hosts ignore it otherwise.
+fn is_exclusive_create(oflags: Oflags) -> bool {
+    let flags = wasi::OFLAGS_CREAT | wasi::OFLAGS_EXCL;
+    oflags & flags == flags
+}
+
 pub(crate) unsafe fn path_open<S: Storage>(
-    fs: &mut FileSystem<S>,
+    mut fs: OverlayFs<'_, S>,
     fd: UserFd,
     dirflags: Lookupflags,
     path: &CStr,
@@ -733,9 +761,41 @@ pub(crate) unsafe fn path_open<S: Storage>(
     match fd {
         BackingFd::Virtual(vfd) => {
             let path = cstr_to_path(path)?;
-            let new_vfd = fs.embedded_fs.open_file(vfd, path, fdflags)?;
+            let follow_symlinks = dirflags & wasi::LOOKUPFLAGS_SYMLINK_FOLLOW != 0;
+            let new_vfd = fs
+                .embedded_fs
+                .open_file(vfd, path, follow_symlinks, fdflags)?;
+            let is_dir = matches!(fs.embedded_fs.get_node(new_vfd)?, Node::Dir(_));
+            let err = if is_exclusive_create(oflags) {
+                Some(wasi::ERRNO_EXIST)
+            } else if oflags & wasi::OFLAGS_DIRECTORY != 0 && !is_dir {
+                Some(wasi::ERRNO_NOTDIR)
+            } else {
+                None
+            };
+            if let Some(err) = err {
+                fs.embedded_fs.close_file(new_vfd)?;
+                return Err(err.into());
+            }
+            if let Some(generate) = fs.embedded_fs.get_generator(new_vfd)? {
+                // Generators are application code, which may access files
+                let (relocked, content) = fs.unlocked(|| generate());
+                fs = relocked;
+                fs.embedded_fs.set_snapshot(new_vfd, content)?;
+            }
             Ok(fs.issue_user_fd(BackingFd::Virtual(new_vfd)))
         }
+        BackingFd::Device(Device::Dir) => {
+            let device = Device::lookup(path.to_bytes())?;
+            if is_exclusive_create(oflags) {
+                return Err(wasi::ERRNO_EXIST.into());
+            }
+            if oflags & wasi::OFLAGS_DIRECTORY != 0 && device != Device::Dir {
+                return Err(wasi::ERRNO_NOTDIR.into());
+            }
+            Ok(fs.issue_user_fd(BackingFd::Device(device)))
+        }
+        BackingFd::Device(_) => Err(wasi::ERRNO_NOTDIR.into()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Fd>::uninit();
             let ret = wasi::wasi_snapshot_preview1::path_open(
@@ -769,7 +829,20 @@ pub(crate) unsafe fn path_readlink<S: Storage>(
 ) -> Result<Size, Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_INVAL.into()),
+        BackingFd::Virtual(vfd) => {
+            let path = cstr_to_path(path)?;
+            let target = fs.embedded_fs.read_link(vfd, path)?;
+            // The result is truncated if the buffer is too small like readlink(2)
+            let copy_len = std::cmp::min(target.len(), buf_len as usize);
+            std::ptr::copy(target.as_ptr(), buf, copy_len);
+            Ok(copy_len)
+        }
+        BackingFd::Device(Device::Dir) => {
+            // There are no symbolic links under `/dev`
+            Device::lookup(path.to_bytes())?;
+            Err(wasi::ERRNO_INVAL.into())
+        }
+        BackingFd::Device(_) => Err(wasi::ERRNO_NOTDIR.into()),
         BackingFd::Wasi(fd) => {
             let mut rp0 = MaybeUninit::<Size>::uninit();
             let ret = wasi::wasi_snapshot_preview1::path_readlink(
@@ -795,7 +868,7 @@ pub(crate) unsafe fn path_remove_directory<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::path_remove_directory(
                 fd as i32,
@@ -846,7 +919,7 @@ pub(crate) unsafe fn path_symlink<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::path_symlink(
                 old_path.as_ptr() as i32,
@@ -870,7 +943,7 @@ pub(crate) unsafe fn path_unlink_file<S: Storage>(
 ) -> Result<(), Error> {
     let fd = fs.get_backing_fd(fd)?;
     match fd {
-        BackingFd::Virtual(vfd) => Err(wasi::ERRNO_NOTSUP.into()),
+        BackingFd::Virtual(_) | BackingFd::Device(_) => Err(wasi::ERRNO_NOTSUP.into()),
         BackingFd::Wasi(fd) => {
             let ret = wasi::wasi_snapshot_preview1::path_unlink_file(
                 fd as i32,
@@ -886,7 +959,7 @@ pub(crate) unsafe fn path_unlink_file<S: Storage>(
 }
 
 pub(crate) unsafe fn poll_oneoff<S: Storage>(
-    fs: &mut FileSystem<S>,
+    fs: OverlayFs<'_, S>,
     in_: *const Subscription,
     out: *mut Event,
     nsubscriptions: u32,
@@ -907,7 +980,9 @@ pub(crate) unsafe fn poll_oneoff<S: Storage>(
             };
             let fd = fs.get_backing_fd(fd)?;
             let new_fd = match fd {
-                BackingFd::Virtual(_) => return Err(wasi::ERRNO_NOTSUP.into()),
+                BackingFd::Virtual(_) | BackingFd::Device(_) => {
+                    return Err(wasi::ERRNO_NOTSUP.into());
+                }
                 BackingFd::Wasi(fd) => fd,
             };
 
@@ -922,6 +997,7 @@ pub(crate) unsafe fn poll_oneoff<S: Storage>(
         _ => return Err(wasi::ERRNO_INVAL.into()),
         }
     }
+    drop(fs);
     let mut rp0 = MaybeUninit::<Fdstat>::uninit();
     let ret = unsafe {
         wasi::wasi_snapshot_preview1::poll_oneoff(
@@ -938,6 +1014,67 @@ pub(crate) unsafe fn poll_oneoff<S: Storage>(
     }
 }
 
+/// `.` and `..` entries, which hosts list before the other entries.
+fn dot_entries(
+    dir_ino: u64,
+    parent_ino: u64,
+) -> impl Iterator<Item = (String, u64, wasi::Filetype)> {
+    [
+        (".".to_string(), dir_ino, wasi::FILETYPE_DIRECTORY),
+        ("..".to_string(), parent_ino, wasi::FILETYPE_DIRECTORY),
+    ]
+    .into_iter()
+}
+
+/// Fill `buf` with the entries after `cookie` in the layout of `fd_readdir`.
+/// Returns the number of bytes stored in the buffer.
+unsafe fn write_dirents<N: AsRef<[u8]>>(
+    entries: impl Iterator<Item = (N, u64, wasi::Filetype)>,
+    buf: *mut u8,
+    buf_len: usize,
+    cookie: Dircookie,
+) -> Size {
+    let mut bufused = 0;
+    let mut current_cookie = cookie;
+    let mut buf = buf;
+    for (name, ino, filetype) in entries.skip(cookie as usize) {
+        current_cookie += 1;
+        let name = name.as_ref();
+        let name_len = name.len();
+        let dirent = wasi::Dirent {
+            d_next: current_cookie,
+            d_ino: ino,
+            d_namlen: name_len as u32,
+            d_type: filetype,
+        };
+
+        // 1. Copy dirent to the buffer
+        let dirent_len = std::mem::size_of::<wasi::Dirent>();
+        let dirent_copy_len = std::cmp::min(dirent_len, buf_len - bufused);
+        // copy dirent even though the buffer doesn't have enough remaining space
+        std::ptr::copy(&dirent as *const _ as *const u8, buf, dirent_copy_len);
+        // bail out if the remaining buffer space is not enough
+        if dirent_copy_len < dirent_len {
+            // return the number of bytes stored in the buffer
+            return buf_len;
+        }
+        buf = buf.add(dirent_copy_len);
+        bufused += dirent_copy_len;
+
+        // 2. Copy name string to the buffer
+        let name_copy_len = std::cmp::min(name_len, buf_len - bufused);
+        // same truncation rule applied as above
+        std::ptr::copy(name.as_ptr(), buf, name_copy_len);
+
+        if name_copy_len < name_len {
+            return buf_len;
+        }
+        buf = buf.add(name_len);
+        bufused += name_copy_len;
+    }
+    bufused
+}
+
 fn read_bytes<R: std::io::Read>(mut src: R, iovs: wasi::IovecArray) -> Result<usize, wasi::Errno> {
     let mut bytes_read = 0;
     for iov in iovs {
@@ -953,3 +1090,285 @@ fn cstr_to_path(path: &CStr) -> Result<&Path, wasi::Errno> {
     let os_str: &OsStr = unsafe { std::mem::transmute(path.to_bytes()) };
     Ok(Path::new(os_str))
 }
+
+// Tests run natively against the mock WASI host
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{
+        ffi::CStr,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{
+        GlobalState,
+        embed::{EmbeddedFs, Generator, LinkedStorage},
+        mock_wasi,
+    };
+
+    /// `/mnt` with `hello.txt` and `dir/a.txt`, and `/removed` which is
+    /// removed by a later packing.
+    fn new_fs() -> FileSystem<LinkedStorage> {
+        let mut embedded = EmbeddedFs::<LinkedStorage>::default();
+        let (mnt, node_id, link_id) = embedded.preopen_dir("/mnt".to_string());
+        embedded
+            .create_file(node_id, link_id, "hello.txt", b"Hello".to_vec())
+            .unwrap();
+        embedded
+            .create_file(node_id, link_id, "dir/a.txt", b"a".to_vec())
+            .unwrap();
+        let (removed, _, _) = embedded.preopen_dir("/removed".to_string());
+        embedded.remove_preopened_dir(removed);
+        FileSystem::create(embedded, &[mnt, removed])
+    }
+
+    fn new_state() -> Mutex<GlobalState<LinkedStorage>> {
+        Mutex::new(GlobalState {
+            embedded_fs: None,
+            overlay_fs: Some(new_fs()),
+        })
+    }
+
+    fn lock(state: &Mutex<GlobalState<LinkedStorage>>) -> OverlayFs<'_, LinkedStorage> {
+        OverlayFs::lock_in(state).unwrap()
+    }
+
+    fn open(fs: OverlayFs<'_, LinkedStorage>, path: &CStr) -> Result<UserFd, Error> {
+        unsafe { path_open(fs, 3, 0, path, 0, 0, 0, 0) }
+    }
+
+    fn errno<T>(result: Result<T, Error>) -> u16 {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.raw(),
+        }
+    }
+
+    /// Parse names of entries written by `fd_readdir`.
+    fn dirent_names(buf: &[u8]) -> Vec<String> {
+        let dirent_len = std::mem::size_of::<wasi::Dirent>();
+        let mut names = vec![];
+        let mut rest = buf;
+        while rest.len() >= dirent_len {
+            let dirent = unsafe { std::ptr::read_unaligned(rest.as_ptr() as *const wasi::Dirent) };
+            let name_len = dirent.d_namlen as usize;
+            let name = &rest[dirent_len..dirent_len + name_len];
+            names.push(String::from_utf8(name.to_vec()).unwrap());
+            rest = &rest[dirent_len + name_len..];
+        }
+        names
+    }
+
+    #[test]
+    fn test_preopens() {
+        let state = new_state();
+        // The mock host has no preopens, so virtual ones start at 3 and
+        // removed ones keep their fd without being registered
+        let registered = mock_wasi::registered_preopens();
+        assert_eq!(registered, [(3, "/mnt".to_string())]);
+        let stat = unsafe { fd_prestat_get(&mut lock(&state), 3) }.unwrap();
+        assert_eq!(stat.tag, wasi::PREOPENTYPE_DIR.raw());
+        assert_eq!(unsafe { stat.u.dir.pr_name_len }, 4);
+        let mut name = [0u8; 4];
+        unsafe { fd_prestat_dir_name(&mut lock(&state), 3, name.as_mut_ptr(), 4) }.unwrap();
+        assert_eq!(&name, b"/mnt");
+        assert_eq!(
+            errno(unsafe { fd_prestat_get(&mut lock(&state), 4) }),
+            wasi::ERRNO_BADF.raw()
+        );
+        assert_eq!(
+            errno(unsafe { fd_prestat_get(&mut lock(&state), 5) }),
+            wasi::ERRNO_BADF.raw()
+        );
+
+        // Opened files are not preopens
+        let fd = open(lock(&state), c"hello.txt").unwrap();
+        assert_eq!(
+            errno(unsafe { fd_prestat_get(&mut lock(&state), fd) }),
+            wasi::ERRNO_BADF.raw()
+        );
+    }
+
+    #[test]
+    fn test_fd_table() {
+        let state = new_state();
+        let first = open(lock(&state), c"hello.txt").unwrap();
+        let second = open(lock(&state), c"dir/a.txt").unwrap();
+        assert_eq!((first, second), (5, 6));
+        assert_eq!(
+            errno(open(lock(&state), c"missing.txt")),
+            wasi::ERRNO_NOENT.raw()
+        );
+        assert_eq!(
+            errno(open(lock(&state), c"hello.txt/a")),
+            wasi::ERRNO_NOTDIR.raw()
+        );
+
+        assert_eq!(
+            errno(unsafe { fd_renumber(&mut lock(&state), first, second) }),
+            wasi::ERRNO_NOTSUP.raw()
+        );
+        assert_eq!(
+            errno(unsafe { fd_close(&mut lock(&state), 100) }),
+            wasi::ERRNO_BADF.raw()
+        );
+        unsafe { fd_close(&mut lock(&state), first) }.unwrap();
+        assert_eq!(
+            errno(unsafe { fd_filestat_get(&mut lock(&state), first) }),
+            wasi::ERRNO_BADF.raw()
+        );
+        let stat = unsafe { fd_filestat_get(&mut lock(&state), second) }.unwrap();
+        assert_eq!(stat.size, 1);
+    }
+
+    #[test]
+    fn test_fd_read() {
+        let state = new_state();
+        let fd = open(lock(&state), c"hello.txt").unwrap();
+        let mut buf = [0u8; 3];
+        let read = |buf: &mut [u8]| {
+            let iovs = [wasi::Iovec {
+                buf: buf.as_mut_ptr(),
+                buf_len: buf.len(),
+            }];
+            unsafe { fd_read(lock(&state), fd, &iovs) }.unwrap()
+        };
+        assert_eq!(read(&mut buf), 3);
+        assert_eq!(&buf, b"Hel");
+        assert_eq!(read(&mut buf), 2);
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(read(&mut buf), 0);
+
+        let iovs = [wasi::Iovec {
+            buf: buf.as_mut_ptr(),
+            buf_len: buf.len(),
+        }];
+        assert_eq!(unsafe { fd_pread(lock(&state), fd, &iovs, 1) }.unwrap(), 3);
+        assert_eq!(&buf, b"ell");
+        assert_eq!(unsafe { fd_tell(&mut lock(&state), fd) }.unwrap(), 5);
+    }
+
+    #[test]
+    fn test_fd_seek() {
+        let state = new_state();
+        let fd = open(lock(&state), c"hello.txt").unwrap();
+        let seek = |fs: &mut FileSystem<LinkedStorage>, offset: i64, whence: wasi::Whence| unsafe {
+            fd_seek(fs, fd, offset, whence.raw() as i32)
+        };
+        assert_eq!(seek(&mut lock(&state), 2, wasi::WHENCE_SET).unwrap(), 2);
+        assert_eq!(seek(&mut lock(&state), 1, wasi::WHENCE_CUR).unwrap(), 3);
+        assert_eq!(seek(&mut lock(&state), -3, wasi::WHENCE_CUR).unwrap(), 0);
+        assert_eq!(
+            errno(seek(&mut lock(&state), -1, wasi::WHENCE_CUR)),
+            wasi::ERRNO_INVAL.raw()
+        );
+        assert_eq!(seek(&mut lock(&state), -1, wasi::WHENCE_END).unwrap(), 4);
+        // Seeking past the end is allowed
+        assert_eq!(seek(&mut lock(&state), 10, wasi::WHENCE_END).unwrap(), 15);
+        assert_eq!(
+            errno(seek(&mut lock(&state), -6, wasi::WHENCE_END)),
+            wasi::ERRNO_INVAL.raw()
+        );
+        assert_eq!(
+            errno(unsafe { fd_seek(&mut lock(&state), fd, 0, 3) }),
+            wasi::ERRNO_INVAL.raw()
+        );
+        // Directories have no end to seek from
+        assert_eq!(
+            errno(unsafe { fd_seek(&mut lock(&state), 3, 0, wasi::WHENCE_END.raw() as i32) }),
+            wasi::ERRNO_INVAL.raw()
+        );
+    }
+
+    #[test]
+    fn test_fd_readdir() {
+        let state = new_state();
+        let dirent_len = std::mem::size_of::<wasi::Dirent>();
+        let mut buf = vec![0u8; 256];
+        let used = unsafe { fd_readdir(&mut lock(&state), 3, buf.as_mut_ptr(), 256, 0) }.unwrap();
+        let names = dirent_names(&buf[..used]);
+        // `.` and `..` come first like hosts list them
+        assert_eq!(names[..2], [".", ".."]);
+        let mut entries = names[2..].to_vec();
+        entries.sort();
+        assert_eq!(entries, ["dir", "hello.txt"]);
+        assert_eq!(
+            used,
+            dirent_len * 4 + "...".len() + "dir".len() + "hello.txt".len()
+        );
+
+        // A buffer filled up means there may be more entries
+        let first_len = dirent_len + dirent_names(&buf[..used])[0].len();
+        for len in [dirent_len - 1, dirent_len, first_len + 1] {
+            let mut small = vec![0u8; len];
+            let used =
+                unsafe { fd_readdir(&mut lock(&state), 3, small.as_mut_ptr(), len as u32, 0) }
+                    .unwrap();
+            assert_eq!(used, len);
+        }
+
+        // Cookies continue after the entries already read
+        let used = unsafe { fd_readdir(&mut lock(&state), 3, buf.as_mut_ptr(), 256, 1) }.unwrap();
+        assert_eq!(dirent_names(&buf[..used]).len(), 3);
+        let used = unsafe { fd_readdir(&mut lock(&state), 3, buf.as_mut_ptr(), 256, 3) }.unwrap();
+        assert_eq!(dirent_names(&buf[..used]).len(), 1);
+        let used = unsafe { fd_readdir(&mut lock(&state), 3, buf.as_mut_ptr(), 256, 4) }.unwrap();
+        assert_eq!(used, 0);
+
+        let fd = open(lock(&state), c"hello.txt").unwrap();
+        assert_eq!(
+            errno(unsafe { fd_readdir(&mut lock(&state), fd, buf.as_mut_ptr(), 256, 0) }),
+            wasi::ERRNO_NOTDIR.raw()
+        );
+    }
+
+    #[test]
+    fn test_path_open_flags() {
+        let state = new_state();
+        let open_with = |path: &CStr, oflags: Oflags| unsafe {
+            path_open(lock(&state), 3, 0, path, oflags, 0, 0, 0)
+        };
+        open_with(c"dir", wasi::OFLAGS_DIRECTORY).unwrap();
+        assert_eq!(
+            errno(open_with(c"hello.txt", wasi::OFLAGS_DIRECTORY)),
+            wasi::ERRNO_NOTDIR.raw()
+        );
+        assert_eq!(
+            errno(open_with(
+                c"hello.txt",
+                wasi::OFLAGS_CREAT | wasi::OFLAGS_EXCL
+            )),
+            wasi::ERRNO_EXIST.raw()
+        );
+        // EXCL without CREAT is ignored like hosts do
+        assert_eq!(open_with(c"hello.txt", wasi::OFLAGS_EXCL).unwrap(), 6);
+        // Fds of the failed opens are not kept
+        assert_eq!(open(lock(&state), c"hello.txt").unwrap(), 7);
+
+        // Escaping the mount is refused like hosts do
+        assert_eq!(errno(open(lock(&state), c"..")), wasi::ERRNO_PERM.raw());
+        assert_eq!(
+            errno(open(lock(&state), c"dir/../../mnt")),
+            wasi::ERRNO_PERM.raw()
+        );
+        open(lock(&state), c"dir/../hello.txt").unwrap();
+    }
+
+    #[test]
+    fn test_path_open_generated() {
+        let state = Arc::new(new_state());
+        let inner = state.clone();
+        // The generator accesses files like applications printing to stdio
+        let generator: Generator = Arc::new(move || {
+            let fd = open(lock(&inner), c"hello.txt").unwrap();
+            let stat = unsafe { fd_filestat_get(&mut lock(&inner), fd) }.unwrap();
+            stat.size.to_string().into_bytes()
+        });
+        lock(&state)
+            .create_generated_file("/mnt/size", generator)
+            .unwrap();
+        let fd = open(lock(&state), c"size").unwrap();
+        let stat = unsafe { fd_filestat_get(&mut lock(&state), fd) }.unwrap();
+        assert_eq!(stat.size, 1);
+    }
+}
@@ -0,0 +1,203 @@
+// Probe printing one canonical line per syscall result, so that runs against
+// a host preopen and an embedded mount of the same tree can be diffed.
+// Values depending on the backing storage, like inode numbers, timestamps and
+// directory sizes, are left out.
+#include <assert.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <wasi/api.h>
+
+static const char *filetype_name(__wasi_filetype_t filetype) {
+  switch (filetype) {
+  case __WASI_FILETYPE_DIRECTORY:
+    return "dir";
+  case __WASI_FILETYPE_REGULAR_FILE:
+    return "file";
+  case __WASI_FILETYPE_SYMBOLIC_LINK:
+    return "symlink";
+  default:
+    return "other";
+  }
+}
+
+static void print_errno(const char *probe, __wasi_errno_t err) {
+  printf("%s: errno %d (%s)\n", probe, err, strerror(err));
+}
+
+static int compare_strings(const void *a, const void *b) {
+  return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+static void probe_readdir(int dirfd, const char *path) {
+  char probe[64];
+  snprintf(probe, sizeof(probe), "readdir %s", path);
+  __wasi_fd_t fd;
+  __wasi_errno_t err = __wasi_path_open(
+      dirfd, 0, path, __WASI_OFLAGS_DIRECTORY, __WASI_RIGHTS_FD_READDIR, 0, 0, &fd);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  uint8_t buf[4096];
+  __wasi_size_t used;
+  err = __wasi_fd_readdir(fd, buf, sizeof(buf), 0, &used);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  // The buffer is large enough for the fixtures
+  assert(used < sizeof(buf));
+
+  // Entries are sorted since their order is unspecified
+  char *entries[64];
+  size_t count = 0;
+  size_t offset = 0;
+  while (offset < used) {
+    __wasi_dirent_t dirent;
+    memcpy(&dirent, buf + offset, sizeof(dirent));
+    offset += sizeof(dirent);
+    assert(count < sizeof(entries) / sizeof(entries[0]));
+    char *entry = malloc(dirent.d_namlen + 16);
+    snprintf(entry, dirent.d_namlen + 16, "%.*s %s", (int)dirent.d_namlen,
+             (const char *)buf + offset, filetype_name(dirent.d_type));
+    entries[count++] = entry;
+    offset += dirent.d_namlen;
+  }
+  qsort(entries, count, sizeof(entries[0]), compare_strings);
+  printf("%s: ok", probe);
+  for (size_t i = 0; i < count; i++) {
+    printf("%s %s", i == 0 ? "" : ",", entries[i]);
+    free(entries[i]);
+  }
+  printf("\n");
+  assert(__wasi_fd_close(fd) == 0);
+}
+
+static void probe_open(int dirfd, const char *path, __wasi_oflags_t oflags) {
+  char probe[64];
+  snprintf(probe, sizeof(probe), "open %s oflags=%d", path, oflags);
+  __wasi_fd_t fd;
+  __wasi_errno_t err =
+      __wasi_path_open(dirfd, 0, path, oflags, __WASI_RIGHTS_FD_READ, 0, 0, &fd);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  printf("%s: ok\n", probe);
+  assert(__wasi_fd_close(fd) == 0);
+}
+
+static void probe_stat(int dirfd, const char *path, __wasi_lookupflags_t flags) {
+  char probe[64];
+  snprintf(probe, sizeof(probe), "stat %s lookupflags=%d", path, flags);
+  __wasi_filestat_t stat;
+  __wasi_errno_t err = __wasi_path_filestat_get(dirfd, flags, path, &stat);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  if (stat.filetype == __WASI_FILETYPE_REGULAR_FILE) {
+    printf("%s: ok file size=%llu\n", probe, stat.size);
+  } else {
+    printf("%s: ok %s\n", probe, filetype_name(stat.filetype));
+  }
+}
+
+static void probe_readlink(int dirfd, const char *path) {
+  char probe[64];
+  snprintf(probe, sizeof(probe), "readlink %s", path);
+  uint8_t buf[64];
+  __wasi_size_t len;
+  __wasi_errno_t err = __wasi_path_readlink(dirfd, path, buf, sizeof(buf), &len);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  printf("%s: ok %.*s\n", probe, (int)len, buf);
+}
+
+static void probe_seek(__wasi_fd_t fd, __wasi_filedelta_t offset,
+                       __wasi_whence_t whence) {
+  char probe[64];
+  snprintf(probe, sizeof(probe), "seek %lld whence=%d", offset, whence);
+  __wasi_filesize_t new_offset;
+  __wasi_errno_t err = __wasi_fd_seek(fd, offset, whence, &new_offset);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  printf("%s: ok %llu\n", probe, new_offset);
+}
+
+static void probe_read(__wasi_fd_t fd) {
+  uint8_t buf[16];
+  __wasi_iovec_t iov = {.buf = buf, .buf_len = sizeof(buf)};
+  __wasi_size_t nread;
+  __wasi_errno_t err = __wasi_fd_read(fd, &iov, 1, &nread);
+  if (err != 0) {
+    print_errno("read", err);
+    return;
+  }
+  printf("read: ok %u bytes\n", nread);
+}
+
+static void probe_pread(__wasi_fd_t fd, __wasi_filesize_t offset) {
+  char probe[64];
+  snprintf(probe, sizeof(probe), "pread %llu", offset);
+  uint8_t buf[16];
+  __wasi_iovec_t iov = {.buf = buf, .buf_len = sizeof(buf)};
+  __wasi_size_t nread;
+  __wasi_errno_t err = __wasi_fd_pread(fd, &iov, 1, offset, &nread);
+  if (err != 0) {
+    print_errno(probe, err);
+    return;
+  }
+  printf("%s: ok %u bytes\n", probe, nread);
+}
+
+int main(int argc, char *argv[]) {
+  int mnt = open("/mnt", O_RDONLY | O_DIRECTORY);
+  assert(mnt != -1);
+
+  probe_readdir(mnt, ".");
+  probe_readdir(mnt, "dir");
+  probe_readdir(mnt, "empty-dir");
+  probe_readdir(mnt, "hello.txt");
+
+  probe_open(mnt, "hello.txt", 0);
+  probe_open(mnt, "missing.txt", 0);
+  probe_open(mnt, "hello.txt/", 0);
+  probe_open(mnt, "hello.txt/a", 0);
+  probe_open(mnt, "dir", __WASI_OFLAGS_DIRECTORY);
+  probe_open(mnt, "hello.txt", __WASI_OFLAGS_DIRECTORY);
+  probe_open(mnt, "hello.txt", __WASI_OFLAGS_CREAT | __WASI_OFLAGS_EXCL);
+  probe_open(mnt, "hello.txt", __WASI_OFLAGS_EXCL);
+  probe_open(mnt, "dir/../hello.txt", 0);
+  probe_open(mnt, "..", 0);
+  probe_open(mnt, "dir/../../mnt/hello.txt", 0);
+
+  probe_stat(mnt, "hello.txt", 0);
+  probe_stat(mnt, "dir", 0);
+  probe_stat(mnt, ".", 0);
+  probe_stat(mnt, "hello.txt/", 0);
+  probe_stat(mnt, "link", 0);
+  probe_stat(mnt, "link", __WASI_LOOKUPFLAGS_SYMLINK_FOLLOW);
+
+  probe_readlink(mnt, "link");
+  probe_readlink(mnt, "hello.txt");
+
+  __wasi_fd_t file;
+  assert(__wasi_path_open(mnt, 0, "hello.txt", 0,
+                          __WASI_RIGHTS_FD_READ | __WASI_RIGHTS_FD_SEEK, 0, 0,
+                          &file) == 0);
+  probe_seek(file, 100, __WASI_WHENCE_SET);
+  probe_read(file);
+  probe_seek(file, -200, __WASI_WHENCE_CUR);
+  probe_seek(file, 0, __WASI_WHENCE_END);
+  probe_seek(file, -7, __WASI_WHENCE_END);
+  probe_pread(file, 2);
+  probe_pread(file, 100);
+  return 0;
+}
@@ -0,0 +1,54 @@
+#include "../check.h"
+#include <assert.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <sys/stat.h>
+
+int main(int argc, char *argv[]) {
+  char buffer[64];
+  struct stat st;
+
+  // writes are discarded and reads hit EOF
+  int null = open("/dev/null", O_RDWR);
+  assert(null != -1);
+  assert(write(null, "hello", 5) == 5);
+  assert(read(null, buffer, sizeof(buffer)) == 0);
+  assert(fstat(null, &st) == 0);
+  assert(S_ISCHR(st.st_mode));
+  close(null);
+
+  int zero = open("/dev/zero", O_RDONLY);
+  assert(zero != -1);
+  memset(buffer, 0xff, sizeof(buffer));
+  assert(read(zero, buffer, sizeof(buffer)) == sizeof(buffer));
+  for (size_t i = 0; i < sizeof(buffer); i++) {
+    assert(buffer[i] == 0);
+  }
+  // Seeking a character device has no effect
+  assert(lseek(zero, 10, SEEK_SET) == 0);
+  close(zero);
+
+  int urandom = open("/dev/urandom", O_RDONLY);
+  assert(urandom != -1);
+  memset(buffer, 0, sizeof(buffer));
+  assert(read(urandom, buffer, sizeof(buffer)) == sizeof(buffer));
+  int nonzero = 0;
+  for (size_t i = 0; i < sizeof(buffer); i++) {
+    nonzero |= buffer[i];
+  }
+  assert(nonzero);
+  close(urandom);
+
+  assert(stat("/dev/urandom", &st) == 0);
+  assert(S_ISCHR(st.st_mode));
+  assert(stat("/dev", &st) == 0);
+  assert(S_ISDIR(st.st_mode));
+  int dev = open("/dev", O_RDONLY | O_DIRECTORY);
+  assert(dev != -1);
+  assert(lseek(dev, 0, SEEK_CUR) == -1 && errno == EISDIR);
+  close(dev);
+  assert(open("/dev/missing", O_RDONLY) == -1);
+  check_dir_entry_size("/dev", 3);
+
+  return 0;
+}
@@ -0,0 +1,6 @@
+#include "../check.h"
+
+int main(int argc, char *argv[]) {
+  check_file_line("/mnt/hello.txt", "hello\n");
+  return 0;
+}
@@ -0,0 +1,63 @@
+#include "../check.h"
+#include "../../../include/wasi_vfs.h"
+#include <assert.h>
+#include <fcntl.h>
+#include <sys/stat.h>
+
+static int opened = 0;
+
+static void generate_count(void *userdata, void *out) {
+  int *count = userdata;
+  char buffer[16];
+  (*count)++;
+  int len = snprintf(buffer, sizeof(buffer), "count=%d\n", *count);
+  wasi_vfs_generated_file_write(out, buffer, len);
+}
+
+static void generate_greeting(void *userdata, void *out) {
+  wasi_vfs_generated_file_write(out, userdata, strlen(userdata));
+}
+
+int main(int argc, char *argv[]) {
+  char buffer[64];
+  struct stat st;
+
+  assert(wasi_vfs_register_generated_file("/proc/self/count", generate_count,
+                                          &opened) == 0);
+  assert(wasi_vfs_register_generated_file("relative", generate_greeting,
+                                          NULL) != 0);
+  assert(opened == 0);
+
+  // Each open fd keeps the content generated when it's opened
+  int first = open("/proc/self/count", O_RDONLY);
+  int second = open("/proc/self/count", O_RDONLY);
+  assert(first != -1 && second != -1);
+  assert(opened == 2);
+  assert(read(second, buffer, sizeof(buffer)) == 8);
+  assert(memcmp(buffer, "count=2\n", 8) == 0);
+  assert(pread(first, buffer, 2, 6) == 2);
+  assert(memcmp(buffer, "1\n", 2) == 0);
+  assert(lseek(first, 0, SEEK_END) == 8);
+  assert(fstat(first, &st) == 0);
+  assert(st.st_size == 8);
+  close(first);
+  close(second);
+
+  // The content isn't generated by stat
+  assert(stat("/proc/self/count", &st) == 0);
+  assert(S_ISREG(st.st_mode) && st.st_size == 0);
+  assert(opened == 2);
+
+  assert(stat("/proc/self", &st) == 0);
+  assert(S_ISDIR(st.st_mode));
+
+  // Generated files can be placed in packed directories
+  if (argc > 1 && strcmp(argv[1], "packed") == 0) {
+    assert(wasi_vfs_register_generated_file(
+               "/mnt/greeting.txt", generate_greeting, (void *)"hi\n") == 0);
+    check_file_line("/mnt/greeting.txt", "hi\n");
+    check_file_line("/mnt/hello.txt", "hello\n");
+  }
+
+  return 0;
+}
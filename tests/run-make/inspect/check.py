@@ -0,0 +1,16 @@
+import json
+import os
+import sys
+
+output = json.load(open(sys.argv[1]))
+manifest, totals = output["manifest"], output["totals"]
+assert manifest["mounts"] == ["/mnt"], manifest
+entries = {entry["path"]: entry for entry in manifest["entries"]}
+assert entries["/mnt/dir"]["type"] == "dir", entries
+hello = entries["/mnt/dir/hello.txt"]
+assert hello["type"] == "file" and hello["size"] == 6, hello
+assert hello["sha256"] == "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03", hello
+unpacked = os.path.getsize(os.path.join(os.path.dirname(sys.argv[1]), "main.wasm"))
+assert manifest["module_size"]["unpacked"] == unpacked, manifest["module_size"]
+assert totals["files"] == 1 and totals["content_bytes"] == 6, totals
+assert totals["embedded_bytes"] >= totals["content_bytes"], totals
@@ -0,0 +1,24 @@
+#include "../check.h"
+#include <sys/stat.h>
+
+int main(void) {
+  struct stat a, b;
+  check_file_exists("/etc/ssl/cert.pem");
+  check_file_exists("/app/a.txt");
+  check_file_not_exists("/app/scratch.tmp");
+  check_dir_entry_size("/app", 2);
+
+  if (stat("/app/a.txt", &a) != 0 || stat("/app/b.txt", &b) != 0) {
+    fprintf(stderr, "stat failed\n");
+    return 1;
+  }
+  if (a.st_ino != b.st_ino || a.st_nlink != 2) {
+    fprintf(stderr, "Identical files are not deduplicated\n");
+    return 1;
+  }
+  if (a.st_mtim.tv_sec != 0) {
+    fprintf(stderr, "Unexpected mtime: %lld\n", (long long)a.st_mtim.tv_sec);
+    return 1;
+  }
+  return 0;
+}
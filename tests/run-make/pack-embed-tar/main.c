@@ -0,0 +1,18 @@
+#include "../check.h"
+
+int main(void) {
+  char buf[16] = {0};
+  FILE *f = fopen("/mnt/dir/symlink.txt", "r");
+  if (!f) {
+    fprintf(stderr, "File /mnt/dir/symlink.txt not found\n");
+    return 1;
+  }
+  fread(buf, 1, sizeof(buf) - 1, f);
+  fclose(f);
+  if (strcmp(buf, "hello\n") != 0) {
+    fprintf(stderr, "Unexpected content: %s\n", buf);
+    return 1;
+  }
+  check_dir_entry_size("/mnt/dir", 2);
+  return 0;
+}
@@ -0,0 +1,27 @@
+#include "../check.h"
+
+static int check_content(const char *path, const char *expected) {
+  char buf[16] = {0};
+  FILE *f = fopen(path, "r");
+  if (!f) {
+    fprintf(stderr, "File %s not found\n", path);
+    return 1;
+  }
+  fread(buf, 1, sizeof(buf) - 1, f);
+  fclose(f);
+  if (strcmp(buf, expected) != 0) {
+    fprintf(stderr, "Unexpected content of %s: %s\n", path, buf);
+    return 1;
+  }
+  return 0;
+}
+
+int main(void) {
+  if (check_content("/etc/ssl/cert.pem", "cert\n") ||
+      check_content("/app/conf/config.toml", "config\n") ||
+      check_content("/app/conf/default.toml", "default\n")) {
+    return 1;
+  }
+  check_dir_entry_size("/app/conf", 2);
+  return 0;
+}
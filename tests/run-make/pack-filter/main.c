@@ -0,0 +1,24 @@
+#include "../check.h"
+
+int main(int argc, char *argv[]) {
+  if (argc != 2) {
+    return 1;
+  }
+  char *mode = argv[1];
+  if (strcmp(mode, "exclude") == 0) {
+    check_file_exists("/mnt/hello.txt");
+    check_file_exists("/mnt/lib/hello.py");
+    check_file_not_exists("/mnt/.hello.txt.swp");
+    check_file_not_exists("/mnt/.wasivfsignore");
+    check_file_not_exists("/mnt/__pycache__/hello.cpython-311.pyc");
+    check_dir_entry_size("/mnt", 2);
+  } else if (strcmp(mode, "include") == 0) {
+    check_file_exists("/mnt/lib/hello.py");
+    check_file_not_exists("/mnt/hello.txt");
+    check_dir_entry_size("/mnt", 1);
+  } else {
+    fprintf(stderr, "Unknown mode: %s\n", mode);
+    return 1;
+  }
+  return 0;
+}
@@ -0,0 +1,28 @@
+#include "../check.h"
+
+static char preloaded[16];
+static int initialized;
+
+// Runs at packing time after the file system is packed
+__attribute__((export_name("init"))) void init(void) {
+  FILE *f = fopen("/app/hello.txt", "r");
+  if (!f) {
+    return;
+  }
+  fread(preloaded, 1, sizeof(preloaded) - 1, f);
+  fclose(f);
+  initialized = 1;
+}
+
+int main(void) {
+  if (!initialized) {
+    fprintf(stderr, "init was not run at packing time\n");
+    return 1;
+  }
+  if (strcmp(preloaded, "hello\n") != 0) {
+    fprintf(stderr, "Unexpected preloaded content: %s\n", preloaded);
+    return 1;
+  }
+  check_file_exists("/app/hello.txt");
+  return 0;
+}
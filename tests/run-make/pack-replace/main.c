@@ -0,0 +1,22 @@
+#include "../check.h"
+
+int main(int argc, char *argv[]) {
+  if (argc != 2) {
+    return 1;
+  }
+  char *mode = argv[1];
+  if (strcmp(mode, "replace") == 0) {
+    check_file_exists("/mnt/new.txt");
+    check_file_not_exists("/mnt/old.txt");
+  } else if (strcmp(mode, "merge") == 0) {
+    check_file_exists("/mnt/new.txt");
+    check_file_exists("/mnt/old.txt");
+  } else if (strcmp(mode, "rm-mount") == 0) {
+    check_file_not_exists("/mnt/new.txt");
+    check_file_exists("/other/other.txt");
+  } else {
+    fprintf(stderr, "Unknown mode: %s\n", mode);
+    return 1;
+  }
+  return 0;
+}
@@ -0,0 +1,8 @@
+#include "../check.h"
+
+int main(int argc, char *argv[]) {
+  check_file_exists("/mnt/hello.txt");
+  check_file_line("/mnt/link.txt", "Hello\n");
+  check_file_not_exists("/mnt/fifo");
+  return 0;
+}
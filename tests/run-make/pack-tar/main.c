@@ -0,0 +1,43 @@
+#include "../check.h"
+#include <sys/stat.h>
+
+static void check_content(const char *path, const char *expected) {
+  char buf[32] = {0};
+  FILE *f = fopen(path, "r");
+  if (!f) {
+    fprintf(stderr, "File %s not found\n", path);
+    exit(1);
+  }
+  fread(buf, 1, sizeof(buf) - 1, f);
+  fclose(f);
+  if (strcmp(buf, expected) != 0) {
+    fprintf(stderr, "Unexpected content of %s: %s\n", path, buf);
+    exit(1);
+  }
+}
+
+int main(void) {
+  struct stat st;
+  char target[32] = {0};
+
+  check_content("/mnt/dir/hello.txt", "hello\n");
+  check_content("/mnt/dir/symlink.txt", "hello\n");
+  check_content("/mnt/dir/hardlink.txt", "hello\n");
+
+  if (stat("/mnt/dir/hello.txt", &st) != 0 || st.st_mtime != 1000000000 ||
+      st.st_nlink != 2) {
+    fprintf(stderr, "Unexpected stat of /mnt/dir/hello.txt\n");
+    return 1;
+  }
+  if (lstat("/mnt/dir/symlink.txt", &st) != 0 || !S_ISLNK(st.st_mode)) {
+    fprintf(stderr, "/mnt/dir/symlink.txt is not a symbolic link\n");
+    return 1;
+  }
+  if (readlink("/mnt/dir/symlink.txt", target, sizeof(target) - 1) < 0 ||
+      strcmp(target, "hello.txt") != 0) {
+    fprintf(stderr, "Unexpected symbolic link target: %s\n", target);
+    return 1;
+  }
+  check_dir_entry_size("/mnt/dir", 3);
+  return 0;
+}
@@ -0,0 +1,21 @@
+#include "../check.h"
+#include <sys/stat.h>
+
+int main(void) {
+  struct stat st;
+
+  check_file_exists("/mnt/pkg/__init__.py");
+  check_file_exists("/mnt/pkg/data/hello.txt");
+  check_dir_entry_size("/mnt/pkg", 2);
+
+  if (stat("/mnt/pkg/data/hello.txt", &st) != 0 ||
+      st.st_mtime != 1000000000 || st.st_size != 6) {
+    fprintf(stderr, "Unexpected stat of /mnt/pkg/data/hello.txt\n");
+    return 1;
+  }
+  if (stat("/mnt/pkg/data", &st) != 0 || !S_ISDIR(st.st_mode)) {
+    fprintf(stderr, "/mnt/pkg/data is not a directory\n");
+    return 1;
+  }
+  return 0;
+}
@@ -0,0 +1,67 @@
+#include "../check.h"
+#include "../../../include/wasi_vfs.h"
+#include <assert.h>
+#include <fcntl.h>
+#include <pthread.h>
+
+#define NUM_THREADS 8
+#define NUM_ITERATIONS 64
+
+static const char content[] =
+    "The quick brown fox jumps over the lazy dog.\n"
+    "Pack my box with five dozen liquor jugs.\n";
+
+static void generate(void *userdata, void *out) {
+  wasi_vfs_generated_file_write(out, content, strlen(content));
+}
+
+// Read the file in small chunks so that offset updates of threads interleave
+static void read_generated(void) {
+  char buf[sizeof(content)];
+  int fd = open("/proc/fixture", O_RDONLY);
+  assert(fd != -1);
+  ssize_t total = 0, n;
+  while ((n = read(fd, buf + total, 7)) > 0) {
+    total += n;
+    assert(total <= (ssize_t)strlen(content));
+    assert(lseek(fd, 0, SEEK_CUR) == total);
+  }
+  assert(n == 0);
+  assert(total == (ssize_t)strlen(content));
+  assert(memcmp(buf, content, total) == 0);
+  assert(close(fd) == 0);
+}
+
+static void read_zero(void) {
+  char buf[64];
+  int fd = open("/dev/zero", O_RDONLY);
+  assert(fd != -1);
+  memset(buf, 1, sizeof(buf));
+  assert(read(fd, buf, sizeof(buf)) == sizeof(buf));
+  for (size_t i = 0; i < sizeof(buf); i++) {
+    assert(buf[i] == 0);
+  }
+  assert(close(fd) == 0);
+}
+
+static void *reader(void *arg) {
+  for (int i = 0; i < NUM_ITERATIONS; i++) {
+    read_generated();
+    read_zero();
+  }
+  return NULL;
+}
+
+int main(int argc, char *argv[]) {
+  assert(wasi_vfs_register_generated_file("/proc/fixture", generate, NULL) ==
+         0);
+
+  pthread_t threads[NUM_THREADS];
+  for (int i = 0; i < NUM_THREADS; i++) {
+    assert(pthread_create(&threads[i], NULL, reader, NULL) == 0);
+  }
+  for (int i = 0; i < NUM_THREADS; i++) {
+    assert(pthread_join(threads[i], NULL) == 0);
+  }
+  return 0;
+}
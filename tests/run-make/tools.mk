@@ -12,6 +12,7 @@ LDFLAGS = -target $(TARGET)
 RUNMAKE_DIR:=$(dir $(abspath $(lastword $(MAKEFILE_LIST))))
 
 LIB_WASI_VFS ?= $(RUNMAKE_DIR)/../../target/wasm32-unknown-unknown/debug/libwasi_vfs.a
+LIB_WASI_VFS_THREADS ?= $(RUNMAKE_DIR)/../../target/wasm32-wasip1-threads/debug/libwasi_vfs.a
 
 TMPDIR = $(shell mkdir -p .tmp && echo .tmp)
 